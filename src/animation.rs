@@ -1,47 +1,218 @@
 /*
-This file contains the KeyframeV3 struct and its related functionality. This struct represents
-a nalgebra_glm::Vec3 value that has specific values at specific frames and that can calculate the
-interpolated value it would have at a given frame via tweening.
+This file contains the Track enum and its related functionality. A Track represents a value that
+has specific values at specific frames and that can calculate the interpolated value it would have
+at a given frame via tweening. Any type implementing Interpolate (see interpolate.rs) can be
+tracked. KeyframeV3 (positions, Euler rotations and scales) and KeyframeQuat (quaternion
+rotations) are the most used ones.
+Each keyframe stores the easing used to tween from it to the next keyframe (see easing.rs), and can
+optionally carry bezier handles like the curves in a graph editor. A segment whose left keyframe
+has an outgoing handle is a bezier curve, and its easing is ignored.
+Keyframes always sit on whole frames, but tracks can be sampled anywhere in between them (see
+Track::sample), e.g. for motion blur or playing back at a different frame rate.
+Before the first keyframe and after the last one, a track is extrapolated using its pre and post
+Extrapolation modes, which can hold, continue or repeat the keyed range.
+A keyed track remembers the value it had before it was first keyed, its rest value. It is what
+the track goes back to when every keyframe is removed, and what is shown when animation is turned
+off.
 
 TODO:
-    * More than 1 kind of tweening
     * More tests
 */
-use nalgebra_glm::Vec3;
+
+use crate::easing::Easing;
+use crate::interpolate::Interpolate;
+use nalgebra_glm::{Quat, Vec3};
 use std::{
     cell::RefCell,
-    collections::{btree_map::Keys, BTreeMap},
+    collections::{btree_map::Keys, BTreeMap, BTreeSet},
+    ops::{Bound::Excluded, Bound::Unbounded, RangeInclusive},
 };
 
 // Type alias for clarity
 pub type Frame = usize;
 
-// Enum that represents one Vec3 that can be keyframed and tweened.
-// The two cases for KeyframeV3. Either it has one value for its whole existence, or it has
-// keyframed values. The reason it is split, rather than just have a tree with 1 element, is because
-// there is no sensible default 'Frame' value (key to the map) for the initial value.
-pub enum KeyframeV3 {
-    Single(Vec3),
+// The most common kinds of track
+pub type KeyframeV3 = Track<Vec3>;
+pub type KeyframeQuat = Track<Quat>;
+
+// One keyframed value, along with the easing used for the segment that starts at it and its
+// optional incoming (left) and outgoing (right) bezier handles
+#[derive(Copy, Clone, Debug)]
+pub struct Key<T> {
+    pub value: T,
+    pub easing: Easing,
+    pub handle_in: Option<Handle<T>>,
+    pub handle_out: Option<Handle<T>>,
+}
+
+impl<T: Interpolate> Key<T> {
+    pub fn new(value: T) -> Key<T> {
+        Key {
+            value,
+            easing: Easing::default(),
+            handle_in: None,
+            handle_out: None,
+        }
+    }
+}
+
+// How a bezier handle is positioned. These follow the handle types of Blender's F-curves:
+//   Auto: smooth curve through the neighbouring keyframes, recalculated when keys change
+//   AutoClamped: like Auto, but flat at peaks and never overshooting the neighbouring values
+//   Vector: points straight at the neighbouring keyframe
+//   Aligned: user placed, but kept in line with the handle on the other side of the keyframe
+//   Free: user placed and left alone
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HandleType {
+    Auto,
+    AutoClamped,
+    Vector,
+    Aligned,
+    Free,
+}
+
+// Which side of a keyframe a handle is on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HandleSide {
+    In,
+    Out,
+}
+
+// A bezier handle, stored relative to its keyframe. `frames` is the offset along the time axis
+// (negative for incoming handles) and `value` is the offset of each component
+#[derive(Copy, Clone, Debug)]
+pub struct Handle<T> {
+    pub kind: HandleType,
+    pub frames: f32,
+    pub value: T,
+}
+
+impl<T: Interpolate> Handle<T> {
+    // Makes a handle with no length. `like` is any value of the tracked type, only used to get a
+    // zero offset of the right shape
+    pub fn new(kind: HandleType, like: &T) -> Handle<T> {
+        Handle {
+            kind,
+            frames: 0.,
+            value: like.map_components(|_| 0.),
+        }
+    }
+}
+
+// What to do when a keyframe is moved onto a frame that already has a keyframe
+//   Replace: the moved keyframe replaces the one that was there
+//   Skip: nothing is moved
+//   Swap: the two keyframes trade places
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum KeyCollision {
+    #[default]
+    Replace,
+    Skip,
+    Swap,
+}
+
+impl KeyCollision {
+    pub const ALL: [KeyCollision; 3] = [
+        KeyCollision::Replace,
+        KeyCollision::Skip,
+        KeyCollision::Swap,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyCollision::Replace => "Replace",
+            KeyCollision::Skip => "Skip",
+            KeyCollision::Swap => "Swap",
+        }
+    }
+}
+
+// How edits made in the editor are stored (see Track::edit_at). Tracks with keyframes are always
+// keyed at the current frame, so the edit shows there
+//   Off: tracks without keyframes have their single value changed
+//   All: every edit keys the track at the current frame
+//   KeyedOnly: same as Off, for while auto-key is on
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum AutoKey {
+    #[default]
+    Off,
+    All,
+    KeyedOnly,
+}
+
+// What a track does before its first keyframe (pre) or after its last keyframe (post)
+//   Constant: hold the value of the end keyframe
+//   Linear: keep going in the direction the curve was heading at the end keyframe
+//   Cycle: repeat the keyed range
+//   CycleOffset: repeat the keyed range, each repeat starting where the last one ended
+//   PingPong: repeat the keyed range, playing every other repeat backwards
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Extrapolation {
+    #[default]
+    Constant,
+    Linear,
+    Cycle,
+    CycleOffset,
+    PingPong,
+}
+
+impl Extrapolation {
+    pub const ALL: [Extrapolation; 5] = [
+        Extrapolation::Constant,
+        Extrapolation::Linear,
+        Extrapolation::Cycle,
+        Extrapolation::CycleOffset,
+        Extrapolation::PingPong,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Extrapolation::Constant => "Constant",
+            Extrapolation::Linear => "Linear",
+            Extrapolation::Cycle => "Cycle",
+            Extrapolation::CycleOffset => "Cycle with offset",
+            Extrapolation::PingPong => "Ping-pong",
+        }
+    }
+}
+
+// Enum that represents one value that can be keyframed and tweened.
+// The two cases for Track. Either it has one value for its whole existence, or it has keyframed
+// values. The reason it is split, rather than just have a tree with 1 element, is because there is
+// no sensible default 'Frame' value (key to the map) for the initial value. Instead, the initial
+// value is kept as the rest value of the keyed track.
+#[derive(Clone)]
+pub enum Track<T: Interpolate> {
+    Single(T),
     Multiple {
-        tree: BTreeMap<Frame, Vec3>,
-        cache: RefCell<Option<(Frame, Vec3)>>,
+        rest: T,
+        tree: BTreeMap<Frame, Key<T>>,
+        cache: RefCell<Option<(Frame, T)>>,
+        pre: Extrapolation,
+        post: Extrapolation,
     },
 }
 
-impl KeyframeV3 {
-    pub fn new(init: Vec3) -> KeyframeV3 {
-        KeyframeV3::Single(init)
+impl<T: Interpolate> Track<T> {
+    pub fn new(init: T) -> Track<T> {
+        Track::Single(init)
     }
 
     // Returns the value that this would have at a given frame. Depending on data held, it may or
     // may not require any tweening calculation
-    pub fn at(&self, frame: Frame) -> Vec3 {
+    pub fn at(&self, frame: Frame) -> T {
         match &self {
-            // There is only ever one value for the float, return it
-            KeyframeV3::Single(v) => *v,
+            // There is only ever one value, return it
+            Track::Single(v) => *v,
 
             // There are keyframed values, and the value at this frame may need to be calculated
-            KeyframeV3::Multiple { tree, cache } => {
+            Track::Multiple {
+                tree,
+                cache,
+                pre,
+                post,
+                ..
+            } => {
                 let cache_ref = cache.borrow();
 
                 match *cache_ref {
@@ -51,35 +222,7 @@ impl KeyframeV3 {
                     // There is not a cached value for this frame. Calculate a new one
                     _ => {
                         // Calculate new value from tree
-                        let value = if let Some(v) = tree.get(&frame) {
-                            // If there is an exact value for this frame in the tree, return it
-                            *v
-                        } else {
-                            // There is no exact value for this frame, so it must be calculated
-                            let prev_val = tree.range(..frame).next_back();
-                            let next_val = tree.range(frame..).next();
-
-                            match (prev_val, next_val) {
-                                // If between two keyframes, calculate the value with tweening
-                                (Some((pf, pv)), Some((nf, nv))) => {
-                                    let frame = frame as f32;
-                                    let pf = *pf as f32;
-                                    let nf = *nf as f32;
-                                    let x = linear_tween((pf, pv.x), (nf, nv.x), frame);
-                                    let y = linear_tween((pf, pv.y), (nf, nv.y), frame);
-                                    let z = linear_tween((pf, pv.z), (nf, nv.z), frame);
-
-                                    Vec3::new(x, y, z)
-                                }
-
-                                // If behind or in front of just one keyframe, return its value
-                                (Some((_pf, pv)), None) => *pv,
-                                (None, Some((_nf, nv))) => *nv,
-
-                                // Empty tree. Currently this is unreachable
-                                (None, None) => unreachable!(),
-                            }
-                        };
+                        let value = evaluate(tree, (*pre, *post), frame as f32);
 
                         // Store value in cache
                         drop(cache_ref);
@@ -93,39 +236,822 @@ impl KeyframeV3 {
         }
     }
 
-    pub fn frames(&self) -> Option<Keys<Frame, Vec3>> {
+    // Returns the value at any point in time, including between frames. Not cached, see at() for
+    // the common case of whole frames
+    pub fn sample(&self, frame: f32) -> T {
         match self {
-            KeyframeV3::Single(_) => None,
-            KeyframeV3::Multiple { tree, .. } => Some(tree.keys()),
+            Track::Single(v) => *v,
+            Track::Multiple {
+                tree, pre, post, ..
+            } => evaluate(tree, (*pre, *post), frame),
         }
     }
 
-    pub fn set_at(&mut self, frame: Frame, val: Vec3) {
+    pub fn frames(&self) -> Option<Keys<'_, Frame, Key<T>>> {
         match self {
-            KeyframeV3::Single(_) => {
-                // Turn self.0 into a btreemap with the given value. This throws out the old
-                // initial value
+            Track::Single(_) => None,
+            Track::Multiple { tree, .. } => Some(tree.keys()),
+        }
+    }
+
+    // Sets the value at a given frame. If there is already a keyframe there, its easing and
+    // handles are kept, otherwise the new keyframe is linear with no handles
+    pub fn set_at(&mut self, frame: Frame, val: T) {
+        match self {
+            Track::Single(init) => {
+                // Turn self.0 into a btreemap with the given value. The old initial value is kept
+                // as the rest value
                 let mut tree = BTreeMap::new();
-                tree.insert(frame, val.clone());
-                *self = KeyframeV3::Multiple {
+                tree.insert(frame, Key::new(val));
+                *self = Track::Multiple {
+                    rest: *init,
                     tree,
                     cache: RefCell::new(None),
+                    pre: Extrapolation::default(),
+                    post: Extrapolation::default(),
                 };
             }
 
-            KeyframeV3::Multiple { tree, cache } => {
+            Track::Multiple { tree, cache, .. } => {
                 // Insert given value
-                tree.insert(frame, val);
+                tree.entry(frame)
+                    .and_modify(|k| k.value = val)
+                    .or_insert_with(|| Key::new(val));
+
+                // Moving a keyframe moves the automatic handles around it
+                recalc_handles(tree);
 
                 // Reset cache
                 *cache.borrow_mut() = None
             }
         }
     }
+
+    // Puts a keyframe at the given frame exactly as given, easing and handles included. Automatic
+    // handles are recalculated to fit their new neighbours
+    pub fn set_key(&mut self, frame: Frame, key: Key<T>) {
+        self.set_at(frame, key.value);
+        self.modify_key(frame, |k| *k = key);
+    }
+
+    // Changes the value seen at a given frame. A track without keyframes just gets a new single
+    // value, a keyframed one gets a keyframe at that frame
+    pub fn update_at(&mut self, frame: Frame, val: T) {
+        match self {
+            Track::Single(v) => *v = val,
+            Track::Multiple { .. } => self.set_at(frame, val),
+        }
+    }
+
+    pub fn is_keyed(&self) -> bool {
+        matches!(self, Track::Multiple { .. })
+    }
+
+    // Stores a value edited at the given frame. Keyed tracks are keyed there, tracks without
+    // keyframes only get keyed with AutoKey::All and otherwise have their single value changed
+    pub fn edit_at(&mut self, frame: Frame, val: T, auto_key: AutoKey) {
+        if self.is_keyed() || auto_key == AutoKey::All {
+            self.set_at(frame, val);
+        } else {
+            self.set_rest(val);
+        }
+    }
+
+    // Returns the value used when the track isn't animated. For a track without keyframes, that is
+    // its single value
+    pub fn rest(&self) -> T {
+        match self {
+            Track::Single(v) => *v,
+            Track::Multiple { rest, .. } => *rest,
+        }
+    }
+
+    // Changes the value used when the track isn't animated. Keyframes are left alone
+    pub fn set_rest(&mut self, val: T) {
+        match self {
+            Track::Single(v) => *v = val,
+            Track::Multiple { rest, .. } => *rest = val,
+        }
+    }
+
+    // Makes the value seen at a given frame the rest value again, keying it if the track is keyed
+    pub fn reset_to_rest(&mut self, frame: Frame) {
+        let rest = self.rest();
+        self.update_at(frame, rest);
+    }
+
+    // Removes every keyframe, turning the track back into its rest value
+    pub fn clear_keys(&mut self) {
+        *self = Track::Single(self.rest());
+    }
+
+    // Returns the (pre, post) extrapolation modes. Tracks without keyframes have none
+    pub fn extrapolation(&self) -> Option<(Extrapolation, Extrapolation)> {
+        match self {
+            Track::Single(_) => None,
+            Track::Multiple { pre, post, .. } => Some((*pre, *post)),
+        }
+    }
+
+    // Sets what happens before the first keyframe and after the last one. Returns false if the
+    // track has no keyframes
+    pub fn set_extrapolation(&mut self, new_pre: Extrapolation, new_post: Extrapolation) -> bool {
+        match self {
+            Track::Single(_) => false,
+            Track::Multiple {
+                cache, pre, post, ..
+            } => {
+                *pre = new_pre;
+                *post = new_post;
+                *cache.borrow_mut() = None;
+                true
+            }
+        }
+    }
+
+    // Returns a copy of the keyframe at the given frame, if there is one
+    pub fn key_at(&self, frame: Frame) -> Option<Key<T>> {
+        match self {
+            Track::Single(_) => None,
+            Track::Multiple { tree, .. } => tree.get(&frame).copied(),
+        }
+    }
+
+    // Returns the easing of the keyframe at the given frame, if there is one
+    pub fn easing_at(&self, frame: Frame) -> Option<Easing> {
+        self.key_at(frame).map(|k| k.easing)
+    }
+
+    // Sets the easing used for the segment starting at the keyframe at the given frame. Returns
+    // false if there is no keyframe there
+    pub fn set_easing_at(&mut self, frame: Frame, easing: Easing) -> bool {
+        self.modify_key(frame, |key| key.easing = easing)
+    }
+
+    // Gives the keyframe at the given frame a handle of the given type on one side, or changes the
+    // type of the handle that is already there. Returns false if there is no keyframe there
+    pub fn set_handle_type(&mut self, frame: Frame, side: HandleSide, kind: HandleType) -> bool {
+        self.modify_key(frame, |key| {
+            let like = key.value;
+            match key.handle_mut(side) {
+                Some(handle) => handle.kind = kind,
+                handle => *handle = Some(Handle::new(kind, &like)),
+            }
+        })
+    }
+
+    // Moves a handle of the keyframe at the given frame to the given offset. Like Blender, moving
+    // an Auto handle makes it Aligned and moving a Vector handle makes it Free. Returns false if
+    // there is no keyframe there
+    pub fn set_handle(&mut self, frame: Frame, side: HandleSide, frames: f32, value: T) -> bool {
+        self.modify_key(frame, |key| {
+            let kind = match key.handle_mut(side) {
+                Some(h) => match h.kind {
+                    HandleType::Auto | HandleType::AutoClamped => HandleType::Aligned,
+                    HandleType::Vector => HandleType::Free,
+                    kind => kind,
+                },
+                None => HandleType::Free,
+            };
+
+            // Handles may not cross over their keyframe in time
+            let frames = match side {
+                HandleSide::In => frames.min(0.),
+                HandleSide::Out => frames.max(0.),
+            };
+
+            *key.handle_mut(side) = Some(Handle {
+                kind,
+                frames,
+                value,
+            });
+
+            // An aligned handle on the other side follows this one. Automatic handles come in
+            // pairs, so when one of them is moved the other one becomes aligned too
+            if let Some(o) = key.handle_mut(side.opposite()) {
+                if kind == HandleType::Aligned
+                    && matches!(o.kind, HandleType::Auto | HandleType::AutoClamped)
+                {
+                    o.kind = HandleType::Aligned;
+                }
+                if o.kind == HandleType::Aligned {
+                    *o = aligned_to(*o, frames, &value);
+                }
+            }
+        })
+    }
+
+    // Removes both handles from the keyframe at the given frame, so it is eased again. Returns
+    // false if there is no keyframe there
+    pub fn clear_handles(&mut self, frame: Frame) -> bool {
+        self.modify_key(frame, |key| {
+            key.handle_in = None;
+            key.handle_out = None;
+        })
+    }
+
+    // Removes the keyframe at the given frame, returning its value. Removing the last keyframe
+    // turns the track back into its rest value
+    pub fn remove_at(&mut self, frame: Frame) -> Option<T> {
+        self.modify_tree(|tree| tree.remove(&frame).map(|k| k.value))
+            .flatten()
+    }
+
+    // Moves the keyframe at `from` to `to`, keeping its easing and handles. What happens if there
+    // is already a keyframe at `to` depends on `collision`. Returns false if nothing was moved
+    pub fn move_key(&mut self, from: Frame, to: Frame, collision: KeyCollision) -> bool {
+        if from == to {
+            return self.key_at(from).is_some();
+        }
+
+        self.modify_tree(|tree| {
+            if !tree.contains_key(&from) {
+                return false;
+            }
+
+            match (tree.contains_key(&to), collision) {
+                (true, KeyCollision::Skip) => false,
+                (true, KeyCollision::Swap) => {
+                    let moved = tree.remove(&from).unwrap();
+                    let other = tree.insert(to, moved).unwrap();
+                    tree.insert(from, other);
+                    true
+                }
+                _ => {
+                    let moved = tree.remove(&from).unwrap();
+                    tree.insert(to, moved);
+                    true
+                }
+            }
+        })
+        .unwrap_or(false)
+    }
+
+    // Retimes every keyframe in `range`, moving it to pivot + (frame - pivot) * scale + offset,
+    // rounded to the nearest frame. Negative scales reverse the keyframes. Frames can't go below
+    // zero. Keyframes landing on a keyframe outside the range are handled like in move_key, with
+    // swapped keyframes taking the frames the retimed ones left. Retimed keyframes landing on each
+    // other can only be merged, so Skip and Swap leave the track untouched when that happens.
+    // Returns the number of keyframes moved
+    pub fn retime(
+        &mut self,
+        range: RangeInclusive<Frame>,
+        pivot: f32,
+        scale: f32,
+        offset: f32,
+        collision: KeyCollision,
+    ) -> usize {
+        self.modify_tree(|tree| {
+            let retimed: Vec<(Frame, Frame)> = tree
+                .range(range)
+                .map(|(f, _)| {
+                    let to = pivot + (*f as f32 - pivot) * scale + offset;
+                    (*f, to.round().max(0.) as Frame)
+                })
+                .collect();
+
+            let mut targets: Vec<Frame> = retimed.iter().map(|(_, to)| *to).collect();
+            targets.sort_unstable();
+            targets.dedup();
+            let merged = targets.len() < retimed.len();
+
+            let sources: BTreeSet<Frame> = retimed.iter().map(|(from, _)| *from).collect();
+            let hit: Vec<Frame> = targets
+                .iter()
+                .copied()
+                .filter(|f| !sources.contains(f) && tree.contains_key(f))
+                .collect();
+
+            let swapped: Vec<(Frame, Key<T>)> = match collision {
+                KeyCollision::Skip if merged || !hit.is_empty() => return 0,
+                KeyCollision::Swap if merged => return 0,
+                KeyCollision::Swap => {
+                    let freed = sources.iter().filter(|f| targets.binary_search(f).is_err());
+                    hit.iter()
+                        .zip(freed)
+                        .map(|(f, to)| (*to, tree.remove(f).unwrap()))
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+
+            let moved: Vec<(Frame, Key<T>)> = retimed
+                .iter()
+                .map(|(from, to)| (*to, tree.remove(from).unwrap()))
+                .collect();
+
+            for (frame, mut key) in moved.iter().copied() {
+                // Handles stretch along with the keyframes. Reversing swaps their sides
+                let stretch = |h: Handle<T>| Handle {
+                    frames: h.frames * scale,
+                    ..h
+                };
+                let (h_in, h_out) = (key.handle_in.map(stretch), key.handle_out.map(stretch));
+                if scale < 0. {
+                    key.handle_in = h_out;
+                    key.handle_out = h_in;
+                } else {
+                    key.handle_in = h_in;
+                    key.handle_out = h_out;
+                }
+
+                tree.insert(frame, key);
+            }
+            tree.extend(swapped);
+
+            moved.len()
+        })
+        .unwrap_or(0)
+    }
+
+    // Moves the keyframes at the given frames by delta frames, all together. Moved keyframes
+    // replace any keyframes they land on. Delta is limited so that no keyframe goes below frame
+    // zero. Returns the frames the keyframes ended up at
+    pub fn shift_keys(&mut self, frames: &[Frame], delta: isize) -> Vec<Frame> {
+        self.modify_tree(|tree| {
+            let moved: Vec<(Frame, Key<T>)> = frames
+                .iter()
+                .filter_map(|f| tree.remove(f).map(|k| (*f, k)))
+                .collect();
+
+            let lowest = moved.iter().map(|(f, _)| *f).min().unwrap_or(0);
+            let delta = delta.max(-(lowest as isize));
+
+            moved
+                .into_iter()
+                .map(|(f, k)| {
+                    let to = (f as isize + delta) as Frame;
+                    tree.insert(to, k);
+                    to
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    // Returns the value of the keyframe at the given frame, if there is one
+    pub fn value_at_key(&self, frame: Frame) -> Option<T> {
+        self.key_at(frame).map(|k| k.value)
+    }
+
+    // Returns the frame of the keyframe closest to the given frame. Ties go to the earlier one
+    pub fn nearest_key(&self, frame: f32) -> Option<Frame> {
+        let frames = self.frames()?;
+        frames.copied().min_by(|a, b| {
+            let da = (*a as f32 - frame).abs();
+            let db = (*b as f32 - frame).abs();
+            da.total_cmp(&db).then(a.cmp(b))
+        })
+    }
+
+    // Returns the frame of the last keyframe before the given frame
+    pub fn prev_key(&self, frame: Frame) -> Option<Frame> {
+        match self {
+            Track::Single(_) => None,
+            Track::Multiple { tree, .. } => tree.range(..frame).next_back().map(|(f, _)| *f),
+        }
+    }
+
+    // Returns the frame of the first keyframe after the given frame
+    pub fn next_key(&self, frame: Frame) -> Option<Frame> {
+        match self {
+            Track::Single(_) => None,
+            Track::Multiple { tree, .. } => tree
+                .range((Excluded(frame), Unbounded))
+                .next()
+                .map(|(f, _)| *f),
+        }
+    }
+
+    // Applies a change to the keyframe at the given frame. Returns false if there is no keyframe
+    // there
+    fn modify_key<F: FnOnce(&mut Key<T>)>(&mut self, frame: Frame, func: F) -> bool {
+        self.modify_tree(|tree| tree.get_mut(&frame).map(func).is_some())
+            .unwrap_or(false)
+    }
+
+    // Applies a change to the keyframes, then brings the automatic handles and the cache up to
+    // date. If the change removes every keyframe, the track goes back to being a single value.
+    // Returns None if there are no keyframes to change
+    fn modify_tree<R, F>(&mut self, func: F) -> Option<R>
+    where
+        F: FnOnce(&mut BTreeMap<Frame, Key<T>>) -> R,
+    {
+        match self {
+            Track::Single(_) => None,
+            Track::Multiple {
+                rest, tree, cache, ..
+            } => {
+                let result = func(tree);
+                recalc_handles(tree);
+                *cache.borrow_mut() = None;
+
+                if tree.is_empty() {
+                    *self = Track::Single(*rest);
+                }
+
+                Some(result)
+            }
+        }
+    }
+}
+
+// Keyframe editing that doesn't depend on the type of value, for editors such as the timeline
+// that only care about where keyframes are
+pub trait Channel {
+    fn key_frames(&self) -> Vec<Frame>;
+    fn delete_key(&mut self, frame: Frame) -> bool;
+    fn shift_keys(&mut self, frames: &[Frame], delta: isize) -> Vec<Frame>;
+}
+
+impl<T: Interpolate> Channel for Track<T> {
+    fn key_frames(&self) -> Vec<Frame> {
+        self.frames()
+            .map(|frames| frames.copied().collect())
+            .unwrap_or_default()
+    }
+
+    fn delete_key(&mut self, frame: Frame) -> bool {
+        self.remove_at(frame).is_some()
+    }
+
+    fn shift_keys(&mut self, frames: &[Frame], delta: isize) -> Vec<Frame> {
+        Track::shift_keys(self, frames, delta)
+    }
+}
+
+impl<T> Key<T> {
+    fn handle_mut(&mut self, side: HandleSide) -> &mut Option<Handle<T>> {
+        match side {
+            HandleSide::In => &mut self.handle_in,
+            HandleSide::Out => &mut self.handle_out,
+        }
+    }
+}
+
+impl HandleSide {
+    pub fn opposite(self) -> HandleSide {
+        match self {
+            HandleSide::In => HandleSide::Out,
+            HandleSide::Out => HandleSide::In,
+        }
+    }
+}
+
+// Bezier handles
+// =================================================================================================
+// Recalculates every handle that isn't placed by the user (Auto, AutoClamped and Vector), then
+// lines up Aligned handles with the handle across from them
+fn recalc_handles<T: Interpolate>(tree: &mut BTreeMap<Frame, Key<T>>) {
+    // Snapshot of (frame, value) for looking at neighbours while modifying keys
+    let points: Vec<(f32, T)> = tree.iter().map(|(f, k)| (*f as f32, k.value)).collect();
+
+    for (i, key) in tree.values_mut().enumerate() {
+        let (frame, value) = points[i];
+        let prev = if i > 0 { Some(points[i - 1]) } else { None };
+        let next = points.get(i + 1).copied();
+
+        for &side in &[HandleSide::In, HandleSide::Out] {
+            let neighbour = match side {
+                HandleSide::In => prev,
+                HandleSide::Out => next,
+            };
+
+            let handle = match key.handle_mut(side) {
+                Some(h) => h,
+                None => continue,
+            };
+
+            // Handles reach a third of the way to the neighbouring keyframe, like a smooth
+            // cubic spline
+            let length = neighbour.map(|(f, _)| (f - frame) / 3.).unwrap_or(0.);
+
+            match handle.kind {
+                HandleType::Auto | HandleType::AutoClamped => {
+                    let clamped = handle.kind == HandleType::AutoClamped;
+                    let slope = auto_slope(prev, (frame, value), next, clamped);
+                    let mut offset = slope.map_components(|s| s * length);
+
+                    // Auto-clamped handles must not reach past the neighbour's value, or the
+                    // curve would overshoot it
+                    if let (true, Some((_, nv))) = (clamped, neighbour) {
+                        let room = nv.zip_components(&value, |n, v| n - v);
+                        offset = offset.zip_components(&room, |o, room| {
+                            if room >= 0. {
+                                o.clamp(0., room)
+                            } else {
+                                o.clamp(room, 0.)
+                            }
+                        });
+                    }
+
+                    handle.frames = length;
+                    handle.value = offset;
+                }
+
+                HandleType::Vector => {
+                    handle.frames = length;
+                    handle.value = match neighbour {
+                        Some((_, nv)) => nv.zip_components(&value, |n, v| (n - v) / 3.),
+                        None => value.map_components(|_| 0.),
+                    };
+                }
+
+                HandleType::Aligned | HandleType::Free => (),
+            }
+        }
+
+        // Aligned handles point directly away from the handle on the other side
+        for &side in &[HandleSide::In, HandleSide::Out] {
+            let other = *key.handle_mut(side.opposite());
+            if let (Some(h), Some(o)) = (key.handle_mut(side), other) {
+                if h.kind == HandleType::Aligned && o.kind != HandleType::Aligned {
+                    *h = aligned_to(*h, o.frames, &o.value);
+                }
+            }
+        }
+    }
+}
+
+// Slope (change in value per frame) of an automatic handle through a keyframe, based on its
+// neighbours. Keyframes at either end of the track get flat handles. Clamped handles are also flat
+// for any component where the keyframe is a peak or a valley
+fn auto_slope<T: Interpolate>(
+    prev: Option<(f32, T)>,
+    (_frame, value): (f32, T),
+    next: Option<(f32, T)>,
+    clamped: bool,
+) -> T {
+    match (prev, next) {
+        (Some((pf, pv)), Some((nf, nv))) => {
+            let mut slope = nv.zip_components(&pv, |n, p| (n - p) / (nf - pf));
+            if clamped {
+                for c in 0..T::COMPONENTS {
+                    let rising = nv.component(c) - value.component(c);
+                    let falling = value.component(c) - pv.component(c);
+                    if rising * falling <= 0. {
+                        slope.set_component(c, 0.);
+                    }
+                }
+            }
+            slope
+        }
+        _ => value.map_components(|_| 0.),
+    }
+}
+
+// Returns a handle that keeps its own length along the time axis, but points opposite to the given
+// handle offset
+fn aligned_to<T: Interpolate>(handle: Handle<T>, frames: f32, value: &T) -> Handle<T> {
+    let own = handle.frames.abs();
+    let other = frames.abs();
+    let value = if other > 0. {
+        value.map_components(|v| -v * (own / other))
+    } else {
+        value.map_components(|_| 0.)
+    };
+
+    Handle { value, ..handle }
+}
+
+// Evaluates the cubic bezier curve running from the left keyframe, through its outgoing handle and
+// the right keyframe's incoming handle, to the right keyframe. The curve is first solved for the
+// point that is at the given frame, then the value there is calculated one component at a time. A
+// missing incoming handle acts like a Vector handle
+fn bezier_segment<T: Interpolate>(
+    (pf, pk): (f32, &Key<T>),
+    (nf, nk): (f32, &Key<T>),
+    frame: f32,
+) -> T {
+    let width = nf - pf;
+    let out = pk
+        .handle_out
+        .unwrap_or_else(|| Handle::new(HandleType::Free, &pk.value));
+    let inc = nk.handle_in.unwrap_or(Handle {
+        kind: HandleType::Vector,
+        frames: -width / 3.,
+        value: pk.value.zip_components(&nk.value, |p, n| (p - n) / 3.),
+    });
+
+    // Keep the handles inside the segment. If they overlap in time, they are both shortened so
+    // that the curve can't loop back on itself (same fix Blender uses)
+    let (mut h1, mut v1) = (out.frames.clamp(0., width), out.value);
+    let (mut h2, mut v2) = (inc.frames.clamp(-width, 0.), inc.value);
+    let reach = h1 - h2;
+    if reach > width {
+        let shrink = width / reach;
+        h1 *= shrink;
+        v1 = v1.map_components(|v| v * shrink);
+        h2 *= shrink;
+        v2 = v2.map_components(|v| v * shrink);
+    }
+
+    // Time along the curve is monotonic, so bisection always finds the right point
+    let (x0, x1, x2, x3) = (pf, pf + h1, nf + h2, nf);
+    let (mut lo, mut hi) = (0_f32, 1_f32);
+    for _ in 0..30 {
+        let mid = (lo + hi) / 2.;
+        if cubic_bezier(x0, x1, x2, x3, mid) < frame {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let s = (lo + hi) / 2.;
+
+    let mut value = pk.value;
+    for c in 0..T::COMPONENTS {
+        let (p0, p3) = (pk.value.component(c), nk.value.component(c));
+        let (p1, p2) = (p0 + v1.component(c), p3 + v2.component(c));
+        value.set_component(c, cubic_bezier(p0, p1, p2, p3, s));
+    }
+    value.normalized()
+}
+
+fn cubic_bezier(p0: f32, p1: f32, p2: f32, p3: f32, s: f32) -> f32 {
+    let r = 1. - s;
+    r * r * r * p0 + 3. * r * r * s * p1 + 3. * r * s * s * p2 + s * s * s * p3
 }
 
 // Tweening functions
 // =================================================================================================
+// Calculates the value of a keyframed track at any point in time, given its (pre, post)
+// extrapolation modes
+fn evaluate<T: Interpolate>(
+    tree: &BTreeMap<Frame, Key<T>>,
+    (pre, post): (Extrapolation, Extrapolation),
+    frame: f32,
+) -> T {
+    let (first, last) = match (tree.keys().next(), tree.keys().next_back()) {
+        (Some(first), Some(last)) => (*first as f32, *last as f32),
+
+        // Empty tree. Currently this is unreachable
+        _ => unreachable!(),
+    };
+
+    // A single keyframe has nothing to repeat or continue, it is always held
+    if first < last {
+        if frame < first {
+            return extrapolate(tree, pre, (first, last), frame);
+        } else if frame > last {
+            return extrapolate(tree, post, (first, last), frame);
+        }
+    }
+
+    evaluate_keyed(tree, frame)
+}
+
+// Calculates the value of a keyframed track at a frame outside of its first and last keyframes
+fn extrapolate<T: Interpolate>(
+    tree: &BTreeMap<Frame, Key<T>>,
+    mode: Extrapolation,
+    (first, last): (f32, f32),
+    frame: f32,
+) -> T {
+    // Stepped values can't be continued or offset, so those just hold or repeat
+    let mode = match mode {
+        Extrapolation::Linear if T::STEPPED => Extrapolation::Constant,
+        Extrapolation::CycleOffset if T::STEPPED => Extrapolation::Cycle,
+        mode => mode,
+    };
+
+    // How many times the keyed range has been repeated (negative before it), and where in the
+    // range the frame falls
+    let length = last - first;
+    let cycles = ((frame - first) / length).floor();
+    let local = frame - cycles * length;
+
+    match mode {
+        Extrapolation::Constant => evaluate_keyed(tree, frame),
+
+        Extrapolation::Linear => linear_extension(tree, frame < first, frame),
+
+        Extrapolation::Cycle => evaluate_keyed(tree, local),
+
+        Extrapolation::CycleOffset => {
+            // Each repeat is moved by the difference between the last and first keyframes
+            let start = tree.values().next().unwrap().value;
+            let end = tree.values().next_back().unwrap().value;
+            evaluate_keyed(tree, local).offset_by(&start, &end, cycles)
+        }
+
+        Extrapolation::PingPong => {
+            if (cycles as i64).rem_euclid(2) == 0 {
+                evaluate_keyed(tree, local)
+            } else {
+                evaluate_keyed(tree, last - (local - first))
+            }
+        }
+    }
+}
+
+// Continues a track in a straight line past its first (before = true) or last keyframe. Bezier
+// keyframes continue along their outer handle, others along the line through the end keyframe and
+// the one next to it
+fn linear_extension<T: Interpolate>(tree: &BTreeMap<Frame, Key<T>>, before: bool, frame: f32) -> T {
+    let mut keys: Box<dyn Iterator<Item = (&Frame, &Key<T>)>> = if before {
+        Box::new(tree.iter())
+    } else {
+        Box::new(tree.iter().rev())
+    };
+    let (end_frame, end) = keys.next().unwrap();
+    let (next_frame, next) = keys.next().unwrap();
+    let (end_frame, next_frame) = (*end_frame as f32, *next_frame as f32);
+
+    let handle = if before {
+        end.handle_in
+    } else {
+        end.handle_out
+    };
+    match handle {
+        Some(h) if h.frames != 0. => {
+            let frames = frame - end_frame;
+            end.value
+                .zip_components(&h.value, |v, h_v| v + h_v / h.frames * frames)
+                .normalized()
+        }
+
+        // Tweening past the ends of a segment keeps going in the same direction
+        _ => {
+            let t = (frame - next_frame) / (end_frame - next_frame);
+            T::interpolate(None, &next.value, &end.value, None, t)
+        }
+    }
+}
+
+// Calculates the value of a keyframed track at a frame, holding the end values outside of the
+// keyframes
+fn evaluate_keyed<T: Interpolate>(tree: &BTreeMap<Frame, Key<T>>, frame: f32) -> T {
+    // Split the keyframes into ones at or before the frame and ones after it. Nothing is before a
+    // negative frame
+    let whole = frame.floor();
+    let (mut before, mut after) = if whole < 0. {
+        (tree.range(..0).rev(), tree.range(0..))
+    } else {
+        let whole = whole as Frame;
+
+        // If there is an exact value for this frame in the tree, return it
+        if whole as f32 == frame {
+            if let Some(k) = tree.get(&whole) {
+                return k.value;
+            }
+        }
+
+        (tree.range(..=whole).rev(), tree.range(whole + 1..))
+    };
+
+    // There is no exact value for this frame, so it must be calculated
+    match (before.next(), after.next()) {
+        // If between two keyframes, calculate the value with tweening. The keyframes on either
+        // side of the segment are passed along for types that tween along a spline
+        (Some((pf, pk)), Some((nf, nk))) => tween_segment(
+            before.next().map(|(_, k)| k),
+            (*pf, pk),
+            (*nf, nk),
+            after.next().map(|(_, k)| k),
+            frame,
+        ),
+
+        // If behind or in front of just one keyframe, return its value
+        (Some((_pf, pk)), None) => pk.value,
+        (None, Some((_nf, nk))) => nk.value,
+
+        // Empty tree. Currently this is unreachable
+        (None, None) => unreachable!(),
+    }
+}
+
+// Calculates the value at a frame between two keyframes. Stepped types hold the left keyframe's
+// value. Segments starting at a keyframe with an outgoing handle are bezier curves. Otherwise the
+// left keyframe's easing decides how far along the segment the value is, and the type decides how
+// to tween (see Interpolate::interpolate)
+fn tween_segment<T: Interpolate>(
+    prev: Option<&Key<T>>,
+    (pf, pk): (Frame, &Key<T>),
+    (nf, nk): (Frame, &Key<T>),
+    next: Option<&Key<T>>,
+    frame: f32,
+) -> T {
+    let (pf, nf) = (pf as f32, nf as f32);
+
+    if T::STEPPED {
+        return pk.value;
+    }
+
+    if pk.handle_out.is_some() {
+        return bezier_segment((pf, pk), (nf, nk), frame);
+    }
+
+    let t = pk.easing.apply(linear_tween((pf, 0.), (nf, 1.), frame));
+    T::interpolate(
+        prev.map(|k| &k.value),
+        &pk.value,
+        &nk.value,
+        next.map(|k| &k.value),
+        t,
+    )
+}
+
 fn linear_tween((x1, y1): (f32, f32), (x2, y2): (f32, f32), x: f32) -> f32 {
     // Uses point slope formula:
     // y-y1 = m(x-x1)
@@ -140,6 +1066,8 @@ fn linear_tween((x1, y1): (f32, f32), (x2, y2): (f32, f32), x: f32) -> f32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::easing::EaseMode;
+    use nalgebra_glm::Vec4;
 
     // Does a 'close enough' approximation of two f32 values
     fn feq(n1: f32, n2: f32) -> bool {
@@ -226,4 +1154,411 @@ mod tests {
 
         assert!(veq(kf.at(20), Vec3::new(2.0, 3.0, 4.0)));
     }
+
+    #[test]
+    fn keyframev3_edit_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+
+        // Nothing to edit without keyframes
+        assert_eq!(kf.remove_at(0), None);
+        assert!(!kf.move_key(0, 1, KeyCollision::Replace));
+        assert_eq!(kf.nearest_key(0.0), None);
+
+        kf.set_at(10, Vec3::new(1.0, 1.0, 1.0));
+        kf.set_at(20, Vec3::new(2.0, 2.0, 2.0));
+        kf.set_at(30, Vec3::new(3.0, 3.0, 3.0));
+
+        // Navigation
+        assert_eq!(kf.prev_key(20), Some(10));
+        assert_eq!(kf.prev_key(10), None);
+        assert_eq!(kf.next_key(20), Some(30));
+        assert_eq!(kf.next_key(25), Some(30));
+        assert_eq!(kf.next_key(30), None);
+        assert_eq!(kf.nearest_key(14.0), Some(10));
+        assert_eq!(kf.nearest_key(15.0), Some(10));
+        assert_eq!(kf.nearest_key(16.0), Some(20));
+        assert_eq!(kf.nearest_key(100.0), Some(30));
+        assert!(kf.nearest_key(f32::NAN).is_some());
+        assert_eq!(kf.next_key(usize::MAX), None);
+        assert!(veq(kf.value_at_key(20).unwrap(), Vec3::new(2.0, 2.0, 2.0)));
+        assert!(kf.value_at_key(21).is_none());
+
+        // Removing resets the cache
+        assert!(veq(kf.at(20), Vec3::new(2.0, 2.0, 2.0)));
+        assert!(veq(kf.remove_at(20).unwrap(), Vec3::new(2.0, 2.0, 2.0)));
+        assert!(veq(kf.at(20), Vec3::new(2.0, 2.0, 2.0)));
+        assert!(veq(kf.at(15), Vec3::new(1.5, 1.5, 1.5)));
+
+        // Moving onto a free frame
+        assert!(kf.move_key(30, 20, KeyCollision::Skip));
+        assert!(veq(kf.at(15), Vec3::new(2.0, 2.0, 2.0)));
+
+        // Moving onto a taken frame
+        kf.set_easing_at(10, Easing::Quad(EaseMode::In));
+        assert!(!kf.move_key(10, 20, KeyCollision::Skip));
+        assert!(kf.move_key(10, 20, KeyCollision::Swap));
+        assert!(veq(kf.value_at_key(10).unwrap(), Vec3::new(3.0, 3.0, 3.0)));
+        assert_eq!(kf.easing_at(20), Some(Easing::Quad(EaseMode::In)));
+        assert!(kf.move_key(10, 20, KeyCollision::Replace));
+        assert_eq!(kf.frames().unwrap().copied().collect::<Vec<_>>(), vec![20]);
+        assert!(veq(kf.at(20), Vec3::new(3.0, 3.0, 3.0)));
+
+        // Removing the last keyframe goes back to the rest value
+        kf.remove_at(20);
+        assert!(kf.frames().is_none());
+        assert!(veq(kf.at(0), Vec3::zeros()));
+    }
+
+    #[test]
+    fn track_rest_test() {
+        let mut kf = KeyframeV3::new(Vec3::new(1.0, 2.0, 3.0));
+
+        // Keying keeps the initial value as the rest value
+        kf.set_at(10, Vec3::new(4.0, 4.0, 4.0));
+        assert!(veq(kf.rest(), Vec3::new(1.0, 2.0, 3.0)));
+        assert!(veq(kf.at(0), Vec3::new(4.0, 4.0, 4.0)));
+
+        // Resetting keys the rest value at that frame
+        kf.reset_to_rest(20);
+        assert!(veq(kf.at(20), Vec3::new(1.0, 2.0, 3.0)));
+        assert!(veq(kf.at(10), Vec3::new(4.0, 4.0, 4.0)));
+
+        // Changing the rest value doesn't touch the keyframes
+        kf.set_rest(Vec3::zeros());
+        assert!(veq(kf.at(20), Vec3::new(1.0, 2.0, 3.0)));
+
+        // Clearing goes back to the rest value
+        kf.clear_keys();
+        assert!(!kf.is_keyed());
+        assert!(veq(kf.at(20), Vec3::zeros()));
+
+        // Without keyframes, the rest value is the value
+        kf.update_at(5, Vec3::new(1.0, 1.0, 1.0));
+        assert!(veq(kf.rest(), Vec3::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn track_auto_key_test() {
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        let two = Vec3::new(2.0, 2.0, 2.0);
+
+        // Off changes the single value of tracks without keyframes, and keys keyed tracks on the
+        // current frame so the edit is seen there
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+        kf.edit_at(5, one, AutoKey::Off);
+        assert!(!kf.is_keyed());
+        assert!(veq(kf.at(5), one));
+        kf.set_at(10, one);
+        kf.edit_at(5, two, AutoKey::Off);
+        assert_eq!(kf.key_frames(), vec![5, 10]);
+        assert!(veq(kf.at(5), two));
+        assert!(veq(kf.rest(), one));
+        kf.edit_at(10, two, AutoKey::Off);
+        assert!(veq(kf.at(10), two));
+
+        // KeyedOnly keys tracks that already have keyframes
+        kf.edit_at(20, one, AutoKey::KeyedOnly);
+        assert_eq!(kf.key_frames(), vec![5, 10, 20]);
+        let mut single = KeyframeV3::new(Vec3::zeros());
+        single.edit_at(20, one, AutoKey::KeyedOnly);
+        assert!(!single.is_keyed());
+        assert!(veq(single.at(0), one));
+
+        // All keys everything, keeping the old value as the rest value
+        single.edit_at(20, two, AutoKey::All);
+        assert_eq!(single.key_frames(), vec![20]);
+        assert!(veq(single.rest(), one));
+    }
+
+    #[test]
+    fn keyframev3_retime_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+        kf.set_at(10, Vec3::new(1.0, 0.0, 0.0));
+        kf.set_at(20, Vec3::new(2.0, 0.0, 0.0));
+        kf.set_at(30, Vec3::new(3.0, 0.0, 0.0));
+        kf.set_at(40, Vec3::new(4.0, 0.0, 0.0));
+
+        let frames = |kf: &KeyframeV3| kf.frames().unwrap().copied().collect::<Vec<_>>();
+
+        // Offset a range
+        assert_eq!(kf.retime(30..=40, 0.0, 1.0, 5.0, KeyCollision::Replace), 2);
+        assert_eq!(frames(&kf), vec![10, 20, 35, 45]);
+        assert!(veq(kf.at(40), Vec3::new(3.5, 0.0, 0.0)));
+
+        // Scale around a pivot
+        assert_eq!(kf.retime(0..=100, 10.0, 2.0, 0.0, KeyCollision::Replace), 4);
+        assert_eq!(frames(&kf), vec![10, 30, 60, 80]);
+
+        // Reverse around a pivot. Handles swap sides
+        kf.set_handle(10, HandleSide::Out, 2.0, Vec3::zeros());
+        kf.retime(10..=30, 20.0, -1.0, 0.0, KeyCollision::Replace);
+        assert_eq!(frames(&kf), vec![10, 30, 60, 80]);
+        assert!(veq(kf.value_at_key(10).unwrap(), Vec3::new(2.0, 0.0, 0.0)));
+        let k = kf.key_at(30).unwrap();
+        assert!(k.handle_out.is_none());
+        assert!(feq(k.handle_in.unwrap().frames, -2.0));
+
+        // Keyframes landing on each other are merged, and frames stop at zero
+        assert_eq!(kf.retime(0..=100, 0.0, 0.0, -5.0, KeyCollision::Replace), 4);
+        assert_eq!(frames(&kf), vec![0]);
+    }
+
+    #[test]
+    fn keyframev3_retime_collision_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+        kf.set_at(10, Vec3::new(1.0, 0.0, 0.0));
+        kf.set_at(20, Vec3::new(2.0, 0.0, 0.0));
+        kf.set_at(30, Vec3::new(3.0, 0.0, 0.0));
+
+        let frames = |kf: &KeyframeV3| kf.frames().unwrap().copied().collect::<Vec<_>>();
+        let x = |kf: &KeyframeV3, f| kf.value_at_key(f).unwrap().x;
+
+        // Landing on a keyframe outside the range
+        assert_eq!(kf.retime(10..=10, 0.0, 1.0, 10.0, KeyCollision::Skip), 0);
+        assert_eq!(frames(&kf), vec![10, 20, 30]);
+        assert!(feq(x(&kf, 10), 1.0));
+
+        assert_eq!(kf.retime(10..=10, 0.0, 1.0, 10.0, KeyCollision::Swap), 1);
+        assert_eq!(frames(&kf), vec![10, 20, 30]);
+        assert!(feq(x(&kf, 10), 2.0));
+        assert!(feq(x(&kf, 20), 1.0));
+
+        // Swapped keyframes take the frames left free by the retimed ones
+        assert_eq!(kf.retime(10..=20, 0.0, 1.0, 10.0, KeyCollision::Swap), 2);
+        assert_eq!(frames(&kf), vec![10, 20, 30]);
+        assert!(feq(x(&kf, 10), 3.0));
+        assert!(feq(x(&kf, 20), 2.0));
+        assert!(feq(x(&kf, 30), 1.0));
+
+        // Retimed keyframes landing on each other, by scaling to zero or clamping at frame zero
+        for collision in [KeyCollision::Skip, KeyCollision::Swap] {
+            assert_eq!(kf.retime(0..=100, 20.0, 0.0, 0.0, collision), 0);
+            assert_eq!(kf.retime(10..=20, 0.0, 1.0, -20.0, collision), 0);
+            assert_eq!(frames(&kf), vec![10, 20, 30]);
+        }
+
+        // Clamping without landing on anything is fine
+        assert_eq!(kf.retime(10..=20, 0.0, 1.0, -15.0, KeyCollision::Skip), 2);
+        assert_eq!(frames(&kf), vec![0, 5, 30]);
+
+        // Replace merges them, the last retimed keyframe winning
+        assert_eq!(kf.retime(0..=5, 0.0, 1.0, -10.0, KeyCollision::Replace), 2);
+        assert_eq!(frames(&kf), vec![0, 30]);
+        assert!(feq(x(&kf, 0), 2.0));
+    }
+
+    #[test]
+    fn channel_shift_keys_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+        kf.set_at(10, Vec3::new(1.0, 0.0, 0.0));
+        kf.set_at(20, Vec3::new(2.0, 0.0, 0.0));
+        kf.set_at(30, Vec3::new(3.0, 0.0, 0.0));
+        let channel: &mut dyn Channel = &mut kf;
+
+        // Keys move together, even past each other's old frames
+        assert_eq!(channel.shift_keys(&[10, 20], 10), vec![20, 30]);
+        assert_eq!(channel.key_frames(), vec![20, 30]);
+
+        // Missing keys are ignored, and nothing goes below zero
+        assert_eq!(channel.shift_keys(&[5, 20], -50), vec![0]);
+        assert_eq!(channel.key_frames(), vec![0, 30]);
+
+        assert!(channel.delete_key(30));
+        assert!(!channel.delete_key(30));
+        assert_eq!(channel.key_frames(), vec![0]);
+        assert!(veq(kf.at(0), Vec3::new(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn keyframev3_easing_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+
+        // Setting easing without any keyframes does nothing
+        assert!(!kf.set_easing_at(0, Easing::Quad(EaseMode::In)));
+
+        kf.set_at(0, Vec3::zeros());
+        kf.set_at(10, Vec3::new(10.0, 20.0, -10.0));
+        kf.set_at(20, Vec3::new(20.0, 20.0, 20.0));
+
+        // Default is linear
+        assert_eq!(kf.easing_at(0), Some(Easing::Linear));
+        assert!(veq(kf.at(5), Vec3::new(5.0, 10.0, -5.0)));
+
+        // Quad in at t = 0.5 is 0.25 of the way. Cache must be reset by set_easing_at
+        assert!(kf.set_easing_at(0, Easing::Quad(EaseMode::In)));
+        assert!(veq(kf.at(5), Vec3::new(2.5, 5.0, -2.5)));
+
+        // Only the segment starting at the eased keyframe is affected
+        assert!(veq(kf.at(15), Vec3::new(15.0, 20.0, 5.0)));
+
+        // Overwriting a keyframe's value keeps its easing
+        kf.set_at(0, Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(kf.easing_at(0), Some(Easing::Quad(EaseMode::In)));
+        assert_eq!(kf.easing_at(10), Some(Easing::Linear));
+        assert_eq!(kf.easing_at(11), None);
+    }
+
+    #[test]
+    fn keyframev3_bezier_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+        kf.set_at(0, Vec3::zeros());
+        kf.set_at(10, Vec3::new(10.0, 10.0, 10.0));
+        kf.set_at(20, Vec3::zeros());
+
+        // Vector handles on both sides of a segment make it a straight line
+        assert!(kf.set_handle_type(0, HandleSide::Out, HandleType::Vector));
+        assert!(kf.set_handle_type(10, HandleSide::In, HandleType::Vector));
+        assert!(veq(kf.at(5), Vec3::new(5.0, 5.0, 5.0)));
+        assert!(veq(kf.at(3), Vec3::new(3.0, 3.0, 3.0)));
+
+        // Auto-clamped handles at a peak are flat, so the curve never goes above it
+        for &f in &[0, 10, 20] {
+            kf.set_handle_type(f, HandleSide::In, HandleType::AutoClamped);
+            kf.set_handle_type(f, HandleSide::Out, HandleType::AutoClamped);
+        }
+        let peak = kf.key_at(10).unwrap();
+        assert!(veq(peak.handle_in.unwrap().value, Vec3::zeros()));
+        assert!(feq(peak.handle_out.unwrap().frames, 10.0 / 3.0));
+        for f in 0..=20 {
+            assert!(kf.at(f).x <= 10.0);
+            assert!(kf.at(f).x >= 0.0);
+        }
+
+        // Flat handles on both ends of a segment give a symmetric S curve
+        assert!(veq(kf.at(5), Vec3::new(5.0, 5.0, 5.0)));
+        assert!(kf.at(2).x < 2.0);
+        assert!(kf.at(8).x > 8.0);
+
+        // Moving an auto handle makes it aligned, and the other side follows it
+        kf.set_handle(10, HandleSide::Out, 2.0, Vec3::new(1.0, 0.0, 0.0));
+        let k = kf.key_at(10).unwrap();
+        assert_eq!(k.handle_out.unwrap().kind, HandleType::Aligned);
+        let hin = k.handle_in.unwrap();
+        assert_eq!(hin.kind, HandleType::Aligned);
+        assert!(veq(hin.value, Vec3::new(-10.0 / 6.0, 0.0, 0.0)));
+
+        // Without handles the segment goes back to easing
+        kf.clear_handles(0);
+        assert!(veq(kf.at(5), Vec3::new(5.0, 5.0, 5.0)));
+        assert!(!kf.clear_handles(1));
+    }
+
+    #[test]
+    fn track_types_test() {
+        // Floats tween like a single component of a KeyframeV3
+        let mut fov = Track::new(65.0_f32);
+        fov.set_at(0, 40.0);
+        fov.set_at(10, 60.0);
+        assert!(feq(fov.at(5), 50.0));
+        fov.set_handle_type(0, HandleSide::Out, HandleType::Vector);
+        assert!(feq(fov.at(5), 50.0));
+
+        // Bools are stepped, holding each keyframe's value until the next one
+        let mut visible = Track::new(true);
+        visible.set_at(0, true);
+        visible.set_at(10, false);
+        visible.set_at(20, true);
+        assert!(visible.at(9));
+        assert!(!visible.at(10));
+        assert!(!visible.at(19));
+        assert!(visible.at(25));
+
+        // Colours tween every channel, including alpha
+        let mut color = Track::new(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        color.set_at(0, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        color.set_at(4, Vec4::new(0.0, 0.0, 1.0, 0.0));
+        let c = color.at(1);
+        assert!(feq(c.x, 0.75) && feq(c.z, 0.25) && feq(c.w, 0.75));
+
+        // Un-keyed tracks change their single value, keyed ones get a new keyframe
+        let mut f = Track::new(1.0_f32);
+        f.update_at(3, 2.0);
+        assert!(!f.is_keyed());
+        assert!(feq(f.at(100), 2.0));
+        f.set_at(0, 0.0);
+        f.update_at(10, 10.0);
+        assert!(feq(f.at(5), 5.0));
+    }
+
+    #[test]
+    fn track_sample_test() {
+        let mut kf = KeyframeV3::new(Vec3::zeros());
+        kf.set_at(10, Vec3::new(1.0, 2.0, 3.0));
+        kf.set_at(20, Vec3::new(2.0, 4.0, 6.0));
+
+        // Whole frames match at()
+        for f in 0..30 {
+            assert!(veq(kf.sample(f as f32), kf.at(f)));
+        }
+
+        // In between frames
+        assert!(veq(kf.sample(12.5), Vec3::new(1.25, 2.5, 3.75)));
+        assert!(veq(kf.sample(19.9), Vec3::new(1.99, 3.98, 5.97)));
+        assert!(veq(kf.sample(10.0), Vec3::new(1.0, 2.0, 3.0)));
+
+        // Before the start of the timeline and past the end
+        assert!(veq(kf.sample(-3.5), Vec3::new(1.0, 2.0, 3.0)));
+        assert!(veq(kf.sample(25.5), Vec3::new(2.0, 4.0, 6.0)));
+
+        // Stepped tracks only change on keyframes
+        let mut b = Track::new(false);
+        b.set_at(1, false);
+        b.set_at(2, true);
+        assert!(!b.sample(1.99));
+        assert!(b.sample(2.0));
+    }
+
+    #[test]
+    fn track_extrapolation_test() {
+        let mut kf = Track::new(0.0_f32);
+        kf.set_at(10, 0.0);
+        kf.set_at(20, 10.0);
+
+        // Constant holds the end values
+        assert_eq!(
+            kf.extrapolation(),
+            Some((Extrapolation::Constant, Extrapolation::Constant))
+        );
+        assert!(feq(kf.at(5), 0.0));
+        assert!(feq(kf.at(30), 10.0));
+
+        // Linear keeps going. The cache must be reset by set_extrapolation
+        kf.set_extrapolation(Extrapolation::Linear, Extrapolation::Linear);
+        assert!(feq(kf.at(5), -5.0));
+        assert!(feq(kf.at(30), 20.0));
+        assert!(feq(kf.sample(-2.0), -12.0));
+
+        // Cycle repeats the keyed range
+        kf.set_extrapolation(Extrapolation::Cycle, Extrapolation::Cycle);
+        assert!(feq(kf.at(25), 5.0));
+        assert!(feq(kf.at(32), 2.0));
+        assert!(feq(kf.at(7), 7.0));
+
+        // Cycle with offset continues on from where the last repeat ended
+        kf.set_extrapolation(Extrapolation::CycleOffset, Extrapolation::CycleOffset);
+        assert!(feq(kf.at(25), 15.0));
+        assert!(feq(kf.at(40), 30.0));
+        assert!(feq(kf.at(7), -3.0));
+
+        // Ping-pong plays every other repeat backwards
+        kf.set_extrapolation(Extrapolation::PingPong, Extrapolation::PingPong);
+        assert!(feq(kf.at(22), 8.0));
+        assert!(feq(kf.at(30), 0.0));
+        assert!(feq(kf.at(33), 3.0));
+        assert!(feq(kf.at(8), 2.0));
+
+        // Bezier keyframes continue along their handle
+        kf.set_extrapolation(Extrapolation::Constant, Extrapolation::Linear);
+        kf.set_handle(20, HandleSide::Out, 2.0, 1.0);
+        assert!(feq(kf.at(24), 12.0));
+        assert!(feq(kf.at(5), 0.0));
+
+        // Single keyframes and un-keyed tracks are always held
+        let mut single = Track::new(1.0_f32);
+        assert!(!single.set_extrapolation(Extrapolation::Cycle, Extrapolation::Cycle));
+        single.set_at(3, 2.0);
+        single.set_extrapolation(Extrapolation::Linear, Extrapolation::Cycle);
+        assert!(feq(single.at(0), 2.0));
+        assert!(feq(single.at(9), 2.0));
+    }
 }
@@ -0,0 +1,277 @@
+/*
+This file contains the Armature struct, a hierarchy of bones deforming the mesh of the object it
+belongs to. Each vertex follows up to 4 bones, blended by its weights (see vertex.rs), which is
+done in the vertex shader (see shaders.rs) from the skin matrices worked out here.
+Bones are placed at rest by their head and tail, in the object's space, and turn about their
+head. They are posed with the same kind of position, rotation (Euler angles in radians) and scale
+tracks objects use, relative to their rest placement and their parent bone.
+
+TODO:
+    * Removing and reparenting bones
+    * Rest orientations, so bones can be keyed along their own length
+*/
+use crate::animation::{KeyframeV3, Track};
+use crate::ik::{self, IkChain};
+use crate::nla::Transform;
+use crate::rotation::euler_to_quat;
+use glm::Vec3;
+use imgui::ImString;
+use nalgebra_glm as glm;
+
+// Most bones an armature can have, as many as the vertex shader has room for
+pub const MAX_BONES: usize = 64;
+
+#[derive(Clone, Default)]
+pub struct Armature {
+    // Parents always come before their children
+    pub bones: Vec<Bone>,
+
+    // Skin with dual quaternions instead of blending matrices, which keeps volume when twisting
+    // but ignores bone scale
+    pub dual_quaternion: bool,
+
+    // Chains of bones posed by inverse kinematics, solved in order (see ik.rs)
+    pub chains: Vec<IkChain>,
+}
+
+#[derive(Clone)]
+pub struct Bone {
+    pub name: ImString,
+
+    // Index of the parent bone, if any
+    pub parent: Option<usize>,
+
+    // Rest placement, in the object's space
+    pub head: Vec3,
+    pub tail: Vec3,
+
+    // Pose, relative to the rest placement
+    pub position: KeyframeV3,
+    pub rotation: KeyframeV3,
+    pub scale: KeyframeV3,
+
+    // Least and most the bone can bend away from its parent's direction, in radians, when posed by
+    // inverse kinematics
+    pub ik_limit: Option<(f32, f32)>,
+}
+
+impl Bone {
+    pub fn new(name: ImString, parent: Option<usize>, head: Vec3, tail: Vec3) -> Bone {
+        Bone {
+            name,
+            parent,
+            head,
+            tail,
+            position: Track::new(Vec3::zeros()),
+            rotation: Track::new(Vec3::zeros()),
+            scale: Track::new(Vec3::new(1., 1., 1.)),
+            ik_limit: None,
+        }
+    }
+}
+
+impl Armature {
+    // Adds a bone, returning its index. Parents must already be in the armature
+    pub fn add_bone(&mut self, bone: Bone) -> Result<usize, String> {
+        if self.bones.len() >= MAX_BONES {
+            return Err(format!("Armatures can have at most {} bones", MAX_BONES));
+        }
+        if bone.parent.is_some_and(|p| p >= self.bones.len()) {
+            return Err("The parent bone doesn't exist".to_string());
+        }
+
+        self.bones.push(bone);
+        Ok(self.bones.len() - 1)
+    }
+
+    // Whether any bone is keyed, or any chain's target, pole or blend
+    pub fn is_animated(&self) -> bool {
+        let bones = self
+            .bones
+            .iter()
+            .any(|b| b.position.is_keyed() || b.rotation.is_keyed() || b.scale.is_keyed());
+        let chains = self.chains.iter().filter(|c| !c.muted).any(|c| {
+            c.target.is_keyed()
+                || c.pole.as_ref().is_some_and(Track::is_keyed)
+                || c.blend.is_keyed()
+        });
+        bones || chains
+    }
+
+    // Each bone's pose from its tracks, relative to its rest placement
+    pub fn local_poses(&self, frame: f32) -> Vec<Transform> {
+        self.bones
+            .iter()
+            .map(|bone| Transform {
+                position: bone.position.sample(frame),
+                rotation: euler_to_quat(&bone.rotation.sample(frame)),
+                scale: bone.scale.sample(frame),
+            })
+            .collect()
+    }
+
+    // Each bone's pose at any point in time, from its tracks and then inverse kinematics
+    pub fn poses(&self, frame: f32) -> Vec<Transform> {
+        let keyed = self.local_poses(frame);
+        if self.chains.is_empty() {
+            return keyed;
+        }
+        ik::solve(self, &self.chains, &keyed, frame)
+    }
+
+    // Poses leaving every bone at rest
+    pub fn rest_poses(&self) -> Vec<Transform> {
+        let rest = Transform {
+            position: Vec3::zeros(),
+            rotation: glm::quat_identity(),
+            scale: Vec3::new(1., 1., 1.),
+        };
+        vec![rest; self.bones.len()]
+    }
+
+    // Matrix of each bone in the object's space, given each bone's pose. Maps the bone's own
+    // space, with its head at the origin, into the object's
+    pub fn pose_mats(&self, poses: &[Transform]) -> Vec<glm::Mat4> {
+        let mut mats: Vec<glm::Mat4> = Vec::with_capacity(self.bones.len());
+
+        for (bone, pose) in self.bones.iter().zip(poses) {
+            let (parent_mat, parent_head) = match bone.parent.filter(|&p| p < mats.len()) {
+                Some(p) => (mats[p], self.bones[p].head),
+                None => (glm::identity(), Vec3::zeros()),
+            };
+            let local = Transform {
+                position: bone.head - parent_head + pose.position,
+                ..*pose
+            };
+            mats.push(parent_mat * local.mat());
+        }
+
+        mats
+    }
+
+    // Matrix moving each bone's vertices from where they are at rest to where the bone is posed
+    pub fn skin_mats(&self, poses: &[Transform]) -> Vec<glm::Mat4> {
+        self.pose_mats(poses)
+            .iter()
+            .zip(&self.bones)
+            .map(|(pose, bone)| pose * glm::translation(&-bone.head))
+            .collect()
+    }
+
+    // Bones and weights for each vertex at the given positions, picking the (up to) 4 bones nearest
+    // to each vertex at rest and weighting closer ones more. Weights add up to 1, or are all 0
+    // when there are no bones
+    pub fn auto_weights(&self, positions: &[Vec3]) -> Vec<([u32; 4], [f32; 4])> {
+        positions
+            .iter()
+            .map(|p| {
+                let mut nearest: Vec<(usize, f32)> = self
+                    .bones
+                    .iter()
+                    .enumerate()
+                    .map(|(i, bone)| {
+                        let d = distance_to_segment(p, &bone.head, &bone.tail);
+                        (i, 1. / (d * d).max(1e-6))
+                    })
+                    .collect();
+                nearest.sort_by(|a, b| b.1.total_cmp(&a.1));
+                nearest.truncate(4);
+
+                let total: f32 = nearest.iter().map(|(_, w)| w).sum();
+                let (mut joints, mut weights) = ([0; 4], [0.; 4]);
+                for (slot, (bone, weight)) in nearest.into_iter().enumerate() {
+                    joints[slot] = bone as u32;
+                    weights[slot] = weight / total;
+                }
+                (joints, weights)
+            })
+            .collect()
+    }
+}
+
+// Distance from p to the nearest point on the line segment from a to b
+fn distance_to_segment(p: &Vec3, a: &Vec3, b: &Vec3) -> f32 {
+    let ab = b - a;
+    let t = if ab.norm_squared() > 0. {
+        ((p - a).dot(&ab) / ab.norm_squared()).clamp(0., 1.)
+    } else {
+        0.
+    };
+    (p - (a + ab * t)).norm()
+}
+
+// Armature tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A bone up y from the origin, with a child carrying on up from its tail
+    fn arm() -> Armature {
+        let mut armature = Armature::default();
+        let up = Vec3::new(0., 1., 0.);
+        armature
+            .add_bone(Bone::new(ImString::new("Upper"), None, Vec3::zeros(), up))
+            .unwrap();
+        armature
+            .add_bone(Bone::new(ImString::new("Lower"), Some(0), up, up * 2.))
+            .unwrap();
+        armature
+    }
+
+    fn moved(mat: &glm::Mat4, p: Vec3) -> Vec3 {
+        (mat * glm::vec4(p.x, p.y, p.z, 1.)).xyz()
+    }
+
+    #[test]
+    fn armature_pose_test() {
+        let mut armature = arm();
+
+        // At rest, skinning doesn't move anything
+        let skin = armature.skin_mats(&armature.local_poses(0.));
+        assert!((moved(&skin[1], Vec3::new(0.5, 1.5, 0.)) - Vec3::new(0.5, 1.5, 0.)).norm() < 1e-5);
+
+        // Turning the upper bone a quarter turn around z swings the lower one along with it
+        armature.bones[0].rotation = Track::new(Vec3::new(0., 0., std::f32::consts::FRAC_PI_2));
+        let poses = armature.local_poses(0.);
+        let pose = armature.pose_mats(&poses);
+        let skin = armature.skin_mats(&poses);
+        assert!((moved(&pose[1], Vec3::zeros()) - Vec3::new(-1., 0., 0.)).norm() < 1e-5);
+        assert!((moved(&skin[1], Vec3::new(0., 2., 0.)) - Vec3::new(-2., 0., 0.)).norm() < 1e-5);
+
+        // Parents must exist, and there is a limit on bones
+        let orphan = Bone::new(
+            ImString::new("Orphan"),
+            Some(5),
+            Vec3::zeros(),
+            Vec3::zeros(),
+        );
+        assert!(armature.add_bone(orphan).is_err());
+        for _ in 2..MAX_BONES {
+            let bone = Bone::new(ImString::new("Bone"), None, Vec3::zeros(), Vec3::zeros());
+            armature.add_bone(bone).unwrap();
+        }
+        let bone = Bone::new(ImString::new("Bone"), None, Vec3::zeros(), Vec3::zeros());
+        assert!(armature.add_bone(bone).is_err());
+    }
+
+    #[test]
+    fn armature_weights_test() {
+        let armature = arm();
+        let weights = armature.auto_weights(&[Vec3::new(0.1, 0.2, 0.), Vec3::new(0., 1., 0.)]);
+
+        // Mostly the nearest bone, adding up to 1
+        let (joints, w) = weights[0];
+        assert_eq!(joints[0], 0);
+        assert!(w[0] > 0.9);
+        assert!((w.iter().sum::<f32>() - 1.).abs() < 1e-5);
+
+        // Evenly split where the bones meet
+        let (_, w) = weights[1];
+        assert!((w[0] - 0.5).abs() < 1e-5 && (w[1] - 0.5).abs() < 1e-5);
+
+        // Nothing to follow without bones
+        let none = Armature::default().auto_weights(&[Vec3::zeros()]);
+        assert_eq!(none[0], ([0; 4], [0.; 4]));
+    }
+}
@@ -20,6 +20,9 @@ pub struct Camera {
     // Aspect ratio, used for calculating projection matrix, window's width/height
     aspect_ratio: f32,
 
+    // Vertical field of view in degrees, used for calculating projection matrix
+    fov: f32,
+
     // Position to look at, just in front of the camera
     // calculated from pitch and yaw
     front: glm::Vec3,
@@ -45,6 +48,7 @@ impl Camera {
             pos: glm::vec3(2., 0., -1.),
             yaw: 140.,
             aspect_ratio: 1.,
+            fov: 65.,
             update_view_mat: true,
             update_proj_mat: true,
             ..Camera::default()
@@ -73,10 +77,10 @@ impl Camera {
 
         if self.update_proj_mat {
             self.projection_matrix = glm::perspective(
-                self.aspect_ratio,   // Aspect ratio
-                65_f32.to_radians(), // Y axis fov
-                0.1,                 // Z near
-                100.,                // Z far
+                self.aspect_ratio,     // Aspect ratio
+                self.fov.to_radians(), // Y axis fov
+                0.1,                   // Z near
+                100.,                  // Z far
             );
         }
 
@@ -95,6 +99,14 @@ impl Camera {
         self.aspect_ratio = n;
     }
 
+    // Set the vertical field of view, in degrees
+    pub fn set_fov(&mut self, fov: f32) {
+        if fov != self.fov {
+            self.update_proj_mat = true;
+            self.fov = fov;
+        }
+    }
+
     // Move the camera the given amount left/right
     pub fn move_right(&mut self, n: f32) {
         self.update_view_mat = true;
@@ -0,0 +1,628 @@
+/*
+This file contains the Constraint struct, which changes an object's world matrix after everything
+else animating it (keyframes, modifiers, layers, drivers and strips) and after its parent, e.g. to
+aim a camera at an object or move something along a path. An object has a stack of constraints,
+each working on the result of the ones before it, and faded in and out by its keyable influence.
+Constraints work in world space. Constraints reading other objects use their world matrices with
+their own constraints applied, so constraints can be chained across objects as long as they don't
+end up depending on themselves. Objects in such a loop are left out of the constraints reading
+them, rather than being evaluated forever.
+
+TODO:
+    * Curve objects to follow, rather than points stored in the constraint
+    * Even speed along paths, rather than even speed between each pair of points
+*/
+use crate::animation::{KeyframeV3, Track};
+use crate::nla::Transform;
+use crate::rotation::{euler_to_quat, quat_to_euler, slerp};
+use glm::{Quat, Vec3};
+use nalgebra_glm as glm;
+use std::cell::RefCell;
+
+#[derive(Clone)]
+pub struct Constraint {
+    pub kind: ConstraintKind,
+
+    // How much of the constraint's effect is used, from 0 to 1
+    pub influence: Track<f32>,
+
+    pub muted: bool,
+}
+
+// What a constraint does. Targets are indices in Project::objs
+//   TrackTo: turns the object so that its track axis points at the target, or at point if there
+//            is no target, with its up axis as close to world up (+y) as it can be
+//   Copy: copies the target's location, rotation (as Euler angles) or scale on the masked axes.
+//         With offset the target's value is added to the object's own (multiplied, for scale)
+//   Limit: keeps the object's location, rotation (as Euler angles) or scale within min and max on
+//          the axes where they are used
+//   FollowPath: moves the object along a smooth path through points, from the first at offset 0
+//               to the last at offset 1. With follow, the object also turns so its forward axis
+//               points along the path
+#[derive(Clone)]
+pub enum ConstraintKind {
+    TrackTo {
+        target: Option<usize>,
+        point: KeyframeV3,
+        track: Axis,
+        up: Axis,
+    },
+    Copy {
+        target: Option<usize>,
+        channel: TransformChannel,
+        axes: [bool; 3],
+        offset: bool,
+    },
+    Limit {
+        channel: TransformChannel,
+        min: KeyframeV3,
+        max: KeyframeV3,
+        use_min: [bool; 3],
+        use_max: [bool; 3],
+    },
+    FollowPath {
+        points: Vec<Vec3>,
+        offset: Track<f32>,
+        follow: bool,
+        forward: Axis,
+        up: Axis,
+    },
+}
+
+// The part of a transform copied or limited by a constraint
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransformChannel {
+    Location,
+    Rotation,
+    Scale,
+}
+
+// An object's local axes, used to say which way it faces
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+    NegX,
+    NegY,
+    NegZ,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 6] = [
+        Axis::X,
+        Axis::Y,
+        Axis::Z,
+        Axis::NegX,
+        Axis::NegY,
+        Axis::NegZ,
+    ];
+
+    pub fn vector(self) -> Vec3 {
+        match self {
+            Axis::X => Vec3::new(1., 0., 0.),
+            Axis::Y => Vec3::new(0., 1., 0.),
+            Axis::Z => Vec3::new(0., 0., 1.),
+            Axis::NegX => Vec3::new(-1., 0., 0.),
+            Axis::NegY => Vec3::new(0., -1., 0.),
+            Axis::NegZ => Vec3::new(0., 0., -1.),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Axis::X => "X",
+            Axis::Y => "Y",
+            Axis::Z => "Z",
+            Axis::NegX => "-X",
+            Axis::NegY => "-Y",
+            Axis::NegZ => "-Z",
+        }
+    }
+}
+
+impl Constraint {
+    pub fn new(kind: ConstraintKind) -> Constraint {
+        Constraint {
+            kind,
+            influence: Track::new(1.),
+            muted: false,
+        }
+    }
+
+    // Whether anything about the constraint is keyed, not counting its target moving
+    pub fn is_animated(&self) -> bool {
+        let keyed = match &self.kind {
+            ConstraintKind::TrackTo { point, .. } => point.is_keyed(),
+            ConstraintKind::Copy { .. } => false,
+            ConstraintKind::Limit { min, max, .. } => min.is_keyed() || max.is_keyed(),
+            ConstraintKind::FollowPath { offset, .. } => offset.is_keyed(),
+        };
+        keyed || self.influence.is_keyed()
+    }
+
+    // Object the constraint reads, if any
+    pub fn target(&self) -> Option<usize> {
+        match &self.kind {
+            ConstraintKind::TrackTo { target, .. } | ConstraintKind::Copy { target, .. } => *target,
+            ConstraintKind::Limit { .. } | ConstraintKind::FollowPath { .. } => None,
+        }
+    }
+}
+
+impl ConstraintKind {
+    // Tracking with -z, the way cameras face, and +y up
+    pub fn track_to() -> ConstraintKind {
+        ConstraintKind::TrackTo {
+            target: None,
+            point: Track::new(Vec3::zeros()),
+            track: Axis::NegZ,
+            up: Axis::Y,
+        }
+    }
+
+    pub fn copy(channel: TransformChannel) -> ConstraintKind {
+        ConstraintKind::Copy {
+            target: None,
+            channel,
+            axes: [true; 3],
+            offset: false,
+        }
+    }
+
+    // Limits starting out at the given value on every side, with neither side used yet
+    pub fn limit(channel: TransformChannel, value: Vec3) -> ConstraintKind {
+        ConstraintKind::Limit {
+            channel,
+            min: Track::new(value),
+            max: Track::new(value),
+            use_min: [false; 3],
+            use_max: [false; 3],
+        }
+    }
+
+    // A path starting at the given point
+    pub fn follow_path(start: Vec3) -> ConstraintKind {
+        ConstraintKind::FollowPath {
+            points: vec![start],
+            offset: Track::new(0.),
+            follow: true,
+            forward: Axis::NegZ,
+            up: Axis::Y,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConstraintKind::TrackTo { .. } => "Track to",
+            ConstraintKind::Copy { channel, .. } => match channel {
+                TransformChannel::Location => "Copy location",
+                TransformChannel::Rotation => "Copy rotation",
+                TransformChannel::Scale => "Copy scale",
+            },
+            ConstraintKind::Limit { channel, .. } => match channel {
+                TransformChannel::Location => "Limit location",
+                TransformChannel::Rotation => "Limit rotation",
+                TransformChannel::Scale => "Limit scale",
+            },
+            ConstraintKind::FollowPath { .. } => "Follow path",
+        }
+    }
+}
+
+// Applies a stack of constraints to a world matrix, in order. targets gives the world matrix of
+// another object, or None if it can't be used
+pub fn apply(
+    constraints: &[Constraint],
+    world: glm::Mat4,
+    targets: &dyn Fn(usize) -> Option<glm::Mat4>,
+    frame: f32,
+) -> glm::Mat4 {
+    let mut out = decompose(&world);
+    let mut changed = false;
+
+    for constraint in constraints.iter().filter(|c| !c.muted) {
+        let influence = constraint.influence.sample(frame).clamp(0., 1.);
+        if influence <= 0. {
+            continue;
+        }
+        let target = match constraint.target() {
+            Some(i) => match targets(i) {
+                Some(mat) => Some(decompose(&mat)),
+                None => continue,
+            },
+            None => None,
+        };
+
+        if let Some(result) = solve(&constraint.kind, &out, target.as_ref(), frame) {
+            out = Transform {
+                position: glm::lerp(&out.position, &result.position, influence),
+                rotation: slerp(&out.rotation, &result.rotation, influence),
+                scale: glm::lerp(&out.scale, &result.scale, influence),
+            };
+            changed = true;
+        }
+    }
+
+    // Leave unconstrained matrices as they are, shear included
+    if changed {
+        out.mat()
+    } else {
+        world
+    }
+}
+
+// What one constraint at full influence turns a transform into. None if it has nothing to do,
+// e.g. copying from no object
+fn solve(
+    kind: &ConstraintKind,
+    current: &Transform,
+    target: Option<&Transform>,
+    frame: f32,
+) -> Option<Transform> {
+    let mut out = *current;
+
+    match kind {
+        ConstraintKind::TrackTo {
+            point, track, up, ..
+        } => {
+            let aim_at = target.map_or_else(|| point.sample(frame), |t| t.position);
+            out.rotation = aim(*track, *up, &(aim_at - current.position))?;
+        }
+
+        ConstraintKind::Copy {
+            channel,
+            axes,
+            offset,
+            ..
+        } => {
+            let target = target?;
+            let own = channel_value(current, *channel);
+            let copied = channel_value(target, *channel);
+            let mut value = own;
+            for axis in 0..3 {
+                if axes[axis] {
+                    value[axis] = match (channel, offset) {
+                        (_, false) => copied[axis],
+                        (TransformChannel::Scale, true) => own[axis] * copied[axis],
+                        (_, true) => own[axis] + copied[axis],
+                    };
+                }
+            }
+            set_channel_value(&mut out, *channel, value);
+        }
+
+        ConstraintKind::Limit {
+            channel,
+            min,
+            max,
+            use_min,
+            use_max,
+        } => {
+            let (min, max) = (min.sample(frame), max.sample(frame));
+            let mut value = channel_value(current, *channel);
+            for axis in 0..3 {
+                if use_min[axis] {
+                    value[axis] = value[axis].max(min[axis]);
+                }
+                if use_max[axis] {
+                    value[axis] = value[axis].min(max[axis]);
+                }
+            }
+            set_channel_value(&mut out, *channel, value);
+        }
+
+        ConstraintKind::FollowPath {
+            points,
+            offset,
+            follow,
+            forward,
+            up,
+        } => {
+            let (position, tangent) = catmull_rom(points, offset.sample(frame))?;
+            out.position = position;
+            if *follow {
+                if let Some(rotation) = aim(*forward, *up, &tangent) {
+                    out.rotation = rotation;
+                }
+            }
+        }
+    }
+
+    Some(out)
+}
+
+// Location, Euler rotation or scale of a transform
+fn channel_value(transform: &Transform, channel: TransformChannel) -> Vec3 {
+    match channel {
+        TransformChannel::Location => transform.position,
+        TransformChannel::Rotation => quat_to_euler(&transform.rotation, &Vec3::zeros()),
+        TransformChannel::Scale => transform.scale,
+    }
+}
+
+fn set_channel_value(transform: &mut Transform, channel: TransformChannel, value: Vec3) {
+    match channel {
+        TransformChannel::Location => transform.position = value,
+        TransformChannel::Rotation => transform.rotation = euler_to_quat(&value),
+        TransformChannel::Scale => transform.scale = value,
+    }
+}
+
+// Rotation turning the local track axis to point along direction, with the local up axis as close
+// to world up as it can be. None if direction is too short to point along
+fn aim(track: Axis, up: Axis, direction: &Vec3) -> Option<Quat> {
+    if direction.norm() < 1e-6 {
+        return None;
+    }
+    let forward = direction.normalize();
+
+    // Falls back on world z when looking straight up or down, and on another local axis when up
+    // is along the track axis
+    let world_up = if forward.y.abs() > 0.9999 {
+        Vec3::new(0., 0., 1.)
+    } else {
+        Vec3::new(0., 1., 0.)
+    };
+    let (t, mut u) = (track.vector(), up.vector());
+    if t.cross(&u).norm() < 0.5 {
+        u = Vec3::new(t.z.abs(), t.x.abs(), t.y.abs());
+    }
+
+    // Maps the local axes t, u and t x u onto forward, the world up flattened onto the plane
+    // facing forward, and forward x that
+    let world_up = (world_up - forward * world_up.dot(&forward)).normalize();
+    let local = glm::Mat3::from_columns(&[t, u, t.cross(&u)]);
+    let world = glm::Mat3::from_columns(&[forward, world_up, forward.cross(&world_up)]);
+    Some(glm::mat3_to_quat(&(world * local.transpose())))
+}
+
+// Position and direction at a point along a Catmull-Rom spline through points, from the first
+// point at t = 0 to the last at t = 1. None if there are no points, and no direction with just one
+fn catmull_rom(points: &[Vec3], t: f32) -> Option<(Vec3, Vec3)> {
+    match points.len() {
+        0 => return None,
+        1 => return Some((points[0], Vec3::zeros())),
+        _ => (),
+    }
+
+    let segments = points.len() - 1;
+    let t = t.clamp(0., 1.) * segments as f32;
+    let i = (t.floor() as usize).min(segments - 1);
+    let u = t - i as f32;
+
+    // The ends are repeated to give the first and last segments their missing neighbour
+    let p0 = points[i.saturating_sub(1)];
+    let (p1, p2) = (points[i], points[i + 1]);
+    let p3 = points[(i + 2).min(segments)];
+
+    let position = ((p1 * 2.)
+        + (p2 - p0) * u
+        + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * u * u
+        + (p1 * 3. - p0 - p2 * 3. + p3) * u * u * u)
+        * 0.5;
+    let direction = ((p2 - p0)
+        + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * 2. * u
+        + (p1 * 3. - p0 - p2 * 3. + p3) * 3. * u * u)
+        * 0.5;
+    Some((position, direction))
+}
+
+// Splits a matrix without shear into position, rotation and scale
+pub fn decompose(mat: &glm::Mat4) -> Transform {
+    let column = |i: usize| -> Vec3 { mat.column(i).xyz() };
+    let scale = Vec3::new(column(0).norm(), column(1).norm(), column(2).norm());
+    let unit = |i: usize| column(i) / scale[i].max(f32::EPSILON);
+
+    Transform {
+        position: column(3),
+        rotation: glm::mat3_to_quat(&glm::Mat3::from_columns(&[unit(0), unit(1), unit(2)])),
+        scale,
+    }
+}
+
+// Works out the world matrices of a project's objects with their constraints applied. Each
+// object's matrix is only worked out once, no matter how many objects read it
+pub struct Solver<'a> {
+    parents: &'a [Option<usize>],
+
+    // Each object's matrix relative to its parent, and its constraints
+    relative: &'a [glm::Mat4],
+    constraints: Vec<&'a [Constraint]>,
+    frame: f32,
+
+    // Matrices worked out so far, and which objects are currently being worked out
+    world: RefCell<Vec<Option<glm::Mat4>>>,
+    working: RefCell<Vec<bool>>,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(
+        parents: &'a [Option<usize>],
+        relative: &'a [glm::Mat4],
+        constraints: Vec<&'a [Constraint]>,
+        frame: f32,
+    ) -> Solver<'a> {
+        Solver {
+            parents,
+            relative,
+            constraints,
+            frame,
+            world: RefCell::new(vec![None; relative.len()]),
+            working: RefCell::new(vec![false; relative.len()]),
+        }
+    }
+
+    // World matrix of every object, in order
+    pub fn world_mats(&self) -> Vec<glm::Mat4> {
+        (0..self.relative.len())
+            .map(|i| self.world(i).unwrap_or_else(glm::identity))
+            .collect()
+    }
+
+    // World matrix of the object at index, or None if it is missing or being worked out, which
+    // means whatever asked for it is part of a loop
+    fn world(&self, index: usize) -> Option<glm::Mat4> {
+        if let Some(mat) = self.world.borrow().get(index).copied()? {
+            return Some(mat);
+        }
+        if self.working.borrow()[index] {
+            return None;
+        }
+
+        self.working.borrow_mut()[index] = true;
+        let parent = self.parents[index]
+            .and_then(|p| self.world(p))
+            .unwrap_or_else(glm::identity);
+        let mat = apply(
+            self.constraints[index],
+            parent * self.relative[index],
+            &|i| self.world(i),
+            self.frame,
+        );
+        self.working.borrow_mut()[index] = false;
+
+        self.world.borrow_mut()[index] = Some(mat);
+        Some(mat)
+    }
+}
+
+// Constraint tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Vec3, b: &Vec3) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    fn at(position: Vec3) -> glm::Mat4 {
+        glm::translate(&glm::identity(), &position)
+    }
+
+    #[test]
+    fn constraint_track_to_test() {
+        let mut track = Constraint::new(ConstraintKind::track_to());
+        if let ConstraintKind::TrackTo { point, .. } = &mut track.kind {
+            *point = Track::new(Vec3::new(5., 0., 0.));
+        }
+
+        // -z turns to face +x, keeping y up
+        let mat = apply(&[track.clone()], glm::identity(), &|_| None, 0.);
+        let facing = (mat * glm::vec4(0., 0., -1., 0.)).xyz();
+        let up = (mat * glm::vec4(0., 1., 0., 0.)).xyz();
+        assert!(close(&facing, &Vec3::new(1., 0., 0.)));
+        assert!(close(&up, &Vec3::new(0., 1., 0.)));
+
+        // Targets are used over the point, and are left out when they can't be used
+        if let ConstraintKind::TrackTo { target, .. } = &mut track.kind {
+            *target = Some(0);
+        }
+        let targets = |_| Some(at(Vec3::new(0., 0., 5.)));
+        let mat = apply(&[track.clone()], glm::identity(), &targets, 0.);
+        let facing = (mat * glm::vec4(0., 0., -1., 0.)).xyz();
+        assert!(close(&facing, &Vec3::new(0., 0., 1.)));
+        let identity: glm::Mat4 = glm::identity();
+        assert_eq!(apply(&[track], identity, &|_| None, 0.), identity);
+    }
+
+    #[test]
+    fn constraint_copy_limit_test() {
+        let targets = |_| Some(at(Vec3::new(1., 2., 3.)));
+        let own = at(Vec3::new(10., 10., 10.));
+        let position =
+            |constraints: &[Constraint]| decompose(&apply(constraints, own, &targets, 0.)).position;
+
+        let mut copy = Constraint::new(ConstraintKind::Copy {
+            target: Some(0),
+            channel: TransformChannel::Location,
+            axes: [true, false, true],
+            offset: false,
+        });
+        assert!(close(&position(&[copy.clone()]), &Vec3::new(1., 10., 3.)));
+        if let ConstraintKind::Copy { offset, .. } = &mut copy.kind {
+            *offset = true;
+        }
+        assert!(close(&position(&[copy.clone()]), &Vec3::new(11., 10., 13.)));
+
+        // Limits work on the result of the copy, faded by influence
+        let mut limit = Constraint::new(ConstraintKind::limit(
+            TransformChannel::Location,
+            Vec3::new(12., 12., 12.),
+        ));
+        if let ConstraintKind::Limit { use_min, .. } = &mut limit.kind {
+            *use_min = [true, false, false];
+        }
+        assert!(close(
+            &position(&[copy.clone(), limit.clone()]),
+            &Vec3::new(12., 10., 13.)
+        ));
+        limit.influence = Track::new(0.5);
+        assert!(close(&position(&[copy, limit]), &Vec3::new(11.5, 10., 13.)));
+    }
+
+    #[test]
+    fn constraint_follow_path_test() {
+        let points = [
+            Vec3::new(0., 0., 0.),
+            Vec3::new(10., 0., 0.),
+            Vec3::new(10., 0., 10.),
+        ];
+        let (start, direction) = catmull_rom(&points, 0.).unwrap();
+        assert!(close(&start, &points[0]));
+        assert!(direction.x > 0.);
+        assert!(close(&catmull_rom(&points, 0.5).unwrap().0, &points[1]));
+        assert!(close(&catmull_rom(&points, 1.).unwrap().0, &points[2]));
+        assert!(catmull_rom(&[], 0.5).is_none());
+
+        // Faces along the path
+        let mut follow = ConstraintKind::follow_path(points[0]);
+        if let ConstraintKind::FollowPath { points: p, .. } = &mut follow {
+            *p = points.to_vec();
+        }
+        let mat = apply(&[Constraint::new(follow)], glm::identity(), &|_| None, 0.);
+        let facing = (mat * glm::vec4(0., 0., -1., 0.)).xyz();
+        assert!(close(&facing, &Vec3::new(1., 0., 0.)));
+    }
+
+    #[test]
+    fn constraint_solver_test() {
+        // 0 copies 1's location, 2 is parented to 0, and 3 and 4 copy each other
+        let copy = |target| {
+            vec![Constraint::new(ConstraintKind::Copy {
+                target: Some(target),
+                channel: TransformChannel::Location,
+                axes: [true; 3],
+                offset: false,
+            })]
+        };
+        let constraints = [copy(1), vec![], vec![], copy(4), copy(3)];
+        let parents = [None, None, Some(0), None, None];
+        let relative = [
+            at(Vec3::new(1., 0., 0.)),
+            at(Vec3::new(0., 5., 0.)),
+            at(Vec3::new(0., 0., 1.)),
+            at(Vec3::new(3., 0., 0.)),
+            at(Vec3::new(4., 0., 0.)),
+        ];
+        let solver = Solver::new(
+            &parents,
+            &relative,
+            constraints.iter().map(Vec::as_slice).collect(),
+            0.,
+        );
+        let positions: Vec<Vec3> = solver
+            .world_mats()
+            .iter()
+            .map(|m| decompose(m).position)
+            .collect();
+
+        assert!(close(&positions[0], &Vec3::new(0., 5., 0.)));
+        assert!(close(&positions[2], &Vec3::new(0., 5., 1.)));
+
+        // The loop is broken where it is first found, without hanging
+        assert!(close(&positions[3], &Vec3::new(4., 0., 0.)));
+        assert!(close(&positions[4], &Vec3::new(4., 0., 0.)));
+    }
+}
@@ -0,0 +1,240 @@
+/*
+This file contains the Driver struct, which sets one of an object's attributes from an expression
+(see expression.rs) in place of its keyframes, and Drivers, which evaluates every driver of a
+project at one point in time.
+Drivers can read attributes of other objects, which may be driven themselves. Drivers are checked
+when they are set so that none of them ends up depending on itself. If a cycle comes about anyway,
+e.g. by renaming an object, the attribute read in a loop falls back to its animated value.
+Driven values replace the animated value after modifiers and layers, before strips are blended on
+top.
+
+TODO:
+*/
+use crate::expression::{Context, Expr};
+use crate::project::Project;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+};
+
+// A single number on an object that can be driven or read by drivers. Axes are 0, 1 and 2 for x, y
+// and z. Rotations are Euler angles in radians
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Attribute {
+    Position(usize),
+    Rotation(usize),
+    Scale(usize),
+    Property(String),
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let axis = |a: &usize| ["x", "y", "z"][*a];
+        match self {
+            Attribute::Position(a) => write!(f, "position.{}", axis(a)),
+            Attribute::Rotation(a) => write!(f, "rotation.{}", axis(a)),
+            Attribute::Scale(a) => write!(f, "scale.{}", axis(a)),
+            Attribute::Property(name) => write!(f, "prop(\"{}\")", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Driver {
+    pub target: Attribute,
+
+    // The expression as it was entered, and parsed
+    text: String,
+    expr: Expr,
+}
+
+impl Driver {
+    // Parses the expression, returning an error describing what is wrong with it if it can't be
+    pub fn new(target: Attribute, text: &str) -> Result<Driver, String> {
+        Ok(Driver {
+            target,
+            text: text.to_string(),
+            expr: Expr::parse(text)?,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+// Checks that a driver set on the object at index only refers to objects that exist, and doesn't
+// end up depending on itself through the project's other drivers
+pub fn check(project: &Project, index: usize, driver: &Driver) -> Result<(), String> {
+    let target = (index, driver.target.clone());
+    let mut start = Vec::new();
+    for (name, attribute) in driver.expr.references() {
+        match project.obj_named(name) {
+            Some(i) => start.push((i, attribute.clone())),
+            None => return Err(format!("There is no object named \"{}\"", name)),
+        }
+    }
+
+    // What each driven attribute reads, using the new driver in place of any it replaces
+    let reads = |(i, attribute): &(usize, Attribute)| -> Vec<(usize, Attribute)> {
+        let expr = if (*i, attribute) == (index, &driver.target) {
+            &driver.expr
+        } else {
+            match project.objs[*i].driver(attribute) {
+                Some(d) => &d.expr,
+                None => return Vec::new(),
+            }
+        };
+
+        expr.references()
+            .into_iter()
+            .filter_map(|(name, a)| Some((project.obj_named(name)?, a.clone())))
+            .collect()
+    };
+
+    if depends_on(start, &target, reads) {
+        Err(format!(
+            "{} of {} would end up depending on itself",
+            driver.target, project.objs[index].name
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Whether target can be reached from any of the start nodes by following edges
+pub fn depends_on<N, F>(start: Vec<N>, target: &N, edges: F) -> bool
+where
+    N: Clone + Eq + Hash,
+    F: Fn(&N) -> Vec<N>,
+{
+    let mut seen = HashSet::new();
+    let mut stack = start;
+
+    while let Some(node) = stack.pop() {
+        if &node == target {
+            return true;
+        }
+        if seen.insert(node.clone()) {
+            stack.extend(edges(&node));
+        }
+    }
+    false
+}
+
+// Evaluates the drivers of a project at one point in time, which may be between frames. Each attribute is only worked out once, no
+// matter how many drivers read it
+pub struct Drivers<'a> {
+    project: &'a Project,
+    frame: f32,
+
+    // Values worked out so far, and the attributes currently being worked out
+    values: RefCell<HashMap<(usize, Attribute), f32>>,
+    working: RefCell<Vec<(usize, Attribute)>>,
+}
+
+impl<'a> Drivers<'a> {
+    pub fn new(project: &'a Project, frame: f32) -> Drivers<'a> {
+        Drivers {
+            project,
+            frame,
+            values: RefCell::new(HashMap::new()),
+            working: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Value of an attribute of the object at index, driven or not. Missing properties are 0
+    pub fn value(&self, index: usize, attribute: &Attribute) -> f32 {
+        let key = (index, attribute.clone());
+        if let Some(value) = self.values.borrow().get(&key) {
+            return *value;
+        }
+
+        let obj = &self.project.objs[index];
+        let animated = obj.attribute_at(attribute, self.frame).unwrap_or(0.);
+        let value = match obj.driver(attribute) {
+            // Attributes read while they are being worked out are part of a cycle
+            Some(driver) if !self.working.borrow().contains(&key) => {
+                self.working.borrow_mut().push(key.clone());
+                let value = driver.expr.evaluate(self);
+                self.working.borrow_mut().pop();
+
+                // Dividing by zero and the like leaves the attribute alone
+                if value.is_finite() {
+                    value
+                } else {
+                    animated
+                }
+            }
+            _ => animated,
+        };
+
+        self.values.borrow_mut().insert(key, value);
+        value
+    }
+
+    // Every driven attribute of the object at index, with its value
+    pub fn driven(&self, index: usize) -> Vec<(Attribute, f32)> {
+        self.project.objs[index]
+            .drivers
+            .iter()
+            .map(|d| (d.target.clone(), self.value(index, &d.target)))
+            .collect()
+    }
+}
+
+impl Context for Drivers<'_> {
+    fn frame(&self) -> f32 {
+        self.frame
+    }
+
+    fn time(&self) -> f32 {
+        self.frame / self.project.fps
+    }
+
+    fn reference(&self, object: &str, attribute: &Attribute) -> f32 {
+        match self.project.obj_named(object) {
+            Some(i) => self.value(i, attribute),
+            None => 0.,
+        }
+    }
+}
+
+// Driver tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn driver_cycle_test() {
+        // a -> b -> c, and d -> d
+        let edges = |n: &char| match n {
+            'a' => vec!['b'],
+            'b' => vec!['c'],
+            'd' => vec!['d'],
+            _ => vec![],
+        };
+
+        assert!(depends_on(vec!['a'], &'c', edges));
+        assert!(!depends_on(vec!['c'], &'a', edges));
+        assert!(depends_on(vec!['d'], &'d', edges));
+        assert!(!depends_on(vec!['d'], &'a', edges));
+        assert!(depends_on(vec!['x', 'b'], &'c', edges));
+    }
+
+    #[test]
+    fn driver_parse_test() {
+        let driver = Driver::new(Attribute::Rotation(2), "obj(\"Gear1\").rotation.z * -2");
+        assert_eq!(driver.unwrap().text(), "obj(\"Gear1\").rotation.z * -2");
+        assert!(Driver::new(Attribute::Position(0), "frame +").is_err());
+
+        assert_eq!(Attribute::Scale(1).to_string(), "scale.y");
+        assert_eq!(
+            Attribute::Property("Speed".to_string()).to_string(),
+            "prop(\"Speed\")"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+/*
+This file contains the Easing enum, which describes how a keyframed value travels from one keyframe
+to the next. An easing maps linear progress through a segment (0 at the left keyframe, 1 at the
+right keyframe) onto eased progress, which animation.rs then uses for tweening.
+The curves are the usual Robert Penner easing equations, see https://easings.net for pictures.
+
+TODO:
+*/
+
+use std::f32::consts::PI;
+
+// Which end(s) of the segment an easing curve is applied to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EaseMode {
+    In,
+    Out,
+    InOut,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    Quad(EaseMode),
+    Cubic(EaseMode),
+    Quart(EaseMode),
+    Sine(EaseMode),
+    Expo(EaseMode),
+    Back(EaseMode),
+    Elastic(EaseMode),
+    Bounce(EaseMode),
+}
+
+impl EaseMode {
+    pub const ALL: [EaseMode; 3] = [EaseMode::In, EaseMode::Out, EaseMode::InOut];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EaseMode::In => "In",
+            EaseMode::Out => "Out",
+            EaseMode::InOut => "In and out",
+        }
+    }
+}
+
+impl Easing {
+    // Every easing, with the curved ones applied to the given end(s)
+    pub fn all(mode: EaseMode) -> [Easing; 9] {
+        [
+            Easing::Linear,
+            Easing::Quad(mode),
+            Easing::Cubic(mode),
+            Easing::Quart(mode),
+            Easing::Sine(mode),
+            Easing::Expo(mode),
+            Easing::Back(mode),
+            Easing::Elastic(mode),
+            Easing::Bounce(mode),
+        ]
+    }
+
+    // Name of the curve, whichever end(s) it is applied to
+    pub fn name(self) -> &'static str {
+        match self {
+            Easing::Linear => "Linear",
+            Easing::Quad(_) => "Quadratic",
+            Easing::Cubic(_) => "Cubic",
+            Easing::Quart(_) => "Quartic",
+            Easing::Sine(_) => "Sine",
+            Easing::Expo(_) => "Exponential",
+            Easing::Back(_) => "Back",
+            Easing::Elastic(_) => "Elastic",
+            Easing::Bounce(_) => "Bounce",
+        }
+    }
+
+    // End(s) the curve is applied to. Linear has none
+    pub fn mode(self) -> Option<EaseMode> {
+        match self {
+            Easing::Linear => None,
+            Easing::Quad(m)
+            | Easing::Cubic(m)
+            | Easing::Quart(m)
+            | Easing::Sine(m)
+            | Easing::Expo(m)
+            | Easing::Back(m)
+            | Easing::Elastic(m)
+            | Easing::Bounce(m) => Some(m),
+        }
+    }
+
+    // The same curve applied to other end(s). Linear stays linear
+    pub fn with_mode(self, mode: EaseMode) -> Easing {
+        match self {
+            Easing::Linear => Easing::Linear,
+            Easing::Quad(_) => Easing::Quad(mode),
+            Easing::Cubic(_) => Easing::Cubic(mode),
+            Easing::Quart(_) => Easing::Quart(mode),
+            Easing::Sine(_) => Easing::Sine(mode),
+            Easing::Expo(_) => Easing::Expo(mode),
+            Easing::Back(_) => Easing::Back(mode),
+            Easing::Elastic(_) => Easing::Elastic(mode),
+            Easing::Bounce(_) => Easing::Bounce(mode),
+        }
+    }
+
+    // Maps progress t (0 to 1) through a segment to eased progress. The result is 0 at t = 0 and 1
+    // at t = 1, but may leave that range in between (Back and Elastic overshoot on purpose)
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+
+        let (ease_in, mode): (fn(f32) -> f32, EaseMode) = match self {
+            Easing::Linear => return t,
+            Easing::Quad(m) => (quad_in, m),
+            Easing::Cubic(m) => (cubic_in, m),
+            Easing::Quart(m) => (quart_in, m),
+            Easing::Sine(m) => (sine_in, m),
+            Easing::Expo(m) => (expo_in, m),
+            Easing::Back(m) => (back_in, m),
+            Easing::Elastic(m) => (elastic_in, m),
+            Easing::Bounce(m) => (bounce_in, m),
+        };
+
+        // Every curve is defined as its ease-in version. Ease-out is the ease-in curve rotated 180
+        // degrees, and ease-in-out is ease-in for the first half and ease-out for the second
+        match mode {
+            EaseMode::In => ease_in(t),
+            EaseMode::Out => 1. - ease_in(1. - t),
+            EaseMode::InOut if t < 0.5 => ease_in(2. * t) / 2.,
+            EaseMode::InOut => 1. - ease_in(2. - 2. * t) / 2.,
+        }
+    }
+}
+
+// Ease-in curves
+// =================================================================================================
+fn quad_in(t: f32) -> f32 {
+    t * t
+}
+
+fn cubic_in(t: f32) -> f32 {
+    t * t * t
+}
+
+fn quart_in(t: f32) -> f32 {
+    t * t * t * t
+}
+
+fn sine_in(t: f32) -> f32 {
+    1. - (t * PI / 2.).cos()
+}
+
+fn expo_in(t: f32) -> f32 {
+    // 2^(10t - 10) is not exactly 0 at t = 0, so that end is pinned
+    if t <= 0. {
+        0.
+    } else {
+        2_f32.powf(10. * t - 10.)
+    }
+}
+
+fn back_in(t: f32) -> f32 {
+    // Amount of overshoot, 1.70158 gives a 10% dip below 0
+    const C1: f32 = 1.70158;
+    const C3: f32 = C1 + 1.;
+    C3 * t * t * t - C1 * t * t
+}
+
+fn elastic_in(t: f32) -> f32 {
+    const C4: f32 = 2. * PI / 3.;
+    if t <= 0. {
+        0.
+    } else if t >= 1. {
+        1.
+    } else {
+        -(2_f32.powf(10. * t - 10.)) * ((t * 10. - 10.75) * C4).sin()
+    }
+}
+
+fn bounce_in(t: f32) -> f32 {
+    1. - bounce_out(1. - t)
+}
+
+// Bounce is naturally described as an ease-out curve: a series of shrinking parabolas
+fn bounce_out(t: f32) -> f32 {
+    const N1: f32 = 7.5625;
+    const D1: f32 = 2.75;
+
+    if t < 1. / D1 {
+        N1 * t * t
+    } else if t < 2. / D1 {
+        let t = t - 1.5 / D1;
+        N1 * t * t + 0.75
+    } else if t < 2.5 / D1 {
+        let t = t - 2.25 / D1;
+        N1 * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D1;
+        N1 * t * t + 0.984375
+    }
+}
+
+// Easing tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feq(n1: f32, n2: f32) -> bool {
+        n1.max(n2) - n1.min(n2) < 0.00005
+    }
+
+    fn all_easings() -> Vec<Easing> {
+        let mut easings = vec![Easing::Linear];
+        for &m in &[EaseMode::In, EaseMode::Out, EaseMode::InOut] {
+            easings.extend(&[
+                Easing::Quad(m),
+                Easing::Cubic(m),
+                Easing::Quart(m),
+                Easing::Sine(m),
+                Easing::Expo(m),
+                Easing::Back(m),
+                Easing::Elastic(m),
+                Easing::Bounce(m),
+            ]);
+        }
+        easings
+    }
+
+    #[test]
+    fn easing_mode_test() {
+        for e in all_easings() {
+            let mode = e.mode().unwrap_or(EaseMode::In);
+            assert_eq!(e.with_mode(mode), e);
+            assert!(Easing::all(mode).contains(&e));
+        }
+        assert_eq!(Easing::Linear.with_mode(EaseMode::Out), Easing::Linear);
+        assert_eq!(
+            Easing::Back(EaseMode::In).with_mode(EaseMode::InOut),
+            Easing::Back(EaseMode::InOut)
+        );
+    }
+
+    #[test]
+    fn easing_endpoints_test() {
+        for e in all_easings() {
+            assert!(feq(e.apply(0.), 0.), "{:?} at 0", e);
+            assert!(feq(e.apply(1.), 1.), "{:?} at 1", e);
+        }
+    }
+
+    #[test]
+    fn easing_shape_test() {
+        // In-out curves are symmetric, so they pass through the middle
+        assert!(feq(Easing::Quad(EaseMode::InOut).apply(0.5), 0.5));
+        assert!(feq(Easing::Sine(EaseMode::InOut).apply(0.5), 0.5));
+
+        assert!(feq(Easing::Quad(EaseMode::In).apply(0.5), 0.25));
+        assert!(feq(Easing::Quad(EaseMode::Out).apply(0.5), 0.75));
+        assert!(feq(Easing::Cubic(EaseMode::InOut).apply(0.25), 0.0625));
+
+        // Back dips below 0 before heading to 1
+        assert!(Easing::Back(EaseMode::In).apply(0.2) < 0.);
+    }
+}
@@ -0,0 +1,540 @@
+/*
+This file contains the Expr enum, a small expression language used by drivers (see driver.rs) to
+work out a channel's value from the current frame and other objects' channels, e.g.
+    obj("Gear1").rotation.z * -2
+    sin(time * 2) * 0.5 + 1
+Expressions are parsed once, when they are entered, and then evaluated every frame. Parsing checks
+everything that can be checked without a project, such as unknown names and the number of
+arguments to functions, so evaluating never fails.
+
+Syntax, from lowest to highest precedence:
+    a + b, a - b
+    a * b, a / b, a % b
+    -a
+    a ^ b (right associative)
+    numbers, (a), frame, time, pi, function(a, b, ...), obj("Name").attribute
+Functions are sin, cos, tan, abs, sqrt, floor, ceil, deg, rad (taking one argument), min, max (two)
+and clamp (value, min, max). Angles are in radians. Attributes are position, rotation and scale
+followed by .x, .y or .z, or the name of a property, either bare or as prop("Name").
+
+TODO:
+*/
+use crate::driver::Attribute;
+use std::f32::consts::PI;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    Frame,
+    Time,
+
+    // An attribute of the object with the given name
+    Reference(String, Attribute),
+
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+    Sqrt,
+    Floor,
+    Ceil,
+    Deg,
+    Rad,
+    Min,
+    Max,
+    Clamp,
+}
+
+// What an expression can read while being evaluated
+pub trait Context {
+    fn frame(&self) -> f32;
+
+    // Seconds since frame 0
+    fn time(&self) -> f32;
+
+    // Value of an attribute of the object with the given name
+    fn reference(&self, object: &str, attribute: &Attribute) -> f32;
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, next: 0 };
+
+        let expr = parser.sum()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((token, column)) => Err(format!("Unexpected {} at column {}", token, column)),
+        }
+    }
+
+    pub fn evaluate(&self, context: &dyn Context) -> f32 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Frame => context.frame(),
+            Expr::Time => context.time(),
+            Expr::Reference(object, attribute) => context.reference(object, attribute),
+            Expr::Negate(a) => -a.evaluate(context),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.evaluate(context), b.evaluate(context));
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Subtract => a - b,
+                    BinaryOp::Multiply => a * b,
+                    BinaryOp::Divide => a / b,
+                    BinaryOp::Remainder => a % b,
+                    BinaryOp::Power => a.powf(b),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<f32> = args.iter().map(|a| a.evaluate(context)).collect();
+                function.call(&args)
+            }
+        }
+    }
+
+    // Every object attribute the expression reads, as (object name, attribute)
+    pub fn references(&self) -> Vec<(&str, &Attribute)> {
+        let mut found = Vec::new();
+        self.find_references(&mut found);
+        found
+    }
+
+    fn find_references<'a>(&'a self, found: &mut Vec<(&'a str, &'a Attribute)>) {
+        match self {
+            Expr::Reference(object, attribute) => found.push((object, attribute)),
+            Expr::Negate(a) => a.find_references(found),
+            Expr::Binary(_, a, b) => {
+                a.find_references(found);
+                b.find_references(found);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.find_references(found);
+                }
+            }
+            Expr::Number(_) | Expr::Frame | Expr::Time => (),
+        }
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        Some(match name {
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "tan" => Function::Tan,
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "floor" => Function::Floor,
+            "ceil" => Function::Ceil,
+            "deg" => Function::Deg,
+            "rad" => Function::Rad,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "clamp" => Function::Clamp,
+            _ => return None,
+        })
+    }
+
+    fn arguments(self) -> usize {
+        match self {
+            Function::Min | Function::Max => 2,
+            Function::Clamp => 3,
+            _ => 1,
+        }
+    }
+
+    // Calls the function. There are always as many arguments as it takes
+    fn call(self, args: &[f32]) -> f32 {
+        match self {
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Tan => args[0].tan(),
+            Function::Abs => args[0].abs(),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Floor => args[0].floor(),
+            Function::Ceil => args[0].ceil(),
+            Function::Deg => args[0].to_degrees(),
+            Function::Rad => args[0].to_radians(),
+            Function::Min => args[0].min(args[1]),
+            Function::Max => args[0].max(args[1]),
+            Function::Clamp => args[0].max(args[1]).min(args[2]),
+        }
+    }
+}
+
+// Tokenizing
+// =================================================================================================
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Name(String),
+    Text(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {}", n),
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Text(text) => write!(f, "\"{}\"", text),
+            Token::Symbol(c) => write!(f, "'{}'", c),
+        }
+    }
+}
+
+// Splits text into tokens, each with the column it starts on (counting from 1)
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let number = number
+                .parse()
+                .map_err(|_| format!("Bad number {} at column {}", number, column))?;
+            tokens.push((Token::Number(number), column));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Name(chars[start..i].iter().collect()), column));
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(format!("Unclosed \" at column {}", column));
+            }
+            tokens.push((Token::Text(chars[start..i].iter().collect()), column));
+            i += 1;
+        } else if "+-*/%^(),.".contains(c) {
+            tokens.push((Token::Symbol(c), column));
+            i += 1;
+        } else {
+            return Err(format!("Unexpected '{}' at column {}", c, column));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Parsing
+// =================================================================================================
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(Token, usize)> {
+        self.tokens.get(self.next)
+    }
+
+    // Column of the next token, or just past the end of the text
+    fn column(&self) -> usize {
+        match self.peek() {
+            Some((_, column)) => *column,
+            None => self.tokens.last().map_or(1, |(_, c)| c + 1),
+        }
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(t, _)| t.clone());
+        self.next += 1;
+        token
+    }
+
+    // Moves past the next token if it is the given symbol
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek().map(|(t, _)| t) == Some(&Token::Symbol(symbol)) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at column {}", symbol, self.column()))
+        }
+    }
+
+    fn expect_name(&mut self, what: &str) -> Result<String, String> {
+        let column = self.column();
+        match self.advance() {
+            Some(Token::Name(name)) => Ok(name),
+            _ => Err(format!("Expected {} at column {}", what, column)),
+        }
+    }
+
+    fn expect_text(&mut self, what: &str) -> Result<String, String> {
+        let column = self.column();
+        match self.advance() {
+            Some(Token::Text(text)) => Ok(text),
+            _ => Err(format!("Expected {} in quotes at column {}", what, column)),
+        }
+    }
+
+    // a + b, a - b
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Subtract
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    // a * b, a / b, a % b
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Multiply
+            } else if self.eat('/') {
+                BinaryOp::Divide
+            } else if self.eat('%') {
+                BinaryOp::Remainder
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    // -a
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            Ok(Expr::Negate(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    // a ^ b. -a ^ b is -(a ^ b) and a ^ -b is allowed
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            let exponent = self.unary()?;
+            Ok(Expr::Binary(
+                BinaryOp::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let column = self.column();
+        match self.advance() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Symbol('(')) => {
+                let expr = self.sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) => match name.as_str() {
+                "frame" => Ok(Expr::Frame),
+                "time" => Ok(Expr::Time),
+                "pi" => Ok(Expr::Number(PI)),
+                "obj" => self.reference(),
+                _ => match Function::from_name(&name) {
+                    Some(function) => self.call(function, &name),
+                    None => Err(format!("Unknown name '{}' at column {}", name, column)),
+                },
+            },
+            Some(token) => Err(format!("Unexpected {} at column {}", token, column)),
+            None => Err(format!("Expression ends early at column {}", column)),
+        }
+    }
+
+    // Arguments of a function, after its name
+    fn call(&mut self, function: Function, name: &str) -> Result<Expr, String> {
+        self.expect('(')?;
+        let mut args = Vec::new();
+        if !self.eat(')') {
+            loop {
+                args.push(self.sum()?);
+                if self.eat(')') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+
+        if args.len() != function.arguments() {
+            return Err(format!(
+                "{} takes {} argument{}, not {}",
+                name,
+                function.arguments(),
+                if function.arguments() == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    // ("Name").attribute, after obj
+    fn reference(&mut self) -> Result<Expr, String> {
+        self.expect('(')?;
+        let object = self.expect_text("an object name")?;
+        self.expect(')')?;
+        self.expect('.')?;
+
+        let name = self.expect_name("an attribute")?;
+        let attribute = match name.as_str() {
+            "position" | "rotation" | "scale" => {
+                self.expect('.')?;
+                let column = self.column();
+                let axis = match self.expect_name("x, y or z")?.as_str() {
+                    "x" => 0,
+                    "y" => 1,
+                    "z" => 2,
+                    _ => return Err(format!("Expected x, y or z at column {}", column)),
+                };
+                match name.as_str() {
+                    "position" => Attribute::Position(axis),
+                    "rotation" => Attribute::Rotation(axis),
+                    _ => Attribute::Scale(axis),
+                }
+            }
+            "prop" => {
+                self.expect('(')?;
+                let property = self.expect_text("a property name")?;
+                self.expect(')')?;
+                Attribute::Property(property)
+            }
+            _ => Attribute::Property(name),
+        };
+
+        Ok(Expr::Reference(object, attribute))
+    }
+}
+
+// Expression tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scene;
+
+    impl Context for Scene {
+        fn frame(&self) -> f32 {
+            48.
+        }
+
+        fn time(&self) -> f32 {
+            2.
+        }
+
+        fn reference(&self, object: &str, attribute: &Attribute) -> f32 {
+            match (object, attribute) {
+                ("Gear1", Attribute::Rotation(2)) => 1.5,
+                ("Gear1", Attribute::Property(name)) if name == "Teeth count" => 12.,
+                _ => 0.,
+            }
+        }
+    }
+
+    fn eval(text: &str) -> f32 {
+        Expr::parse(text).unwrap().evaluate(&Scene)
+    }
+
+    #[test]
+    fn expression_arithmetic_test() {
+        assert_eq!(eval("1 + 2 * 3"), 7.);
+        assert_eq!(eval("(1 + 2) * 3"), 9.);
+        assert_eq!(eval("10 - 4 - 3"), 3.);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.);
+        assert_eq!(eval("-2 ^ 2"), -4.);
+        assert_eq!(eval("2 ^ -1"), 0.5);
+        assert_eq!(eval("7 % 4 / .5"), 6.);
+        assert_eq!(eval("frame / 24 + time"), 4.);
+        assert_eq!(eval("max(1, min(5, 3)) + clamp(9, 0, 2)"), 5.);
+        assert_eq!(eval("deg(pi)"), 180.);
+        assert!((eval("sin(pi / 2) + cos(0)") - 2.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn expression_reference_test() {
+        assert_eq!(eval("obj(\"Gear1\").rotation.z * -2"), -3.);
+        assert_eq!(eval("obj(\"Gear1\").prop(\"Teeth count\")"), 12.);
+
+        let expr = Expr::parse("obj(\"A\").position.x + obj(\"B\").speed").unwrap();
+        assert_eq!(
+            expr.references(),
+            vec![
+                ("A", &Attribute::Position(0)),
+                ("B", &Attribute::Property("speed".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn expression_error_test() {
+        let error = |text: &str| Expr::parse(text).unwrap_err();
+
+        assert_eq!(error("fram + 1"), "Unknown name 'fram' at column 1");
+        assert_eq!(error("1 +"), "Expression ends early at column 4");
+        assert_eq!(error("(1 + 2"), "Expected ')' at column 7");
+        assert_eq!(error("1 2"), "Unexpected number 2 at column 3");
+        assert_eq!(error("sin(1, 2)"), "sin takes 1 argument, not 2");
+        assert_eq!(error("1 $ 2"), "Unexpected '$' at column 3");
+        assert_eq!(error("obj(\"A"), "Unclosed \" at column 5");
+        assert_eq!(
+            error("obj(\"A\").rotation.w"),
+            "Expected x, y or z at column 19"
+        );
+        assert_eq!(
+            error("obj(A).scale.x"),
+            "Expected an object name in quotes at column 5"
+        );
+    }
+}
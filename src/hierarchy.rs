@@ -0,0 +1,110 @@
+/*
+This file contains functions for working with the parent/child hierarchy of a project's objects.
+Each object may have a parent, given as its index in the project's list of objects. An object's
+world matrix is its parent's world matrix times its own matrix relative to the parent, so moving a
+parent moves all of its children along with it.
+Everything here works on plain lists of parents and matrices, one entry per object, so it doesn't
+depend on objects or meshes.
+
+TODO:
+*/
+use nalgebra_glm as glm;
+
+// Whether giving child the given parent would make child one of its own ancestors
+pub fn creates_cycle(parents: &[Option<usize>], child: usize, parent: usize) -> bool {
+    let mut current = Some(parent);
+    let mut steps = 0;
+    while let Some(i) = current {
+        // A chain longer than the list of objects is already a cycle
+        if i == child || steps > parents.len() {
+            return true;
+        }
+        current = parents.get(i).copied().flatten();
+        steps += 1;
+    }
+    false
+}
+
+// World matrix of every object, given each object's matrix relative to its parent. Parents are
+// worked out before their children, and each object only once, no matter how many children it
+// has. Objects with a missing parent, or caught in a cycle, are treated as having none
+pub fn world_mats(parents: &[Option<usize>], relative: &[glm::Mat4]) -> Vec<glm::Mat4> {
+    let mut world: Vec<Option<glm::Mat4>> = vec![None; relative.len()];
+
+    for i in 0..relative.len() {
+        // Walk up to the first ancestor that is already done, or the root
+        let mut chain = Vec::new();
+        let mut current = Some(i);
+        while let Some(j) = current {
+            if world[j].is_some() || chain.contains(&j) {
+                break;
+            }
+            chain.push(j);
+            current = parents[j].filter(|&p| p < relative.len());
+        }
+
+        // Then work back down to i
+        let mut matrix = match current {
+            Some(j) => world[j].unwrap_or_else(glm::identity),
+            None => glm::identity(),
+        };
+        for j in chain.into_iter().rev() {
+            matrix *= relative[j];
+            world[j] = Some(matrix);
+        }
+    }
+
+    world.into_iter().map(Option::unwrap).collect()
+}
+
+// Hierarchy tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glm::Vec3;
+
+    fn translation(x: f32, y: f32, z: f32) -> glm::Mat4 {
+        glm::translate(&glm::identity(), &Vec3::new(x, y, z))
+    }
+
+    fn position(matrix: &glm::Mat4) -> Vec3 {
+        (matrix * glm::vec4(0., 0., 0., 1.)).xyz()
+    }
+
+    #[test]
+    fn hierarchy_cycle_test() {
+        // 0 <- 1 <- 2, and 3 on its own
+        let parents = [None, Some(0), Some(1), None];
+
+        assert!(creates_cycle(&parents, 0, 0));
+        assert!(creates_cycle(&parents, 0, 2));
+        assert!(creates_cycle(&parents, 1, 2));
+        assert!(!creates_cycle(&parents, 2, 0));
+        assert!(!creates_cycle(&parents, 3, 2));
+        assert!(!creates_cycle(&parents, 0, 3));
+    }
+
+    #[test]
+    fn hierarchy_world_mats_test() {
+        // Children listed before their parents still get their parent's matrix
+        let parents = [Some(1), Some(2), None, None];
+        let relative = [
+            translation(0., 0., 1.),
+            translation(0., 1., 0.),
+            glm::rotate_z(&translation(1., 0., 0.), std::f32::consts::FRAC_PI_2),
+            translation(5., 0., 0.),
+        ];
+        let world = world_mats(&parents, &relative);
+
+        let close = |a: Vec3, b: Vec3| glm::distance(&a, &b) < 1e-5;
+        assert!(close(position(&world[2]), Vec3::new(1., 0., 0.)));
+        assert!(close(position(&world[1]), Vec3::new(0., 0., 0.)));
+        assert!(close(position(&world[0]), Vec3::new(0., 0., 1.)));
+        assert!(close(position(&world[3]), Vec3::new(5., 0., 0.)));
+
+        // Cycles don't hang
+        let world = world_mats(&[Some(1), Some(0)], &relative[..2]);
+        assert_eq!(world.len(), 2);
+    }
+}
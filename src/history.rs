@@ -0,0 +1,551 @@
+/*
+This file contains the History struct, the undo/redo stack for every change made to a Project from
+the UI. Each change is recorded as a Command holding what is needed to both undo and redo it.
+Changes to objects store the object as it was before and after the change, which covers anything
+that can be done to an object's attributes and tracks with one kind of command.
+Changes made while dragging a widget come in every frame. Commands given the same merge key are
+merged into one until end_merge is called, which the UI does when the mouse button is released.
+
+TODO:
+*/
+use crate::{animation::Track, mesh::Mesh, nla::Clip, object::Object, project::Project};
+use glium::Display;
+use imgui::ImString;
+use std::{
+    collections::{BTreeSet, VecDeque},
+    path::Path,
+};
+
+pub struct History {
+    // Commands that can be undone, oldest first
+    undo: VecDeque<Command>,
+
+    // Commands that can be redone, most recently undone last
+    redo: Vec<Command>,
+
+    // Most commands kept. The oldest ones are forgotten first
+    depth: usize,
+
+    // Whether the last command may still absorb commands with the same merge key
+    merging: bool,
+
+    // Goes up every time the project is changed, done, undone or redone, so that anything worked
+    // out from the project can tell when it is out of date
+    revision: u64,
+}
+
+struct Command {
+    name: String,
+    merge_key: Option<String>,
+    change: Change,
+}
+
+enum Change {
+    // Objects replaced with new versions, as (index, before, after)
+    Objects(Vec<(usize, Object, Object)>),
+
+    // An object or mesh added to the end of its list
+    AddObject(Box<Object>),
+    AddMesh(Mesh),
+
+    // A mesh replaced with a new version, as (index, before, after)
+    Mesh(usize, Mesh, Mesh),
+
+    // A clip added to the end of the project's clips
+    AddClip(Box<Clip>),
+
+    // Project wide settings, before and after
+    Settings(Settings, Settings),
+
+    // Several changes made as one, applied in order and reverted in reverse
+    Group(Vec<Change>),
+}
+
+// Project wide values that aren't part of any object
+#[derive(Clone)]
+struct Settings {
+    fov: Track<f32>,
+    fps: f32,
+    show_rest_pose: bool,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth: 100,
+            merging: false,
+            revision: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    // Changes one object. name describes the change in the history panel. Changes with the same
+    // merge key made one after another are merged until end_merge is called
+    pub fn edit_object<R, F>(
+        &mut self,
+        project: &mut Project,
+        index: usize,
+        name: &str,
+        merge_key: Option<&str>,
+        func: F,
+    ) -> R
+    where
+        F: FnOnce(&mut Object) -> R,
+    {
+        let before = project.objs[index].clone();
+        let result = func(&mut project.objs[index]);
+        let after = project.objs[index].clone();
+
+        self.push(
+            name,
+            merge_key,
+            Change::Objects(vec![(index, before, after)]),
+        );
+        result
+    }
+
+    // Changes any number of objects at once, e.g. keyframes across several objects. Only the
+    // objects at the given indices are recorded, so func must leave the others alone
+    pub fn edit_objects<R, F>(
+        &mut self,
+        project: &mut Project,
+        indices: &BTreeSet<usize>,
+        name: &str,
+        merge_key: Option<&str>,
+        func: F,
+    ) -> R
+    where
+        F: FnOnce(&mut [Object]) -> R,
+    {
+        let indices: Vec<usize> = indices
+            .iter()
+            .copied()
+            .filter(|&i| i < project.objs.len())
+            .collect();
+        let before: Vec<Object> = indices.iter().map(|&i| project.objs[i].clone()).collect();
+        let result = func(&mut project.objs);
+        let objects = indices
+            .into_iter()
+            .zip(before)
+            .map(|(i, b)| (i, b, project.objs[i].clone()))
+            .collect();
+
+        self.push(name, merge_key, Change::Objects(objects));
+        result
+    }
+
+    // Changes project wide settings such as the frame rate and camera FOV
+    pub fn edit_settings<R, F>(
+        &mut self,
+        project: &mut Project,
+        name: &str,
+        merge_key: Option<&str>,
+        func: F,
+    ) -> R
+    where
+        F: FnOnce(&mut Project) -> R,
+    {
+        let before = Settings::of(project);
+        let result = func(project);
+        let after = Settings::of(project);
+
+        self.push(name, merge_key, Change::Settings(before, after));
+        result
+    }
+
+    pub fn add_object(&mut self, project: &mut Project, object: Object) {
+        let name = format!("Add object {}", object.name);
+        project.objs.push(object.clone());
+        self.push(&name, None, Change::AddObject(Box::new(object)));
+    }
+
+    // Loads a mesh like Project::load_mesh_from_file. Nothing is recorded if loading fails
+    pub fn load_mesh<P>(
+        &mut self,
+        project: &mut Project,
+        display: &Display,
+        path: P,
+    ) -> Result<usize, String>
+    where
+        P: AsRef<Path>,
+    {
+        let index = project.load_mesh_from_file(display, path)?;
+        let mesh = project.meshes[index].clone();
+        self.push(
+            &format!("Load mesh {}", mesh.name),
+            None,
+            Change::AddMesh(mesh),
+        );
+        Ok(index)
+    }
+
+    // Adds a morph target to the mesh at index like Project::load_morph_target, and swaps the new
+    // mesh in for the old one on every object using it. Nothing is recorded if loading fails
+    pub fn load_morph_target<P>(
+        &mut self,
+        project: &mut Project,
+        display: &Display,
+        index: usize,
+        path: P,
+    ) -> Result<(), String>
+    where
+        P: AsRef<Path>,
+    {
+        let before = project.meshes[index].clone();
+        let after = project.load_morph_target(display, index, path)?;
+        // Objects using the mesh get the new version, and skinned copies of it are made again
+        let objects = project
+            .objs
+            .iter()
+            .enumerate()
+            .filter(|(_, obj)| obj.mesh.made_from(&before))
+            .map(|(i, obj)| {
+                let mut morphed = obj.clone();
+                morphed.mesh = if obj.mesh.same(&before) {
+                    after.clone()
+                } else {
+                    obj.mesh.reskinned(display, &after)
+                };
+                (i, obj.clone(), morphed)
+            })
+            .collect();
+
+        let name = match after.morphs.last() {
+            Some(morph) => format!("Load morph target {}", morph.name),
+            None => "Load morph target".to_string(),
+        };
+        let change = Change::Group(vec![
+            Change::Mesh(index, before, after),
+            Change::Objects(objects),
+        ]);
+        change.apply(project);
+        self.push(&name, None, change);
+        Ok(())
+    }
+
+    // Moves an object's keyframes into a new clip like Project::push_down. Nothing is recorded if
+    // no clip can be made
+    pub fn push_down(
+        &mut self,
+        project: &mut Project,
+        index: usize,
+        name: ImString,
+    ) -> Result<usize, String> {
+        let before = project.objs[index].clone();
+        let clip = project.push_down(index, name)?;
+        let after = project.objs[index].clone();
+
+        self.push(
+            &format!("Make clip {}", project.clips[clip].name),
+            None,
+            Change::Group(vec![
+                Change::AddClip(Box::new(project.clips[clip].clone())),
+                Change::Objects(vec![(index, before, after)]),
+            ]),
+        );
+        Ok(clip)
+    }
+
+    // Undoes the last command. Returns false if there is nothing to undo
+    pub fn undo(&mut self, project: &mut Project) -> bool {
+        self.merging = false;
+        match self.undo.pop_back() {
+            Some(command) => {
+                command.change.revert(project);
+                self.redo.push(command);
+                self.revision += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Redoes the last undone command. Returns false if there is nothing to redo
+    pub fn redo(&mut self, project: &mut Project) -> bool {
+        self.merging = false;
+        match self.redo.pop() {
+            Some(command) => {
+                command.change.apply(project);
+                self.undo.push_back(command);
+                self.revision += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Undoes or redoes commands until the given number of commands are done
+    pub fn go_to(&mut self, project: &mut Project, done: usize) {
+        while self.undo.len() > done && self.undo(project) {}
+        while self.undo.len() < done && self.redo(project) {}
+    }
+
+    // Stops the last command from absorbing any more commands
+    pub fn end_merge(&mut self) {
+        self.merging = false;
+    }
+
+    // Names of every command that is done, oldest first, followed by every command that can be
+    // redone, next to be redone first
+    pub fn entries(&self) -> (Vec<&str>, Vec<&str>) {
+        let done = self.undo.iter().map(|c| c.name.as_str()).collect();
+        let undone = self.redo.iter().rev().map(|c| c.name.as_str()).collect();
+        (done, undone)
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Changes the most commands kept, forgetting the oldest ones if there are too many
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth.max(1);
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+
+    fn push(&mut self, name: &str, merge_key: Option<&str>, change: Change) {
+        // Nothing was changed, so there is nothing to undo and redo stays as it is
+        if change.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.revision += 1;
+
+        // Keep adding on to the last command while a drag goes on
+        if let (true, Some(key), Some(last)) = (self.merging, merge_key, self.undo.back_mut()) {
+            if last.merge_key.as_deref() == Some(key) && last.change.merge(&change) {
+                return;
+            }
+        }
+
+        self.undo.push_back(Command {
+            name: name.to_string(),
+            merge_key: merge_key.map(str::to_string),
+            change,
+        });
+        self.merging = merge_key.is_some();
+
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+}
+
+impl Change {
+    // Whether the change leaves the project as it was, e.g. an edit of no objects
+    fn is_empty(&self) -> bool {
+        match self {
+            Change::Objects(objects) => objects.is_empty(),
+            Change::Group(changes) => changes.iter().all(Change::is_empty),
+            _ => false,
+        }
+    }
+
+    fn apply(&self, project: &mut Project) {
+        match self {
+            Change::Objects(objects) => {
+                for (i, _, after) in objects {
+                    project.objs[*i] = after.clone();
+                }
+            }
+            Change::AddObject(object) => project.objs.push(object.as_ref().clone()),
+            Change::AddMesh(mesh) => project.meshes.push(mesh.clone()),
+            Change::Mesh(i, _, after) => project.meshes[*i] = after.clone(),
+            Change::AddClip(clip) => project.clips.push(clip.as_ref().clone()),
+            Change::Settings(_, after) => after.apply(project),
+            Change::Group(changes) => {
+                for change in changes {
+                    change.apply(project);
+                }
+            }
+        }
+    }
+
+    fn revert(&self, project: &mut Project) {
+        match self {
+            Change::Objects(objects) => {
+                for (i, before, _) in objects {
+                    project.objs[*i] = before.clone();
+                }
+            }
+            Change::AddObject(_) => {
+                project.objs.pop();
+            }
+            Change::AddMesh(_) => {
+                project.meshes.pop();
+            }
+            Change::Mesh(i, before, _) => project.meshes[*i] = before.clone(),
+            Change::AddClip(_) => {
+                project.clips.pop();
+            }
+            Change::Settings(before, _) => before.apply(project),
+            Change::Group(changes) => {
+                for change in changes.iter().rev() {
+                    change.revert(project);
+                }
+            }
+        }
+    }
+
+    // Takes on the end result of a change following this one. Returns false if the changes are of
+    // different things and can't be merged
+    fn merge(&mut self, next: &Change) -> bool {
+        match (self, next) {
+            (Change::Objects(objects), Change::Objects(next)) => {
+                let same = objects.len() == next.len()
+                    && objects.iter().zip(next).all(|(a, b)| a.0 == b.0);
+                if same {
+                    for (object, next) in objects.iter_mut().zip(next) {
+                        object.2 = next.2.clone();
+                    }
+                }
+                same
+            }
+            (Change::Settings(_, after), Change::Settings(_, next)) => {
+                *after = next.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Settings {
+    fn of(project: &Project) -> Settings {
+        Settings {
+            fov: project.fov.clone(),
+            fps: project.fps,
+            show_rest_pose: project.show_rest_pose,
+        }
+    }
+
+    fn apply(&self, project: &mut Project) {
+        project.fov = self.fov.clone();
+        project.fps = self.fps;
+        project.show_rest_pose = self.show_rest_pose;
+    }
+}
+
+// History tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_fps(history: &mut History, project: &mut Project, fps: f32, merge_key: Option<&str>) {
+        history.edit_settings(project, "Frame rate", merge_key, |p| p.fps = fps);
+    }
+
+    #[test]
+    fn history_undo_redo_test() {
+        let mut project = Project::default();
+        let mut history = History::new();
+
+        assert!(!history.undo(&mut project));
+        set_fps(&mut history, &mut project, 30., None);
+        set_fps(&mut history, &mut project, 60., None);
+
+        assert!(history.undo(&mut project));
+        assert_eq!(project.fps, 30.);
+        assert!(history.undo(&mut project));
+        assert_eq!(project.fps, 24.);
+        assert!(!history.undo(&mut project));
+
+        assert!(history.redo(&mut project));
+        assert_eq!(project.fps, 30.);
+        assert_eq!(history.entries(), (vec!["Frame rate"], vec!["Frame rate"]));
+
+        // A new command forgets what could be redone
+        set_fps(&mut history, &mut project, 12., None);
+        assert!(!history.redo(&mut project));
+
+        // Jumping around the history
+        history.go_to(&mut project, 0);
+        assert_eq!(project.fps, 24.);
+        history.go_to(&mut project, 2);
+        assert_eq!(project.fps, 12.);
+    }
+
+    #[test]
+    fn history_empty_change_test() {
+        let mut project = Project::default();
+        let mut history = History::new();
+        set_fps(&mut history, &mut project, 30., None);
+        history.undo(&mut project);
+        let revision = history.revision();
+
+        // Editing no objects isn't recorded, and leaves redo alone
+        history.edit_objects(
+            &mut project,
+            &BTreeSet::new(),
+            "Delete keyframes",
+            None,
+            |_| (),
+        );
+        assert_eq!(history.entries(), (vec![], vec!["Frame rate"]));
+        assert_eq!(history.revision(), revision);
+    }
+
+    #[test]
+    fn history_merge_test() {
+        let mut project = Project::default();
+        let mut history = History::new();
+
+        // One drag is one command
+        for fps in &[25., 26., 27.] {
+            set_fps(&mut history, &mut project, *fps, Some("fps"));
+        }
+        assert_eq!(history.entries().0.len(), 1);
+
+        // The next drag is another one
+        history.end_merge();
+        set_fps(&mut history, &mut project, 28., Some("fps"));
+        set_fps(&mut history, &mut project, 29., Some("fps"));
+        assert_eq!(history.entries().0.len(), 2);
+
+        history.undo(&mut project);
+        assert_eq!(project.fps, 27.);
+        history.undo(&mut project);
+        assert_eq!(project.fps, 24.);
+
+        // Different keys aren't merged
+        set_fps(&mut history, &mut project, 30., Some("a"));
+        set_fps(&mut history, &mut project, 31., Some("b"));
+        assert_eq!(history.entries().0.len(), 2);
+    }
+
+    #[test]
+    fn history_depth_test() {
+        let mut project = Project::default();
+        let mut history = History::new();
+        history.set_depth(3);
+
+        for fps in 1..=5 {
+            set_fps(&mut history, &mut project, fps as f32, None);
+        }
+        assert_eq!(history.entries().0.len(), 3);
+
+        while history.undo(&mut project) {}
+        assert_eq!(project.fps, 2.);
+
+        // Shrinking forgets the oldest commands
+        history.go_to(&mut project, 3);
+        history.set_depth(1);
+        history.undo(&mut project);
+        assert_eq!(project.fps, 4.);
+    }
+}
@@ -0,0 +1,410 @@
+/*
+This file contains inverse kinematics (IK) for armatures: turning a chain of bones so that the tail
+of its last bone reaches a target, so that e.g. a foot can be planted or a hand can reach with one
+keyed target rather than keying every joint.
+Chains of two bones, such as limbs, are solved exactly, bending towards a pole. Longer chains use
+FABRIK (forward and backward reaching IK). Both keep each bone's bend from its parent within the
+bone's limits, if it has any. The result is blended with the keyed (FK) pose by the chain's IK/FK
+blend, which can be keyed to switch between the two.
+Only bones make up chains. Objects parented to each other aren't solved as chains, as an object has
+no length to reach with; rigs that need IK should use an armature.
+
+TODO:
+    * Targets following other objects, rather than keyed points in the object's space
+    * Twist limits
+*/
+use crate::animation::{KeyframeV3, Track};
+use crate::armature::Armature;
+use crate::constraint::decompose;
+use crate::nla::Transform;
+use crate::rotation::slerp;
+use glm::{Quat, Vec3};
+use nalgebra_glm as glm;
+use std::f32::consts::PI;
+
+#[derive(Clone)]
+pub struct IkChain {
+    // Index of the last bone in the chain, whose tail reaches for the target
+    pub tip: usize,
+
+    // Bones in the chain, going up from the tip through its parents
+    pub length: usize,
+
+    pub solver: IkSolver,
+
+    // Where the chain reaches for, in the object's space
+    pub target: KeyframeV3,
+
+    // Point two-bone chains bend towards, in the object's space. Without one, chains keep bending
+    // the way their keyed pose does
+    pub pole: Option<KeyframeV3>,
+
+    // How much IK counts over the keyed pose, from 0 (all FK) to 1 (all IK)
+    pub blend: Track<f32>,
+
+    // Passes FABRIK makes each time it is solved
+    pub iterations: u32,
+
+    pub muted: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IkSolver {
+    TwoBone,
+    Fabrik,
+}
+
+impl IkChain {
+    // A two-bone chain ending at tip, reaching for target
+    pub fn new(tip: usize, target: Vec3) -> IkChain {
+        IkChain {
+            tip,
+            length: 2,
+            solver: IkSolver::TwoBone,
+            target: Track::new(target),
+            pole: None,
+            blend: Track::new(1.),
+            iterations: 10,
+            muted: false,
+        }
+    }
+
+    // Indices of the bones in the chain, from its root down to its tip. Shorter than length when
+    // the tip doesn't have enough ancestors
+    pub fn bones(&self, armature: &Armature) -> Vec<usize> {
+        let mut bones = Vec::new();
+        let mut current = Some(self.tip).filter(|&b| b < armature.bones.len());
+        while let Some(b) = current {
+            if bones.len() >= self.length {
+                break;
+            }
+            bones.push(b);
+            current = armature.bones[b].parent;
+        }
+        bones.reverse();
+        bones
+    }
+}
+
+// Bends each chain of the armature towards its target, in order, starting from the keyed poses.
+// Returns the new poses
+pub fn solve(
+    armature: &Armature,
+    chains: &[IkChain],
+    poses: &[Transform],
+    frame: f32,
+) -> Vec<Transform> {
+    let mut poses = poses.to_vec();
+
+    for chain in chains.iter().filter(|c| !c.muted) {
+        let blend = chain.blend.sample(frame).clamp(0., 1.);
+        let bones = chain.bones(armature);
+        if blend <= 0. || bones.is_empty() {
+            continue;
+        }
+
+        let mats = armature.pose_mats(&poses);
+        let joints = chain_joints(armature, &bones, &mats);
+
+        // Bending limits of each bone from the one before it, and the direction of the bone before
+        // the root, if any
+        let limits: Vec<Option<(f32, f32)>> =
+            bones.iter().map(|&b| armature.bones[b].ik_limit).collect();
+        let before = armature.bones[bones[0]].parent.map(|p| {
+            let parent = &armature.bones[p];
+            point(&mats[p], &(parent.tail - parent.head)) - point(&mats[p], &Vec3::zeros())
+        });
+
+        let target = chain.target.sample(frame);
+        let solved = match (chain.solver, joints.len()) {
+            (IkSolver::TwoBone, 3) => {
+                let pole = chain.pole.as_ref().map(|p| p.sample(frame));
+                two_bone(&joints, &target, pole.as_ref(), limits[1])
+            }
+            _ => fabrik(&joints, &target, &limits, before.as_ref(), chain.iterations),
+        };
+
+        // Turn each bone from where it points now to where it should, root first so that each
+        // bone is turned after its parent has moved it
+        let fk: Vec<Quat> = bones.iter().map(|&b| poses[b].rotation).collect();
+        for (i, &b) in bones.iter().enumerate() {
+            let mats = armature.pose_mats(&poses);
+            let joints = chain_joints(armature, &bones, &mats);
+            let now = (joints[i + 1] - joints[i]).try_normalize(1e-6);
+            let wanted = (solved[i + 1] - solved[i]).try_normalize(1e-6);
+            let bone = &armature.bones[b];
+
+            // Bones without a length point nowhere, so they are left as they are
+            let turn = match (now, wanted) {
+                (Some(now), Some(wanted)) => glm::quat_rotation(&now, &wanted),
+                _ => continue,
+            };
+            let parent = match bone.parent {
+                Some(p) => decompose(&mats[p]).rotation,
+                None => Quat::identity(),
+            };
+            let rotation = glm::quat_inverse(&parent) * turn * parent * poses[b].rotation;
+            poses[b].rotation = glm::quat_normalize(&rotation);
+        }
+        for (&b, fk) in bones.iter().zip(fk) {
+            poses[b].rotation = slerp(&fk, &poses[b].rotation, blend);
+        }
+    }
+
+    poses
+}
+
+// Positions of a chain's joints in the object's space, given each bone's pose matrix: the head of
+// each bone, then the tail of the last one
+fn chain_joints(armature: &Armature, bones: &[usize], mats: &[glm::Mat4]) -> Vec<Vec3> {
+    let mut joints: Vec<Vec3> = bones
+        .iter()
+        .map(|&b| point(&mats[b], &Vec3::zeros()))
+        .collect();
+    if let Some(&tip) = bones.last() {
+        let bone = &armature.bones[tip];
+        joints.push(point(&mats[tip], &(bone.tail - bone.head)));
+    }
+    joints
+}
+
+// Where a point in a bone's space ends up in the object's space
+fn point(mat: &glm::Mat4, p: &Vec3) -> Vec3 {
+    (mat * glm::vec4(p.x, p.y, p.z, 1.)).xyz()
+}
+
+// Solves a chain of two bones exactly, given the positions of the root, the middle joint and the
+// tip. The chain bends in the plane holding the root, the target and the pole, or the middle joint
+// without a pole. Targets out of reach, or out of the middle joint's limits, are reached for as
+// closely as possible
+pub fn two_bone(
+    joints: &[Vec3],
+    target: &Vec3,
+    pole: Option<&Vec3>,
+    limit: Option<(f32, f32)>,
+) -> Vec<Vec3> {
+    let (root, middle, tip) = (joints[0], joints[1], joints[2]);
+    let (a, b) = ((middle - root).norm(), (tip - middle).norm());
+    let to_target = target - root;
+    let forward = if to_target.norm() > 1e-6 {
+        to_target.normalize()
+    } else {
+        (tip - root)
+            .try_normalize(1e-6)
+            .unwrap_or_else(|| Vec3::new(0., 1., 0.))
+    };
+
+    // How far away the tip can be, given how much the middle joint is allowed to bend
+    let mut bend = PI - law_of_cosines(a, b, to_target.norm());
+    if let Some((min, max)) = limit {
+        bend = bend.clamp(min, max);
+    }
+    let reach = (a * a + b * b + 2. * a * b * bend.cos()).max(0.).sqrt();
+
+    // Bend towards the pole, or whichever way the chain already bends
+    let towards = pole.map_or(middle, |p| *p) - root;
+    let side = perpendicular(&forward, &towards);
+
+    let angle = law_of_cosines(a, reach, b);
+    let middle = root + (forward * angle.cos() + side * angle.sin()) * a;
+    let tip = root + forward * reach;
+    vec![root, middle, tip]
+}
+
+// Angle opposite side c in a triangle with sides a, b and c. Sides that can't make a triangle give
+// the nearest angle that can
+fn law_of_cosines(a: f32, b: f32, c: f32) -> f32 {
+    if a < 1e-6 || b < 1e-6 {
+        return 0.;
+    }
+    ((a * a + b * b - c * c) / (2. * a * b))
+        .clamp(-1., 1.)
+        .acos()
+}
+
+// The part of v at right angles to the unit vector forward, as a unit vector. Any direction at
+// right angles to forward if v is along forward
+fn perpendicular(forward: &Vec3, v: &Vec3) -> Vec3 {
+    let side = v - forward * v.dot(forward);
+    side.try_normalize(1e-6).unwrap_or_else(|| {
+        let other = if forward.x.abs() < 0.9 {
+            Vec3::new(1., 0., 0.)
+        } else {
+            Vec3::new(0., 1., 0.)
+        };
+        forward.cross(&other).normalize()
+    })
+}
+
+// Solves a chain of any length with FABRIK, given the position of each joint from the root's head
+// to the tip's tail. Each bone keeps its length and the root stays put. limits are the bending
+// limits of each bone from the one before it, and before the direction of the bone before the root
+pub fn fabrik(
+    joints: &[Vec3],
+    target: &Vec3,
+    limits: &[Option<(f32, f32)>],
+    before: Option<&Vec3>,
+    iterations: u32,
+) -> Vec<Vec3> {
+    let lengths: Vec<f32> = joints.windows(2).map(|j| (j[1] - j[0]).norm()).collect();
+    let root = joints[0];
+    let mut out = joints.to_vec();
+    let last = out.len() - 1;
+
+    for _ in 0..iterations.max(1) {
+        if (out[last] - target).norm() < 1e-4 {
+            break;
+        }
+
+        // Backward, from the target to the root
+        out[last] = *target;
+        for i in (0..last).rev() {
+            let direction = (out[i] - out[i + 1])
+                .try_normalize(1e-6)
+                .unwrap_or_default();
+            out[i] = out[i + 1] + direction * lengths[i];
+        }
+
+        // Forward, from the root back out to the tip, keeping each bone within its limits
+        out[0] = root;
+        for i in 0..last {
+            let mut direction = (out[i + 1] - out[i])
+                .try_normalize(1e-6)
+                .unwrap_or_default();
+            let previous = if i > 0 {
+                Some(out[i] - out[i - 1])
+            } else {
+                before.copied()
+            };
+            if let (Some(previous), Some((min, max))) = (previous, limits.get(i).copied().flatten())
+            {
+                direction = limit_bend(&direction, &previous, min, max);
+            }
+            out[i + 1] = out[i] + direction * lengths[i];
+        }
+    }
+
+    out
+}
+
+// Turns direction towards or away from previous, just enough to keep the angle between them from
+// min to max
+fn limit_bend(direction: &Vec3, previous: &Vec3, min: f32, max: f32) -> Vec3 {
+    let previous = match previous.try_normalize(1e-6) {
+        Some(p) => p,
+        None => return *direction,
+    };
+    let angle = direction.dot(&previous).clamp(-1., 1.).acos();
+    let limited = angle.clamp(min, max);
+    if (limited - angle).abs() < 1e-6 {
+        return *direction;
+    }
+
+    let side = perpendicular(&previous, direction);
+    previous * limited.cos() + side * limited.sin()
+}
+
+// IK tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::armature::Bone;
+    use imgui::ImString;
+
+    fn close(a: &Vec3, b: &Vec3) -> bool {
+        (a - b).norm() < 1e-3
+    }
+
+    // Straight up y, one unit per bone
+    fn straight(bones: usize) -> Vec<Vec3> {
+        (0..=bones).map(|i| Vec3::new(0., i as f32, 0.)).collect()
+    }
+
+    #[test]
+    fn ik_two_bone_test() {
+        let target = Vec3::new(1., 1., 0.);
+        let pole = Vec3::new(0., 0., 5.);
+        let joints = two_bone(&straight(2), &target, Some(&pole), None);
+
+        // Reaches the target, keeping bone lengths and bending towards the pole
+        assert!(close(&joints[2], &target));
+        assert!(((joints[1] - joints[0]).norm() - 1.).abs() < 1e-4);
+        assert!(((joints[2] - joints[1]).norm() - 1.).abs() < 1e-4);
+        assert!(joints[1].z > 0.1);
+
+        // Out of reach, the chain straightens towards the target
+        let joints = two_bone(&straight(2), &Vec3::new(5., 0., 0.), Some(&pole), None);
+        assert!(close(&joints[2], &Vec3::new(2., 0., 0.)));
+
+        // The middle joint can't bend past its limit, so the target is out of reach
+        let joints = two_bone(
+            &straight(2),
+            &Vec3::new(0.2, 0., 0.),
+            Some(&pole),
+            Some((0., PI / 2.)),
+        );
+        let bent = (joints[1] - joints[0]).angle(&(joints[2] - joints[1]));
+        assert!((bent - PI / 2.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ik_fabrik_test() {
+        let target = Vec3::new(2., 2., 0.);
+        let joints = fabrik(&straight(4), &target, &[None; 4], None, 20);
+        assert!(close(&joints[4], &target));
+        assert!(close(&joints[0], &Vec3::zeros()));
+        for pair in joints.windows(2) {
+            assert!(((pair[1] - pair[0]).norm() - 1.).abs() < 1e-4);
+        }
+
+        // Limited to bending 10 degrees at each joint, the chain can't curl around to the target
+        let limits = [Some((0., 10f32.to_radians())); 4];
+        let joints = fabrik(&straight(4), &Vec3::new(0., 0.5, 0.), &limits, None, 20);
+        for pair in joints.windows(3) {
+            let bent = (pair[1] - pair[0]).angle(&(pair[2] - pair[1]));
+            assert!(bent <= 10f32.to_radians() + 1e-3);
+        }
+    }
+
+    #[test]
+    fn ik_chain_test() {
+        let mut armature = Armature::default();
+        let up = Vec3::new(0., 1., 0.);
+        armature
+            .add_bone(Bone::new(ImString::new("Upper"), None, Vec3::zeros(), up))
+            .unwrap();
+        armature
+            .add_bone(Bone::new(ImString::new("Lower"), Some(0), up, up * 2.))
+            .unwrap();
+
+        let mut chain = IkChain::new(1, Vec3::new(1., 1., 0.));
+        chain.pole = Some(Track::new(Vec3::new(0., 0., 5.)));
+        assert_eq!(chain.bones(&armature), vec![0, 1]);
+
+        // The posed tip reaches the target
+        let poses = solve(&armature, &[chain.clone()], &armature.rest_poses(), 0.);
+        let mats = armature.pose_mats(&poses);
+        assert!(close(&point(&mats[1], &up), &Vec3::new(1., 1., 0.)));
+
+        // Halfway between FK and IK, the tip is somewhere in between
+        chain.blend = Track::new(0.5);
+        let poses = solve(&armature, &[chain.clone()], &armature.rest_poses(), 0.);
+        let tip = point(&armature.pose_mats(&poses)[1], &up);
+        assert!(!close(&tip, &Vec3::new(1., 1., 0.)) && !close(&tip, &(up * 2.)));
+
+        // Muted chains leave the pose alone
+        chain.muted = true;
+        let poses = solve(&armature, &[chain], &armature.rest_poses(), 0.);
+        assert_eq!(poses, armature.rest_poses());
+
+        // A bone without a length is left alone, and doesn't break the rest of the chain
+        armature.bones[1].tail = up;
+        let chain = IkChain::new(1, Vec3::new(1., 1., 0.));
+        let poses = solve(&armature, &[chain], &armature.rest_poses(), 0.);
+        assert!(poses
+            .iter()
+            .all(|p| p.rotation.coords.iter().all(|c| c.is_finite())));
+        assert_eq!(poses[1], armature.rest_poses()[1]);
+    }
+}
@@ -0,0 +1,196 @@
+/*
+This file contains the Interpolate trait, implemented by every type that can be keyframed in a
+Track (see animation.rs), and its implementations for f32, Vec3, Vec4 (colours), quaternions and
+stepped values such as bools and enums.
+A value is treated as a small list of f32 components. Bezier handles, and anything else that
+works on a curve one component at a time, go through those. How two keyframes are tweened can be
+overridden per type, e.g. quaternions use squad instead of tweening each component.
+
+TODO:
+*/
+use crate::rotation::squad;
+use glm::{Quat, Vec3, Vec4};
+use nalgebra_glm as glm;
+
+pub trait Interpolate: Copy {
+    // Number of f32 components making up a value
+    const COMPONENTS: usize;
+
+    // Whether the value jumps from one keyframe to the next instead of being tweened. Used for
+    // values that can't be partway between two others, like bools and enums
+    const STEPPED: bool = false;
+
+    fn component(&self, i: usize) -> f32;
+    fn set_component(&mut self, i: usize, value: f32);
+
+    // Tweens from keyframe value a to keyframe value b, where t is the eased progress between them
+    // (0 to 1). prev and next are the keyframe values before a and after b, if there are any, for
+    // types that tween along a spline. By default each component is tweened linearly
+    fn interpolate(_prev: Option<&Self>, a: &Self, b: &Self, _next: Option<&Self>, t: f32) -> Self {
+        a.zip_components(b, |a, b| a + (b - a) * t)
+    }
+
+    // Moves a value by the difference between from and to, the given number of times. Used for
+    // repeating a keyed range so that each repeat carries on from the end of the last one
+    fn offset_by(&self, from: &Self, to: &Self, times: f32) -> Self {
+        let delta = to.zip_components(from, |t, f| t - f);
+        self.zip_components(&delta, |v, d| v + d * times)
+    }
+
+    // Fixes up a value that was built one component at a time, e.g. by a bezier curve
+    fn normalized(self) -> Self {
+        self
+    }
+
+    // Returns a value with f applied to each component
+    fn map_components<F: Fn(f32) -> f32>(&self, f: F) -> Self {
+        let mut out = *self;
+        for i in 0..Self::COMPONENTS {
+            out.set_component(i, f(self.component(i)));
+        }
+        out
+    }
+
+    // Returns a value with f applied to each pair of matching components
+    fn zip_components<F: Fn(f32, f32) -> f32>(&self, other: &Self, f: F) -> Self {
+        let mut out = *self;
+        for i in 0..Self::COMPONENTS {
+            out.set_component(i, f(self.component(i), other.component(i)));
+        }
+        out
+    }
+}
+
+impl Interpolate for f32 {
+    const COMPONENTS: usize = 1;
+
+    fn component(&self, _i: usize) -> f32 {
+        *self
+    }
+
+    fn set_component(&mut self, _i: usize, value: f32) {
+        *self = value;
+    }
+}
+
+impl Interpolate for Vec3 {
+    const COMPONENTS: usize = 3;
+
+    fn component(&self, i: usize) -> f32 {
+        self[i]
+    }
+
+    fn set_component(&mut self, i: usize, value: f32) {
+        self[i] = value;
+    }
+}
+
+// Used for colours (RGBA)
+impl Interpolate for Vec4 {
+    const COMPONENTS: usize = 4;
+
+    fn component(&self, i: usize) -> f32 {
+        self[i]
+    }
+
+    fn set_component(&mut self, i: usize, value: f32) {
+        self[i] = value;
+    }
+}
+
+// Rotations. Components are x, y, z, w
+impl Interpolate for Quat {
+    const COMPONENTS: usize = 4;
+
+    fn component(&self, i: usize) -> f32 {
+        self.coords[i]
+    }
+
+    fn set_component(&mut self, i: usize, value: f32) {
+        self.coords[i] = value;
+    }
+
+    fn interpolate(prev: Option<&Quat>, a: &Quat, b: &Quat, next: Option<&Quat>, t: f32) -> Quat {
+        squad(prev, a, b, next, t)
+    }
+
+    // The rotation from `from` to `to` is applied on top, the given number of times
+    fn offset_by(&self, from: &Quat, to: &Quat, times: f32) -> Quat {
+        let delta = (to * glm::quat_inverse(from)).normalize();
+        (glm::quat_pow(&delta, times) * self).normalize()
+    }
+
+    fn normalized(self) -> Quat {
+        self.normalize()
+    }
+}
+
+// Values made of a fixed list of cases, such as bools and enums, which jump from one keyframe to
+// the next. ALL lists every case. The single component is the index of the value in ALL
+pub trait Stepped: Copy + PartialEq + 'static {
+    const ALL: &'static [Self];
+}
+
+impl<T: Stepped> Interpolate for T {
+    const COMPONENTS: usize = 1;
+    const STEPPED: bool = true;
+
+    fn component(&self, _i: usize) -> f32 {
+        T::ALL.iter().position(|v| v == self).unwrap_or(0) as f32
+    }
+
+    fn set_component(&mut self, _i: usize, value: f32) {
+        let last = T::ALL.len() - 1;
+        *self = T::ALL[(value.round().max(0.) as usize).min(last)];
+    }
+}
+
+// On/off values such as visibility. The single component is 1 for true and 0 for false
+impl Stepped for bool {
+    const ALL: &'static [bool] = &[false, true];
+}
+
+// Interpolate tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::Track;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Shape {
+        Circle,
+        Square,
+        Star,
+    }
+
+    impl Stepped for Shape {
+        const ALL: &'static [Shape] = &[Shape::Circle, Shape::Square, Shape::Star];
+    }
+
+    #[test]
+    fn stepped_components_test() {
+        assert_eq!(Shape::Star.component(0), 2.);
+        assert_eq!(Shape::Circle.map_components(|c| c + 1.), Shape::Square);
+        assert_eq!(Shape::Circle.map_components(|c| c + 10.), Shape::Star);
+        assert_eq!(Shape::Star.map_components(|c| c - 10.), Shape::Circle);
+
+        assert_eq!(true.component(0), 1.);
+        assert!(false.map_components(|_| 0.5));
+        assert!(!true.map_components(|_| 0.4));
+    }
+
+    #[test]
+    fn stepped_track_test() {
+        let mut track = Track::new(Shape::Circle);
+        track.set_at(10, Shape::Square);
+        track.set_at(20, Shape::Star);
+
+        // Values jump on keyframes instead of passing through the cases in between
+        assert_eq!(track.at(5), Shape::Square);
+        assert_eq!(track.at(19), Shape::Square);
+        assert_eq!(track.sample(19.9), Shape::Square);
+        assert_eq!(track.at(20), Shape::Star);
+        assert_eq!(track.rest(), Shape::Circle);
+    }
+}
@@ -0,0 +1,158 @@
+/*
+This file contains the Layer struct, holding a stack of extra position, rotation and scale tracks
+on an object, blended on top of the object's own tracks. Layers let small tweaks be keyed on top
+of mocap or baked motion without touching the keys underneath.
+Override layers only override the tracks they have keys on, so a new layer changes nothing until
+something is keyed on it. Rotations are Euler angles, like the object's Euler rotation track.
+
+TODO:
+    * Keying layers from the timeline and graph editor
+    * Merging a layer down into the one below
+*/
+use crate::animation::{KeyframeV3, Track};
+use crate::nla::Transform;
+use crate::rotation::{euler_to_quat, slerp};
+use glm::{Quat, Vec3};
+use imgui::ImString;
+use nalgebra_glm as glm;
+
+// How a layer combines with what is below it
+//   Override: fades from what is below to the layer's keyed tracks, by the layer's weight
+//   Additive: adds the layer's position and rotation, and multiplies by its scale, by its weight
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LayerMode {
+    Override,
+    Additive,
+}
+
+#[derive(Clone)]
+pub struct Layer {
+    pub name: ImString,
+    pub position: KeyframeV3,
+    pub rotation: KeyframeV3,
+    pub scale: KeyframeV3,
+
+    // How much the layer counts, from 0 to 1
+    pub weight: Track<f32>,
+
+    pub mode: LayerMode,
+    pub muted: bool,
+
+    // While any layer is soloed, only soloed layers are blended
+    pub solo: bool,
+}
+
+impl Layer {
+    // A layer that doesn't change anything yet
+    pub fn new(name: ImString, mode: LayerMode) -> Layer {
+        Layer {
+            name,
+            position: Track::new(Vec3::zeros()),
+            rotation: Track::new(Vec3::zeros()),
+            scale: Track::new(Vec3::new(1., 1., 1.)),
+            weight: Track::new(1.),
+            mode,
+            muted: false,
+            solo: false,
+        }
+    }
+}
+
+// Blends each layer on top of base in order, returning the end result
+pub fn evaluate(base: Transform, layers: &[Layer], frame: f32) -> Transform {
+    let soloing = layers.iter().any(|l| l.solo);
+    let mut out = base;
+
+    for layer in layers {
+        if layer.muted || (soloing && !layer.solo) {
+            continue;
+        }
+        let weight = layer.weight.sample(frame).clamp(0., 1.);
+        if weight <= 0. {
+            continue;
+        }
+
+        let position = layer.position.sample(frame);
+        let rotation = euler_to_quat(&layer.rotation.sample(frame));
+        let scale = layer.scale.sample(frame);
+
+        match layer.mode {
+            LayerMode::Override => {
+                if layer.position.is_keyed() {
+                    out.position = glm::lerp(&out.position, &position, weight);
+                }
+                if layer.rotation.is_keyed() {
+                    out.rotation = slerp(&out.rotation, &rotation, weight);
+                }
+                if layer.scale.is_keyed() {
+                    out.scale = glm::lerp(&out.scale, &scale, weight);
+                }
+            }
+            LayerMode::Additive => {
+                let one = Vec3::new(1., 1., 1.);
+                out.position += position * weight;
+                out.rotation = slerp(&Quat::identity(), &rotation, weight) * out.rotation;
+                out.scale = out.scale.component_mul(&glm::lerp(&one, &scale, weight));
+            }
+        }
+    }
+
+    out
+}
+
+// Layer tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Transform {
+        Transform {
+            position: Vec3::new(1., 2., 3.),
+            rotation: Quat::identity(),
+            scale: Vec3::new(2., 2., 2.),
+        }
+    }
+
+    #[test]
+    fn layer_blend_test() {
+        // Unkeyed layers leave everything alone
+        let mut over = Layer::new(ImString::new("Override"), LayerMode::Override);
+        assert_eq!(evaluate(base(), &[over.clone()], 0.), base());
+
+        // Override by weight, only where keyed
+        over.position.set_at(0, Vec3::new(5., 2., 3.));
+        over.weight = Track::new(0.5);
+        let out = evaluate(base(), &[over.clone()], 0.);
+        assert_eq!(out.position, Vec3::new(3., 2., 3.));
+        assert_eq!(out.scale, base().scale);
+
+        // Additive adds position and multiplies scale
+        let mut add = Layer::new(ImString::new("Additive"), LayerMode::Additive);
+        add.position = Track::new(Vec3::new(0., 1., 0.));
+        add.scale = Track::new(Vec3::new(1.5, 1., 1.));
+        let out = evaluate(base(), &[over.clone(), add.clone()], 0.);
+        assert_eq!(out.position, Vec3::new(3., 3., 3.));
+        assert_eq!(out.scale, Vec3::new(3., 2., 2.));
+    }
+
+    #[test]
+    fn layer_mute_solo_test() {
+        let mut a = Layer::new(ImString::new("A"), LayerMode::Additive);
+        a.position = Track::new(Vec3::new(1., 0., 0.));
+        let mut b = a.clone();
+        b.position = Track::new(Vec3::new(10., 0., 0.));
+        let x = |layers: &[Layer]| evaluate(base(), layers, 0.).position.x;
+
+        assert_eq!(x(&[a.clone(), b.clone()]), 12.);
+        a.muted = true;
+        assert_eq!(x(&[a.clone(), b.clone()]), 11.);
+
+        // Soloed layers are the only ones used, even when other layers aren't muted
+        a.muted = false;
+        a.solo = true;
+        assert_eq!(x(&[a.clone(), b.clone()]), 2.);
+        a.muted = true;
+        assert_eq!(x(&[a, b]), 1.);
+    }
+}
@@ -11,19 +11,40 @@ TODO:
 */
 
 mod animation;
+mod armature;
 mod camera;
+mod constraint;
 mod controls;
+mod driver;
+mod easing;
+mod expression;
+mod hierarchy;
+mod history;
+mod ik;
+mod interpolate;
+mod layer;
 mod mesh;
+mod modifier;
+mod nla;
 mod object;
+mod onion;
+mod overlay;
+mod playback;
 mod project;
+mod rotation;
 mod shaders;
 mod ui;
 mod vertex;
 
-use animation::Frame;
 use camera::Camera;
 use controls::CameraControls;
-use glium::{glutin, program, uniform, Surface};
+use glium::{
+    glutin, program,
+    uniforms::{
+        MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, UniformValue, Uniforms,
+    },
+    Surface,
+};
 use glutin::{
     dpi::{PhysicalPosition, PhysicalSize},
     event::{
@@ -34,8 +55,11 @@ use glutin::{
     },
     event_loop::ControlFlow,
 };
+use history::History;
+use mesh::Mesh;
 use nalgebra_glm as glm;
 use object::Object;
+use playback::Playback;
 use project::Project;
 use std::time::Instant;
 use ui::UIState;
@@ -103,8 +127,14 @@ fn main() {
     // For ui to know what to draw
     let mut ui_state = UIState::new();
 
-    // The current frame to draw objects with
-    let mut frame: Frame = 0;
+    // Decides the current frame to draw objects with
+    let mut playback = Playback::new();
+
+    // For undoing and redoing changes made in the ui
+    let mut history = History::new();
+
+    // Motion path positions, only worked out again when something changes
+    let mut path_cache = overlay::PathCache::default();
 
     project
         .load_mesh_from_file(&display, "res/cube.obj")
@@ -131,7 +161,14 @@ fn main() {
 
             // Do imgui drawing
             let mut ui = imgui.frame();
-            ui::draw(&mut ui, &mut project, &mut ui_state, &display, &mut frame);
+            ui::draw(
+                &mut ui,
+                &mut project,
+                &mut ui_state,
+                &display,
+                &mut playback,
+                &mut history,
+            );
             platform.prepare_render(&ui, display.gl_window().window());
 
             // Clear frame buffer
@@ -145,14 +182,81 @@ fn main() {
                     write: true,
                     ..glium::Depth::default()
                 },
+                // Allow see-through objects
+                blend: glium::Blend::alpha_blending(),
                 ..glium::DrawParameters::default()
             };
 
+            // The frame to draw, as chosen by playback and the UI
+            let frame = playback.frame();
+
+            // Keyable camera attributes
+            camera.set_fov(project.fov.at(frame));
+
+            // World matrices of every object, parents included
+            let world_mats = if project.show_rest_pose {
+                project.world_mats(Object::model_mat_rest)
+            } else {
+                project.world_mats_at(frame as f32)
+            };
+
+            // Motion path of the selected object, drawn over the viewport by imgui
+            if let Some(index) = ui_state.selected_object() {
+                if ui_state.motion_path().enabled && !project.show_rest_pose {
+                    path_cache.set_revision(history.revision());
+                    overlay::motion_path(
+                        &ui,
+                        ui_state.motion_path(),
+                        &project,
+                        &mut path_cache,
+                        index,
+                        frame,
+                        camera.camera_mat(),
+                    );
+                }
+            }
+
+            // Bones of the selected object, drawn over the viewport by imgui
+            if let Some(index) = ui_state.selected_object() {
+                if let Some(armature) = &project.objs[index].armature {
+                    let poses = if project.show_rest_pose {
+                        armature.rest_poses()
+                    } else {
+                        armature.poses(frame as f32)
+                    };
+                    overlay::bones(
+                        &ui,
+                        armature,
+                        &poses,
+                        &world_mats[index],
+                        ui_state.selected_bone(),
+                        camera.camera_mat(),
+                    );
+                }
+            }
+
             // Draw objects in project
-            for obj in &mut project.objs {
-                let uniforms = uniform! {
-                    color: [1., 1., 1.],
-                    matrix: mat4_to_array(&(camera.camera_mat() * obj.model_mat_at(0)))
+            for (obj, model_mat) in project.objs.iter().zip(&world_mats) {
+                if !obj.visible.at(frame) {
+                    continue;
+                }
+
+                let color = obj.color.at(frame);
+                let (bones, morph_weights) = if project.show_rest_pose {
+                    (obj.rest_skin_mats(), obj.rest_morph_weights())
+                } else {
+                    (
+                        obj.skin_mats(frame as f32),
+                        obj.morph_weights_at(frame as f32),
+                    )
+                };
+                let uniforms = ObjectUniforms {
+                    tint: [color.x, color.y, color.z, color.w],
+                    matrix: camera.camera_mat() * model_mat,
+                    bones,
+                    dual_quat: obj.armature.as_ref().is_some_and(|a| a.dual_quaternion),
+                    mesh: &obj.mesh,
+                    morph_weights,
                 };
 
                 target
@@ -166,6 +270,57 @@ fn main() {
                     .unwrap();
             }
 
+            // Onion skinning: translucent ghosts of objects on nearby frames. They don't write
+            // depth, so they never hide each other
+            let onion = ui_state.onion_skin();
+            if onion.enabled && !project.show_rest_pose {
+                let ghosted: Vec<usize> = (0..project.objs.len())
+                    .filter(|&i| {
+                        if onion.all_animated {
+                            project.moves(i)
+                        } else {
+                            ui_state.selected_object() == Some(i)
+                        }
+                    })
+                    .collect();
+                let ghost_params = glium::draw_parameters::DrawParameters {
+                    depth: glium::Depth {
+                        test: glium::DepthTest::IfLess,
+                        write: false,
+                        ..glium::Depth::default()
+                    },
+                    ..draw_params.clone()
+                };
+
+                for (ghost_frame, tint) in onion.ghosts(frame) {
+                    let world_mats = project.world_mats_at(ghost_frame as f32);
+                    for &i in &ghosted {
+                        let obj = &project.objs[i];
+                        if !obj.visible.at(ghost_frame) {
+                            continue;
+                        }
+
+                        let uniforms = ObjectUniforms {
+                            tint,
+                            matrix: camera.camera_mat() * world_mats[i],
+                            bones: obj.skin_mats(ghost_frame as f32),
+                            dual_quat: obj.armature.as_ref().is_some_and(|a| a.dual_quaternion),
+                            mesh: &obj.mesh,
+                            morph_weights: obj.morph_weights_at(ghost_frame as f32),
+                        };
+                        target
+                            .draw(
+                                &obj.mesh.vb,
+                                &obj.mesh.ib,
+                                &program,
+                                &uniforms,
+                                &ghost_params,
+                            )
+                            .unwrap();
+                    }
+                }
+            }
+
             // Draw imgui ui
             renderer.render(&mut target, ui.render()).unwrap();
 
@@ -271,10 +426,11 @@ fn main() {
 
         // Misc event handling for imgui
         // =========================================================================================
-        // Update imgui internal frame time
+        // Update imgui internal frame time, and move playback along by the same amount of time
         Event::NewEvents(_) => {
             let now = Instant::now();
             imgui.io_mut().update_delta_time(now - last_frame);
+            playback.advance(project.frame_at_time((now - last_frame).as_secs_f32()));
             last_frame = now;
         }
 
@@ -296,6 +452,60 @@ fn main() {
     });
 }
 
+// Uniforms for drawing an object with the shaders in shaders.rs. bones are the skin matrices of the
+// object's armature, if it has one, and morph_weights the weights of its mesh's morph targets
+struct ObjectUniforms<'m> {
+    tint: [f32; 4],
+    matrix: glm::Mat4,
+    bones: Vec<glm::Mat4>,
+    dual_quat: bool,
+    mesh: &'m Mesh,
+    morph_weights: Vec<f32>,
+}
+
+impl Uniforms for ObjectUniforms<'_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut visit: F) {
+        visit("tint", UniformValue::Vec4(self.tint));
+        visit("matrix", UniformValue::Mat4(mat4_to_array(&self.matrix)));
+        visit(
+            "bone_count",
+            UniformValue::SignedInt(self.bones.len() as i32),
+        );
+        visit("dual_quat", UniformValue::Bool(self.dual_quat));
+        for (i, bone) in self.bones.iter().enumerate() {
+            visit(
+                &format!("bones[{}]", i),
+                UniformValue::Mat4(mat4_to_array(bone)),
+            );
+        }
+
+        // Morph targets are read texel by texel, so there's nothing to filter
+        let sampler = SamplerBehavior {
+            minify_filter: MinifySamplerFilter::Nearest,
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            ..SamplerBehavior::default()
+        };
+        visit(
+            "morphs",
+            UniformValue::Texture2d(&self.mesh.morph_texture, Some(sampler)),
+        );
+        visit(
+            "morph_count",
+            UniformValue::SignedInt(self.morph_weights.len() as i32),
+        );
+        visit(
+            "vertex_count",
+            UniformValue::SignedInt(self.mesh.vertices.len() as i32),
+        );
+        for (i, weight) in self.morph_weights.iter().enumerate() {
+            visit(
+                &format!("morph_weights[{}]", i),
+                UniformValue::Float(*weight),
+            );
+        }
+    }
+}
+
 fn mat4_to_array(m: &glm::Mat4) -> [[f32; 4]; 4] {
     [
         [m[0], m[1], m[2], m[3]],
@@ -1,23 +1,165 @@
 /*
 This file contains the MeshInternals struct, which represents a vertex buffer, and index buffer,
-and an associated name. The MeshInternals struct is wrapped in an Rc in the Mesh struct to provide
+and an associated name, along with copies of the vertices and indices for working with them on the
+CPU. The MeshInternals struct is wrapped in an Rc in the Mesh struct to provide
 cloning.
+Meshes can also have morph targets (blend shapes): other shapes with the same vertices, stored as
+how far each vertex moves. They are packed into a float texture, one texel per vertex per target,
+for the vertex shader to blend by each object's weights (see shaders.rs).
+Objects bound to an armature get their own skinned copy of their mesh, which remembers the mesh it
+was made from so that morph targets loaded onto that mesh later reach it too.
 
 TODO: (these structs  are pretty simple, probably will not need any new features)
 */
 use crate::vertex::Vertex;
+use glium::texture::{MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat};
 use glium::{index::PrimitiveType::TrianglesList, Display, IndexBuffer, VertexBuffer};
 use imgui::{ImStr, ImString};
 use std::{ops::Deref, rc::Rc};
 
+// Most morph targets a mesh can have, as many as the vertex shader has room for
+pub const MAX_MORPHS: usize = 32;
+
+// Width of the texture morph targets are packed into. Same as MORPH_TEXTURE_WIDTH in shaders.rs
+pub const MORPH_TEXTURE_WIDTH: usize = 1024;
+
 #[derive(Clone)]
 pub struct Mesh(Rc<MeshInternals>);
 
+// Another shape of a mesh, as how far each of its vertices moves from the mesh's own shape
+#[derive(Clone)]
+pub struct MorphTarget {
+    pub name: ImString,
+    pub deltas: Vec<[f32; 3]>,
+}
+
 impl Mesh {
     pub fn new(display: &Display, name: ImString, verts: &[Vertex], inds: &[u16]) -> Mesh {
+        Mesh::with_morphs(display, name, verts, inds, Vec::new(), None)
+    }
+
+    fn with_morphs(
+        display: &Display,
+        name: ImString,
+        verts: &[Vertex],
+        inds: &[u16],
+        morphs: Vec<MorphTarget>,
+        source: Option<Mesh>,
+    ) -> Mesh {
         let vb = VertexBuffer::new(display, verts).unwrap();
         let ib = IndexBuffer::new(display, TrianglesList, inds).unwrap();
-        Mesh(Rc::new(MeshInternals { name, vb, ib }))
+
+        // Every target's deltas one after another, in rows of MORPH_TEXTURE_WIDTH texels
+        let mut texels: Vec<f32> = morphs
+            .iter()
+            .flat_map(|m| m.deltas.iter().flatten().copied())
+            .collect();
+        let rows = (texels.len() / 3).div_ceil(MORPH_TEXTURE_WIDTH).max(1);
+        texels.resize(rows * MORPH_TEXTURE_WIDTH * 3, 0.);
+        let image = RawImage2d::from_raw_rgb(texels, (MORPH_TEXTURE_WIDTH as u32, rows as u32));
+        let morph_texture = Texture2d::with_format(
+            display,
+            image,
+            UncompressedFloatFormat::F32F32F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap();
+
+        Mesh(Rc::new(MeshInternals {
+            name,
+            vb,
+            ib,
+            vertices: verts.to_vec(),
+            indices: inds.to_vec(),
+            morphs,
+            morph_texture,
+            source,
+        }))
+    }
+
+    // A copy of the mesh with another morph target, given where each vertex is in the new shape.
+    // The shape must have the same vertices, in the same order, as the mesh
+    pub fn with_morph(
+        &self,
+        display: &Display,
+        name: ImString,
+        positions: &[[f32; 3]],
+    ) -> Result<Mesh, String> {
+        if self.morphs.len() >= MAX_MORPHS {
+            return Err(format!(
+                "Meshes can have at most {} morph targets",
+                MAX_MORPHS
+            ));
+        }
+        let deltas = morph_deltas(&self.vertices, positions).ok_or(format!(
+            "{} has {} vertices, but the morph target has {}",
+            self.name,
+            self.vertices.len(),
+            positions.len()
+        ))?;
+
+        let mut morphs = self.morphs.clone();
+        morphs.push(MorphTarget { name, deltas });
+        Ok(Mesh::with_morphs(
+            display,
+            self.name.clone(),
+            &self.vertices,
+            &self.indices,
+            morphs,
+            self.source.clone(),
+        ))
+    }
+
+    // Whether both are the same mesh, rather than copies of each other
+    pub fn same(&self, other: &Mesh) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    // Whether this is the given mesh or a skinned copy of it
+    pub fn made_from(&self, other: &Mesh) -> bool {
+        self.same(other) || self.source.as_ref().is_some_and(|s| s.same(other))
+    }
+
+    // A copy of the mesh with the given bones and weights for each vertex (see
+    // Armature::auto_weights)
+    pub fn skinned(&self, display: &Display, skin: &[([u32; 4], [f32; 4])]) -> Mesh {
+        let vertices: Vec<Vertex> = self
+            .vertices
+            .iter()
+            .zip(skin)
+            .map(|(v, &(joints, weights))| Vertex {
+                joints,
+                weights,
+                ..*v
+            })
+            .collect();
+
+        let name = match self.name.to_str().strip_suffix(" (skinned)") {
+            Some(_) => self.name.clone(),
+            None => ImString::new(format!("{} (skinned)", self.name)),
+        };
+        let source = self.source.clone().unwrap_or_else(|| self.clone());
+        Mesh::with_morphs(
+            display,
+            name,
+            &vertices,
+            &self.indices,
+            self.morphs.clone(),
+            Some(source),
+        )
+    }
+
+    // A skinned copy made from source, a new version of the mesh it was made from, keeping its
+    // bones and weights and taking source's morph targets
+    pub fn reskinned(&self, display: &Display, source: &Mesh) -> Mesh {
+        Mesh::with_morphs(
+            display,
+            self.name.clone(),
+            &self.vertices,
+            &self.indices,
+            source.morphs.clone(),
+            Some(source.clone()),
+        )
     }
 
     pub fn name_imstr(&self) -> &ImStr {
@@ -40,4 +182,51 @@ pub struct MeshInternals {
     // GL Buffers
     pub vb: VertexBuffer<Vertex>,
     pub ib: IndexBuffer<u16>,
+
+    // What the buffers were made from
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+
+    // Morph targets, and the texture they are packed into for the vertex shader
+    pub morphs: Vec<MorphTarget>,
+    pub morph_texture: Texture2d,
+
+    // Mesh a skinned copy was made from (see Mesh::skinned)
+    pub source: Option<Mesh>,
+}
+
+// How far each vertex moves to reach positions. None if the vertex counts don't match
+fn morph_deltas(vertices: &[Vertex], positions: &[[f32; 3]]) -> Option<Vec<[f32; 3]>> {
+    if vertices.len() != positions.len() {
+        return None;
+    }
+    let deltas = vertices
+        .iter()
+        .zip(positions)
+        .map(|(v, p)| {
+            [
+                p[0] - v.position[0],
+                p[1] - v.position[1],
+                p[2] - v.position[2],
+            ]
+        })
+        .collect();
+    Some(deltas)
+}
+
+// Mesh tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_morph_deltas_test() {
+        let vertices = [Vertex::new([0., 0., 0.]), Vertex::new([1., 2., 3.])];
+        let deltas = morph_deltas(&vertices, &[[0., 1., 0.], [1., 2., 5.]]).unwrap();
+        assert_eq!(deltas, vec![[0., 1., 0.], [0., 0., 2.]]);
+
+        // Shapes with different vertices can't be morphed between
+        assert!(morph_deltas(&vertices, &[[0., 0., 0.]]).is_none());
+    }
 }
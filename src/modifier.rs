@@ -0,0 +1,319 @@
+/*
+This file contains the Modifier struct, which post-processes a KeyframeV3 track after its
+keyframes are evaluated, e.g. adding noise for camera shake or flicker, or holding values to
+animate on twos. A channel has a stack of modifiers, each working on the result of the ones before
+it.
+Every modifier parameter is a Track, so it can be keyed like any other attribute. A modifier can
+also be limited to a range of frames, and faded in and out with its influence.
+
+TODO:
+*/
+use crate::animation::{Frame, KeyframeV3, Track};
+use glm::Vec3;
+use nalgebra_glm as glm;
+use std::f32::consts::TAU;
+
+#[derive(Clone)]
+pub struct Modifier {
+    pub kind: ModifierKind,
+
+    // How much of the modifier's effect is used, from 0 to 1
+    pub influence: Track<f32>,
+
+    // Frames the modifier is used on, inclusive. None for every frame
+    pub range: Option<(Frame, Frame)>,
+
+    pub muted: bool,
+}
+
+// What a modifier does. Frequencies are in cycles per frame and phases in cycles
+//   Noise: adds smooth random wobble, different on each axis. The same seed always gives the
+//          same noise
+//   Sine: adds a sine wave
+//   Stepped: holds the value for step frames at a time, e.g. 2 to animate on twos. Steps start on
+//            offset
+//   Limits: keeps each axis at or above min and at or below max, for whichever of them are used
+#[derive(Clone)]
+pub enum ModifierKind {
+    Noise {
+        seed: u32,
+        amplitude: KeyframeV3,
+        frequency: Track<f32>,
+        phase: Track<f32>,
+    },
+    Sine {
+        amplitude: KeyframeV3,
+        frequency: Track<f32>,
+        phase: Track<f32>,
+    },
+    Stepped {
+        step: Track<f32>,
+        offset: Track<f32>,
+    },
+    Limits {
+        min: KeyframeV3,
+        max: KeyframeV3,
+        use_min: bool,
+        use_max: bool,
+    },
+}
+
+impl Modifier {
+    pub fn new(kind: ModifierKind) -> Modifier {
+        Modifier {
+            kind,
+            influence: Track::new(1.),
+            range: None,
+            muted: false,
+        }
+    }
+
+    // Whether the modifier does anything on the given frame
+    pub fn active_at(&self, frame: f32) -> bool {
+        let in_range = match self.range {
+            Some((start, end)) => frame >= start as f32 && frame <= end as f32,
+            None => true,
+        };
+        !self.muted && in_range
+    }
+}
+
+impl ModifierKind {
+    pub fn noise(seed: u32) -> ModifierKind {
+        ModifierKind::Noise {
+            seed,
+            amplitude: Track::new(Vec3::new(0.1, 0.1, 0.1)),
+            frequency: Track::new(0.1),
+            phase: Track::new(0.),
+        }
+    }
+
+    pub fn sine() -> ModifierKind {
+        ModifierKind::Sine {
+            amplitude: Track::new(Vec3::new(1., 1., 1.)),
+            frequency: Track::new(1. / 24.),
+            phase: Track::new(0.),
+        }
+    }
+
+    pub fn stepped() -> ModifierKind {
+        ModifierKind::Stepped {
+            step: Track::new(2.),
+            offset: Track::new(0.),
+        }
+    }
+
+    // Limits starting out at the given value on every side, with neither side used yet
+    pub fn limits(value: Vec3) -> ModifierKind {
+        ModifierKind::Limits {
+            min: Track::new(value),
+            max: Track::new(value),
+            use_min: false,
+            use_max: false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModifierKind::Noise { .. } => "Noise",
+            ModifierKind::Sine { .. } => "Sine",
+            ModifierKind::Stepped { .. } => "Stepped",
+            ModifierKind::Limits { .. } => "Limits",
+        }
+    }
+}
+
+// Value of a track at any point in time, after a stack of modifiers. Modifiers are applied in
+// order, so each one works on the result of the ones before it
+pub fn evaluate(track: &KeyframeV3, modifiers: &[Modifier], frame: f32) -> Vec3 {
+    let (modifier, below) = match modifiers.split_last() {
+        Some(split) => split,
+        None => return track.sample(frame),
+    };
+    if !modifier.active_at(frame) {
+        return evaluate(track, below, frame);
+    }
+
+    // Parameters are always read on the frame being evaluated, even when stepping holds the value
+    // of an earlier frame
+    let influence = modifier.influence.sample(frame).clamp(0., 1.);
+    match &modifier.kind {
+        ModifierKind::Noise {
+            seed,
+            amplitude,
+            frequency,
+            phase,
+        } => {
+            let t = frame * frequency.sample(frame) + phase.sample(frame);
+            let amplitude = amplitude.sample(frame);
+            let noise = Vec3::new(
+                perlin(*seed, t),
+                perlin(seed.wrapping_add(1), t),
+                perlin(seed.wrapping_add(2), t),
+            );
+            evaluate(track, below, frame) + amplitude.component_mul(&noise) * influence
+        }
+
+        ModifierKind::Sine {
+            amplitude,
+            frequency,
+            phase,
+        } => {
+            let t = frame * frequency.sample(frame) + phase.sample(frame);
+            let wave = amplitude.sample(frame) * (t * TAU).sin();
+            evaluate(track, below, frame) + wave * influence
+        }
+
+        ModifierKind::Stepped { step, offset } => {
+            let step = step.sample(frame).max(0.01);
+            let offset = offset.sample(frame);
+            let held = ((frame - offset) / step).floor() * step + offset;
+            let held = evaluate(track, below, held);
+            if influence < 1. {
+                glm::lerp(&evaluate(track, below, frame), &held, influence)
+            } else {
+                held
+            }
+        }
+
+        ModifierKind::Limits {
+            min,
+            max,
+            use_min,
+            use_max,
+        } => {
+            let value = evaluate(track, below, frame);
+            let mut limited = value;
+            if *use_min {
+                limited = glm::max2(&limited, &min.sample(frame));
+            }
+            if *use_max {
+                limited = glm::min2(&limited, &max.sample(frame));
+            }
+            glm::lerp(&value, &limited, influence)
+        }
+    }
+}
+
+// 1D Perlin (gradient) noise, smooth in t and between -1 and 1. A random gradient is picked at
+// each whole t from the seed, so the same seed always gives the same noise
+fn perlin(seed: u32, t: f32) -> f32 {
+    let i = t.floor();
+    let x = t - i;
+    let g0 = gradient(seed, i as i32);
+    let g1 = gradient(seed, i as i32 + 1);
+
+    // Quintic fade, so the noise is smooth across whole values of t
+    let fade = x * x * x * (x * (x * 6. - 15.) + 10.);
+    let (n0, n1) = (g0 * x, g1 * (x - 1.));
+
+    // 1D gradient noise peaks at 0.5 with gradients between -1 and 1
+    (n0 + (n1 - n0) * fade) * 2.
+}
+
+// Gradient between -1 and 1 for a whole number, hashed with the seed
+fn gradient(seed: u32, i: i32) -> f32 {
+    let mut h = (i as u32).wrapping_mul(0x9E37_79B1) ^ seed.wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^= h >> 16;
+
+    h as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+// Modifier tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Goes from 0 on frame 0 to 10 on frame 10, on every axis
+    fn ramp() -> KeyframeV3 {
+        let mut track = Track::new(Vec3::zeros());
+        track.set_at(0, Vec3::zeros());
+        track.set_at(10, Vec3::new(10., 10., 10.));
+        track
+    }
+
+    #[test]
+    fn modifier_noise_test() {
+        let noise = [Modifier::new(ModifierKind::noise(7))];
+        let samples: Vec<Vec3> = (0..100)
+            .map(|i| evaluate(&ramp(), &noise, 5. + i as f32 * 0.037))
+            .collect();
+
+        // Deterministic, within the amplitude and not flat
+        let again = evaluate(&ramp(), &noise, 5. + 10. * 0.037);
+        assert_eq!(samples[10], again);
+        for (i, value) in samples.iter().enumerate() {
+            let base = 5. + i as f32 * 0.037;
+            assert!((value - Vec3::new(base, base, base)).amax() <= 0.1 + 1e-4);
+        }
+        assert!(samples.iter().any(|v| (v.x - 5.).abs() > 0.01));
+
+        // Other seeds and axes give other noise
+        let other = [Modifier::new(ModifierKind::noise(8))];
+        assert_ne!(
+            evaluate(&ramp(), &other, 5.5),
+            evaluate(&ramp(), &noise, 5.5)
+        );
+        let value = evaluate(&ramp(), &noise, 5.5);
+        assert_ne!(value.x, value.y);
+    }
+
+    #[test]
+    fn modifier_sine_test() {
+        let mut sine = Modifier::new(ModifierKind::sine());
+        sine.kind = ModifierKind::Sine {
+            amplitude: Track::new(Vec3::new(2., 0., 0.)),
+            frequency: Track::new(0.25),
+            phase: Track::new(0.),
+        };
+        let stack = [sine];
+
+        assert!((evaluate(&ramp(), &stack, 1.).x - 3.).abs() < 1e-5);
+        assert!((evaluate(&ramp(), &stack, 3.).x - 1.).abs() < 1e-5);
+        assert!((evaluate(&ramp(), &stack, 3.).y - 3.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn modifier_stepped_test() {
+        let stack = [Modifier::new(ModifierKind::stepped())];
+        assert_eq!(evaluate(&ramp(), &stack, 4.).x, 4.);
+        assert_eq!(evaluate(&ramp(), &stack, 5.).x, 4.);
+        assert_eq!(evaluate(&ramp(), &stack, 5.9).x, 4.);
+
+        // Half influence is halfway between stepped and smooth
+        let mut half = Modifier::new(ModifierKind::stepped());
+        half.influence = Track::new(0.5);
+        assert_eq!(evaluate(&ramp(), &[half], 5.).x, 4.5);
+    }
+
+    #[test]
+    fn modifier_limits_test() {
+        let mut limits = Modifier::new(ModifierKind::Limits {
+            min: Track::new(Vec3::new(2., 2., 2.)),
+            max: Track::new(Vec3::new(8., 8., 8.)),
+            use_min: false,
+            use_max: true,
+        });
+        assert_eq!(evaluate(&ramp(), &[limits.clone()], 1.).x, 1.);
+        assert_eq!(evaluate(&ramp(), &[limits.clone()], 9.).x, 8.);
+
+        // Only within the frame range, and faded by influence
+        limits.range = Some((0, 8));
+        assert_eq!(evaluate(&ramp(), &[limits.clone()], 9.).x, 9.);
+        limits.range = None;
+        limits.influence = Track::new(0.5);
+        assert_eq!(evaluate(&ramp(), &[limits.clone()], 10.).x, 9.);
+
+        // Stacked modifiers work on the result of the ones before them
+        limits.influence = Track::new(1.);
+        let stack = [limits.clone(), Modifier::new(ModifierKind::stepped())];
+        assert_eq!(evaluate(&ramp(), &stack, 9.).x, 8.);
+        limits.muted = true;
+        assert_eq!(evaluate(&ramp(), &[limits], 9.).x, 9.);
+    }
+}
@@ -0,0 +1,381 @@
+/*
+This file contains the Clip and Strip structs making up non-linear animation (NLA). A Clip is a
+named, reusable piece of position, rotation and scale animation stored on the Project, e.g. one
+walk or spin cycle. Objects play clips through Strips, which place a clip on the timeline with its
+own start frame, speed, repeat count and fades, so the same clip can be reused across many objects
+and frame ranges.
+Strips are blended on top of an object's own tracks, in order, each either replacing what is
+below it or adding to it. Clip rotations are always quaternions so they blend smoothly.
+
+TODO:
+    * Editing clips after they are made
+*/
+use crate::animation::{Frame, KeyframeQuat, KeyframeV3, Track};
+use crate::interpolate::Interpolate;
+use crate::object::trs_mat;
+use crate::rotation::{euler_track_to_quat_checked, slerp};
+use glm::{Quat, Vec3};
+use imgui::ImString;
+use nalgebra_glm as glm;
+
+// An object's position, rotation and scale at one point in time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn mat(&self) -> glm::Mat4 {
+        trs_mat(
+            &self.position,
+            &glm::quat_to_mat4(&self.rotation),
+            &self.scale,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Clip {
+    pub name: ImString,
+    pub position: KeyframeV3,
+    pub rotation: KeyframeQuat,
+    pub scale: KeyframeV3,
+}
+
+impl Clip {
+    // A clip made from copies of position, Euler rotation and scale tracks. Fails if nothing is
+    // keyed, or if the rotation can't be turned into quaternions without changing how it moves
+    pub fn from_euler(
+        name: ImString,
+        position: &KeyframeV3,
+        rotation: &KeyframeV3,
+        scale: &KeyframeV3,
+    ) -> Result<Clip, String> {
+        Clip {
+            name,
+            position: position.clone(),
+            rotation: euler_track_to_quat_checked(rotation)?,
+            scale: scale.clone(),
+        }
+        .keyed()
+    }
+
+    // The clip, or an error if nothing in it is keyed
+    pub fn keyed(self) -> Result<Clip, String> {
+        match self.range() {
+            Some(_) => Ok(self),
+            None => Err("There are no position, rotation or scale keyframes".to_string()),
+        }
+    }
+
+    // The keyed range of the clip, from the first keyframe of any track to the last. None if
+    // nothing is keyed
+    pub fn range(&self) -> Option<(Frame, Frame)> {
+        let ranges = [
+            keyed_range(&self.position),
+            keyed_range(&self.rotation),
+            keyed_range(&self.scale),
+        ];
+
+        ranges.iter().flatten().fold(None, |range, &(first, last)| {
+            Some(match range {
+                Some((a, b)) => (first.min(a), last.max(b)),
+                None => (first, last),
+            })
+        })
+    }
+
+    // Frames one play through the clip lasts. A clip keyed on a single frame lasts one frame
+    pub fn length(&self) -> f32 {
+        self.range().map_or(1., |(a, b)| (b - a).max(1) as f32)
+    }
+
+    pub fn sample(&self, frame: f32) -> Transform {
+        Transform {
+            position: self.position.sample(frame),
+            rotation: self.rotation.sample(frame),
+            scale: self.scale.sample(frame),
+        }
+    }
+}
+
+// How a strip combines with the animation below it
+//   Replace: fades from the animation below to the clip, by the strip's weight
+//   Additive: adds the change the clip makes since its first frame, scaled by the strip's weight
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StripMode {
+    Replace,
+    Additive,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Strip {
+    // Index of the clip in Project::clips
+    pub clip: usize,
+
+    // Frame the strip starts on
+    pub start: f32,
+
+    // How fast the clip plays. 2 plays it twice as fast, making the strip half as long
+    pub time_scale: f32,
+
+    // How many times the clip plays back to back. Can be fractional
+    pub repeat: f32,
+
+    // Frames taken to fade the strip in from its start and out to its end
+    pub blend_in: f32,
+    pub blend_out: f32,
+
+    pub mode: StripMode,
+    pub muted: bool,
+}
+
+impl Strip {
+    pub fn new(clip: usize, start: f32) -> Strip {
+        Strip {
+            clip,
+            start,
+            time_scale: 1.,
+            repeat: 1.,
+            blend_in: 0.,
+            blend_out: 0.,
+            mode: StripMode::Replace,
+            muted: false,
+        }
+    }
+
+    // Frames the whole strip lasts, repeats included
+    pub fn length(&self, clip: &Clip) -> f32 {
+        clip.length() * self.repeat.max(0.) / self.time_scale.max(f32::EPSILON)
+    }
+
+    pub fn end(&self, clip: &Clip) -> f32 {
+        self.start + self.length(clip)
+    }
+
+    // The frame of the clip played at the given frame, along with how much the strip counts there
+    // (0 to 1) given its fades. None outside of the strip
+    pub fn clip_frame(&self, clip: &Clip, frame: f32) -> Option<(f32, f32)> {
+        let end = self.end(clip);
+        if self.muted || frame < self.start || frame > end {
+            return None;
+        }
+
+        // Time since the strip started, in the clip's frames
+        let len = clip.length();
+        let total = len * self.repeat.max(0.);
+        let local = (frame - self.start) * self.time_scale.max(f32::EPSILON);
+
+        // Each repeat starts over from the clip's first frame, except that the very end of the
+        // strip shows the end of the last repeat rather than the start of another one
+        let local = if local >= total {
+            total - (self.repeat.ceil() - 1.).max(0.) * len
+        } else {
+            local % len
+        };
+        let first = clip.range().map_or(0, |(a, _)| a) as f32;
+
+        let mut weight: f32 = 1.;
+        if self.blend_in > 0. {
+            weight = weight.min((frame - self.start) / self.blend_in);
+        }
+        if self.blend_out > 0. {
+            weight = weight.min((end - frame) / self.blend_out);
+        }
+
+        Some((first + local, weight.clamp(0., 1.)))
+    }
+}
+
+// First and last keyframes of a track, if it has any
+fn keyed_range<T: Interpolate>(track: &Track<T>) -> Option<(Frame, Frame)> {
+    let mut frames = track.frames()?;
+    let first = *frames.next()?;
+    Some((first, frames.next_back().copied().unwrap_or(first)))
+}
+
+// Blends each strip on top of base in order, returning the end result. Strips whose clip doesn't
+// exist are skipped
+pub fn evaluate(base: Transform, strips: &[Strip], clips: &[Clip], frame: f32) -> Transform {
+    let mut out = base;
+
+    for strip in strips {
+        let clip = match clips.get(strip.clip) {
+            Some(clip) => clip,
+            None => continue,
+        };
+        let (clip_frame, weight) = match strip.clip_frame(clip, frame) {
+            Some(found) => found,
+            None => continue,
+        };
+        let value = clip.sample(clip_frame);
+
+        match strip.mode {
+            StripMode::Replace => {
+                out.position = glm::lerp(&out.position, &value.position, weight);
+                out.rotation = slerp(&out.rotation, &value.rotation, weight);
+                out.scale = glm::lerp(&out.scale, &value.scale, weight);
+            }
+            StripMode::Additive => {
+                let first = clip.range().map_or(0, |(a, _)| a) as f32;
+                let reference = clip.sample(first);
+                let turn = value.rotation * glm::quat_inverse(&reference.rotation);
+
+                out.position += (value.position - reference.position) * weight;
+                out.rotation = slerp(&Quat::identity(), &turn, weight) * out.rotation;
+                out.scale += (value.scale - reference.scale) * weight;
+            }
+        }
+    }
+
+    out
+}
+
+// NLA tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Moves from x = 0 on frame 10 to x = 10 on frame 20
+    fn slide() -> Clip {
+        let mut position = Track::new(Vec3::zeros());
+        position.set_at(10, Vec3::zeros());
+        position.set_at(20, Vec3::new(10., 0., 0.));
+
+        Clip {
+            name: ImString::new("Slide"),
+            position,
+            rotation: Track::new(Quat::identity()),
+            scale: Track::new(Vec3::new(1., 1., 1.)),
+        }
+    }
+
+    fn base() -> Transform {
+        Transform {
+            position: Vec3::new(0., 5., 0.),
+            rotation: Quat::identity(),
+            scale: Vec3::new(1., 1., 1.),
+        }
+    }
+
+    fn x_at(strips: &[Strip], frame: f32) -> f32 {
+        evaluate(base(), strips, &[slide()], frame).position.x
+    }
+
+    #[test]
+    fn clip_range_test() {
+        let clip = slide();
+        assert_eq!(clip.range(), Some((10, 20)));
+        assert_eq!(clip.length(), 10.);
+
+        let mut empty = slide();
+        empty.position.clear_keys();
+        assert_eq!(empty.range(), None);
+        assert_eq!(empty.length(), 1.);
+    }
+
+    #[test]
+    fn clip_from_euler_test() {
+        use crate::animation::{Extrapolation, HandleSide, HandleType};
+        use crate::rotation::euler_to_quat;
+
+        // A quarter turn around z and back, with a bezier handle easing out of the first key,
+        // cycling after the last key
+        let mut euler = Track::new(Vec3::zeros());
+        euler.set_at(0, Vec3::zeros());
+        euler.set_at(10, Vec3::new(0., 0., 1.5));
+        euler.set_at(20, Vec3::zeros());
+        euler.set_handle_type(0, HandleSide::Out, HandleType::Free);
+        euler.set_handle(0, HandleSide::Out, 5., Vec3::new(0., 0., 0.2));
+        euler.set_extrapolation(Extrapolation::Constant, Extrapolation::Cycle);
+        let still = Track::new(Vec3::zeros());
+
+        // The handle and cycle come along, and the clip rotates like the Euler track did along the
+        // handle, and on every keyframe and its repeats
+        let clip = Clip::from_euler(ImString::new("Turn"), &still, &euler, &still).unwrap();
+        assert!(clip.rotation.key_at(0).unwrap().handle_out.is_some());
+        let modes = (Extrapolation::Constant, Extrapolation::Cycle);
+        assert_eq!(clip.rotation.extrapolation(), Some(modes));
+        for frame in [0., 2.5, 5., 7.5, 10., 20., 22.5, 30.] {
+            let q = clip.rotation.sample(frame);
+            let expected = euler_to_quat(&euler.sample(frame));
+            let angle = glm::quat_angle(&(q * glm::quat_inverse(&expected)));
+            let angle = angle.min(2. * std::f32::consts::PI - angle);
+            assert!(angle < 0.02, "frame {}: off by {}", frame, angle);
+        }
+
+        // Continuing an Euler rotation in a straight line can't be done with quaternions
+        euler.set_extrapolation(Extrapolation::Constant, Extrapolation::Linear);
+        assert!(Clip::from_euler(ImString::new("Spin"), &still, &euler, &still).is_err());
+
+        // Nor can a clip be made from nothing
+        assert!(Clip::from_euler(ImString::new("Empty"), &still, &still, &still).is_err());
+    }
+
+    #[test]
+    fn strip_timing_test() {
+        let clip = slide();
+        let mut strip = Strip::new(0, 100.);
+
+        // Plays the clip's keyed range from the strip's start
+        assert_eq!(strip.clip_frame(&clip, 99.), None);
+        assert_eq!(strip.clip_frame(&clip, 100.), Some((10., 1.)));
+        assert_eq!(strip.clip_frame(&clip, 110.), Some((20., 1.)));
+        assert_eq!(strip.clip_frame(&clip, 111.), None);
+
+        // Twice as fast, two and a half times
+        strip.time_scale = 2.;
+        strip.repeat = 2.5;
+        assert_eq!(strip.end(&clip), 112.5);
+        assert_eq!(strip.clip_frame(&clip, 102.5), Some((15., 1.)));
+        assert_eq!(strip.clip_frame(&clip, 105.), Some((10., 1.)));
+        assert_eq!(strip.clip_frame(&clip, 112.5), Some((15., 1.)));
+
+        // Fades
+        strip.blend_in = 2.;
+        strip.blend_out = 5.;
+        assert_eq!(strip.clip_frame(&clip, 101.).unwrap().1, 0.5);
+        assert_eq!(strip.clip_frame(&clip, 106.).unwrap().1, 1.);
+        assert_eq!(strip.clip_frame(&clip, 111.5).unwrap().1, 0.2);
+
+        strip.muted = true;
+        assert_eq!(strip.clip_frame(&clip, 106.), None);
+    }
+
+    #[test]
+    fn strip_blend_test() {
+        let mut strip = Strip::new(0, 0.);
+        strip.blend_in = 10.;
+
+        // Replace fades from the base to the clip
+        assert_eq!(x_at(&[strip.clone()], 0.), 0.);
+        assert_eq!(x_at(&[strip.clone()], 5.), 2.5);
+        assert_eq!(x_at(&[strip.clone()], 10.), 10.);
+        assert_eq!(x_at(&[strip.clone()], 11.), 0.);
+
+        // Replacing the y the base has
+        let y = evaluate(base(), &[strip.clone()], &[slide()], 10.)
+            .position
+            .y;
+        assert_eq!(y, 0.);
+
+        // Additive strips add on to what is below
+        strip.mode = StripMode::Additive;
+        strip.blend_in = 0.;
+        let y = evaluate(base(), &[strip.clone()], &[slide()], 5.)
+            .position
+            .y;
+        assert_eq!(y, 5.);
+        let other = Strip {
+            start: 5.,
+            ..strip.clone()
+        };
+        assert_eq!(x_at(&[strip, other], 10.), 15.);
+
+        // Strips of missing clips do nothing
+        assert_eq!(x_at(&[Strip::new(3, 0.)], 5.), 0.);
+    }
+}
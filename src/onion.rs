@@ -0,0 +1,111 @@
+/*
+This file contains the settings for onion skinning, which draws translucent ghosts of objects as
+they are on nearby frames so that spacing and arcs can be judged while posing. Ghosts on past
+frames and future frames are tinted differently, and fade out the further they are from the
+current frame.
+
+TODO:
+    * Ghosts on keyframes only
+*/
+use crate::animation::Frame;
+use std::cmp::Reverse;
+
+#[derive(Copy, Clone)]
+pub struct OnionSkin {
+    pub enabled: bool,
+
+    // Ghost every animated object rather than just the selected one
+    pub all_animated: bool,
+
+    // How many ghosts are drawn before and after the current frame, and how many frames apart
+    pub before: u32,
+    pub after: u32,
+    pub step: u32,
+
+    // Opacity of the nearest ghosts
+    pub opacity: f32,
+
+    pub past_color: [f32; 3],
+    pub future_color: [f32; 3],
+}
+
+impl Default for OnionSkin {
+    fn default() -> OnionSkin {
+        OnionSkin {
+            enabled: false,
+            all_animated: false,
+            before: 2,
+            after: 2,
+            step: 2,
+            opacity: 0.3,
+            past_color: [1., 0.3, 0.3],
+            future_color: [0.3, 1., 0.3],
+        }
+    }
+}
+
+impl OnionSkin {
+    // The frame and tint of each ghost shown around current_frame, furthest first so that nearer
+    // ghosts are drawn on top. Frames before the first are left out
+    pub fn ghosts(&self, current_frame: Frame) -> Vec<(Frame, [f32; 4])> {
+        let step = self.step.max(1) as Frame;
+        let mut ghosts = Vec::new();
+
+        for (count, color, past) in [
+            (self.before, self.past_color, true),
+            (self.after, self.future_color, false),
+        ] {
+            for k in 1..=count as Frame {
+                let frame = if past {
+                    match current_frame.checked_sub(k * step) {
+                        Some(frame) => frame,
+                        None => break,
+                    }
+                } else {
+                    current_frame + k * step
+                };
+
+                // Fades out linearly, with the furthest ghost still faintly visible
+                let fade = 1. - (k - 1) as f32 / count as f32;
+                let [r, g, b] = color;
+                ghosts.push((k, frame, [r, g, b, self.opacity * fade]));
+            }
+        }
+
+        ghosts.sort_by_key(|ghost| Reverse(ghost.0));
+        ghosts
+            .into_iter()
+            .map(|(_, frame, tint)| (frame, tint))
+            .collect()
+    }
+}
+
+// Onion skin tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn onion_ghosts_test() {
+        let onion = OnionSkin {
+            before: 2,
+            after: 1,
+            step: 3,
+            opacity: 0.5,
+            ..OnionSkin::default()
+        };
+
+        // Furthest first, tinted by which side of the current frame they are on
+        let ghosts = onion.ghosts(10);
+        let frames: Vec<Frame> = ghosts.iter().map(|g| g.0).collect();
+        assert_eq!(frames, vec![4, 7, 13]);
+        assert_eq!(ghosts[1].1, [1., 0.3, 0.3, 0.5]);
+        assert_eq!(ghosts[0].1[3], 0.25);
+        assert_eq!(ghosts[2].1, [0.3, 1., 0.3, 0.5]);
+
+        // Nothing before the first frame
+        let frames: Vec<Frame> = onion.ghosts(4).iter().map(|g| g.0).collect();
+        assert_eq!(frames, vec![1, 7]);
+    }
+}
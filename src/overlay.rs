@@ -0,0 +1,182 @@
+/*
+This file contains drawing done over the 3D viewport with imgui's background draw list, such as
+motion paths and bones. It is drawn on top of objects, but behind every window. Everything is
+worked out in world space and then projected onto the screen with the camera's matrix.
+
+TODO:
+    * Hide parts of paths that are behind objects
+*/
+use crate::animation::Frame;
+use crate::armature::Armature;
+use crate::nla::Transform;
+use crate::project::Project;
+use imgui::Ui;
+use nalgebra_glm as glm;
+
+// Settings for showing the path the selected object takes through the world around the current
+// frame
+#[derive(Copy, Clone)]
+pub struct MotionPath {
+    pub enabled: bool,
+
+    // Frames shown before and after the current frame
+    pub before: u32,
+    pub after: u32,
+
+    // Frame numbers are shown on frames that are a multiple of this
+    pub label_every: u32,
+}
+
+impl Default for MotionPath {
+    fn default() -> MotionPath {
+        MotionPath {
+            enabled: false,
+            before: 24,
+            after: 24,
+            label_every: 5,
+        }
+    }
+}
+
+// World positions a motion path goes through, kept from one UI frame to the next. Working them out
+// evaluates the whole project once per frame of the path, so it is only done again when the
+// project, the object or the frames shown change
+#[derive(Default)]
+pub struct PathCache {
+    // Project revision (see History::revision), object index, first frame and last frame the
+    // positions are for
+    key: Option<(u64, usize, Frame, Frame)>,
+    revision: u64,
+    positions: Vec<glm::Vec4>,
+}
+
+impl PathCache {
+    // Lets the cache know which revision of the project is being drawn
+    pub fn set_revision(&mut self, revision: u64) {
+        self.revision = revision;
+    }
+
+    fn positions(
+        &mut self,
+        project: &Project,
+        index: usize,
+        first: Frame,
+        last: Frame,
+    ) -> &[glm::Vec4] {
+        let key = Some((self.revision, index, first, last));
+        if self.key != key {
+            self.key = key;
+            self.positions = (first..=last)
+                .map(|frame| project.world_mats_at(frame as f32)[index] * glm::vec4(0., 0., 0., 1.))
+                .collect();
+        }
+        &self.positions
+    }
+}
+
+// Draws the motion path of the object at index. Each frame gets a dot, so uneven spacing stands
+// out, and keyframes get a bigger one
+pub fn motion_path(
+    ui: &Ui,
+    settings: &MotionPath,
+    project: &Project,
+    cache: &mut PathCache,
+    index: usize,
+    current_frame: Frame,
+    camera_mat: &glm::Mat4,
+) {
+    let first = current_frame.saturating_sub(settings.before as Frame);
+    let last = current_frame + settings.after as Frame;
+    let size = ui.io().display_size;
+
+    // World positions, projected onto the screen. None where behind the camera
+    let points: Vec<(Frame, Option<[f32; 2]>)> = (first..=last)
+        .zip(cache.positions(project, index, first, last))
+        .map(|(frame, world)| (frame, to_screen(camera_mat, world, size)))
+        .collect();
+    let keys = project.objs[index].transform_key_frames();
+
+    let draw_list = ui.get_background_draw_list();
+    for pair in points.windows(2) {
+        if let ((frame, Some(a)), (_, Some(b))) = (pair[0], pair[1]) {
+            let color = if frame < current_frame {
+                [0.5, 0.7, 1., 0.8]
+            } else {
+                [1., 0.7, 0.4, 0.8]
+            };
+            draw_list.add_line(a, b, color).thickness(2.).build();
+        }
+    }
+
+    for (frame, point) in points {
+        let [x, y] = match point {
+            Some(point) => point,
+            None => continue,
+        };
+
+        let (radius, color) = if frame == current_frame {
+            (5., [1., 0.2, 0.2, 1.])
+        } else if keys.contains(&frame) {
+            (4., [1., 0.85, 0.2, 1.])
+        } else {
+            (2., [1., 1., 1., 0.8])
+        };
+        draw_list
+            .add_circle([x, y], radius, color)
+            .filled(true)
+            .build();
+
+        if settings.label_every > 0 && frame % settings.label_every as Frame == 0 {
+            draw_list.add_text([x + 6., y - 6.], [1., 1., 1., 0.9], format!("{}", frame));
+        }
+    }
+}
+
+// Draws each bone of an armature posed, as a line from its head to its tail with a dot on its head.
+// world is the world matrix of the object the armature belongs to
+pub fn bones(
+    ui: &Ui,
+    armature: &Armature,
+    poses: &[Transform],
+    world: &glm::Mat4,
+    selected: Option<usize>,
+    camera_mat: &glm::Mat4,
+) {
+    let size = ui.io().display_size;
+    let draw_list = ui.get_background_draw_list();
+
+    let pose_mats = armature.pose_mats(poses);
+    for (i, (bone, pose)) in armature.bones.iter().zip(pose_mats).enumerate() {
+        let mat = world * pose;
+        let tail = bone.tail - bone.head;
+        let head = to_screen(camera_mat, &(mat * glm::vec4(0., 0., 0., 1.)), size);
+        let tail = to_screen(
+            camera_mat,
+            &(mat * glm::vec4(tail.x, tail.y, tail.z, 1.)),
+            size,
+        );
+
+        let color = if selected == Some(i) {
+            [1., 0.6, 0.2, 1.]
+        } else {
+            [0.8, 0.8, 0.8, 0.9]
+        };
+        if let (Some(head), Some(tail)) = (head, tail) {
+            draw_list.add_line(head, tail, color).thickness(3.).build();
+        }
+        if let Some(head) = head {
+            draw_list.add_circle(head, 4., color).filled(true).build();
+        }
+    }
+}
+
+// Screen position in pixels of a point in world space, or None if it is behind the camera
+fn to_screen(camera_mat: &glm::Mat4, world: &glm::Vec4, [w, h]: [f32; 2]) -> Option<[f32; 2]> {
+    let clip = camera_mat * world;
+    if clip.w <= 0.0001 {
+        return None;
+    }
+
+    let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+    Some([(x + 1.) / 2. * w, (1. - y) / 2. * h])
+}
@@ -0,0 +1,310 @@
+/*
+This file contains the Playback struct, the clock that decides which frame of the project is shown.
+It is advanced by the real time between frames of the main loop, so animation plays back at the
+project's frame rate no matter how fast frames are drawn. When drawing is slow, frames are skipped
+rather than played back slower.
+Playback is limited to a range of frames, which can play once, loop or ping-pong back and forth.
+
+TODO:
+*/
+use crate::animation::Frame;
+
+pub struct Playback {
+    state: PlayState,
+    mode: LoopMode,
+
+    // Current position, in (possibly fractional) frames
+    position: f32,
+
+    // Frames played back, inclusive
+    start: Frame,
+    end: Frame,
+
+    // Multiplier on the project's frame rate. Negative speeds play backwards
+    pub speed: f32,
+
+    // Whether ping-pong playback is currently going forwards through the range
+    forward: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlayState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+// What happens when playback reaches the end of its range
+//   Once: playback pauses on the last frame
+//   Loop: playback jumps back to the first frame
+//   PingPong: playback turns around and plays the range backwards, then forwards again
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoopMode {
+    Once,
+    Loop,
+    PingPong,
+}
+
+impl Default for Playback {
+    fn default() -> Playback {
+        Playback {
+            state: PlayState::Stopped,
+            mode: LoopMode::Loop,
+            position: 0.,
+            start: 0,
+            end: 100,
+            speed: 1.,
+            forward: true,
+        }
+    }
+}
+
+impl Playback {
+    pub fn new() -> Playback {
+        Playback::default()
+    }
+
+    // The whole frame currently shown
+    pub fn frame(&self) -> Frame {
+        self.position.max(0.) as Frame
+    }
+
+    pub fn state(&self) -> PlayState {
+        self.state
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state == PlayState::Playing
+    }
+
+    pub fn play(&mut self) {
+        // Playing a range that already finished starts it over
+        if self.mode == LoopMode::Once && self.finished() {
+            self.position = self.start_position();
+        }
+
+        self.state = PlayState::Playing;
+    }
+
+    pub fn pause(&mut self) {
+        if self.state == PlayState::Playing {
+            self.state = PlayState::Paused;
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        if self.is_playing() {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    // Stops playback and goes back to the start of the range
+    pub fn stop(&mut self) {
+        self.state = PlayState::Stopped;
+        self.position = self.start as f32;
+        self.forward = true;
+    }
+
+    // Jumps to a frame, e.g. when scrubbing. Playback carries on from there if it is playing
+    pub fn seek(&mut self, frame: Frame) {
+        self.position = frame as f32;
+        if self.state == PlayState::Stopped {
+            self.state = PlayState::Paused;
+        }
+    }
+
+    pub fn mode(&self) -> LoopMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: LoopMode) {
+        self.mode = mode;
+        self.forward = true;
+    }
+
+    pub fn range(&self) -> (Frame, Frame) {
+        (self.start, self.end)
+    }
+
+    // Sets the frames to play back. If start is after end they are swapped. The current position
+    // is moved into the new range
+    pub fn set_range(&mut self, start: Frame, end: Frame) {
+        self.start = start.min(end);
+        self.end = start.max(end);
+        self.position = self.position.clamp(self.start as f32, self.end as f32);
+    }
+
+    // Moves playback forward by the given number of frames of real time, e.g. the time since the
+    // last drawn frame multiplied by the project's frame rate. Does nothing unless playing
+    pub fn advance(&mut self, frames: f32) {
+        if !self.is_playing() {
+            return;
+        }
+
+        let step = frames * self.speed;
+        let (start, end) = (self.start as f32, self.end as f32);
+
+        match self.mode {
+            LoopMode::Once => {
+                self.position += step;
+                if self.finished() {
+                    self.position = self.position.clamp(start, end);
+                    self.state = PlayState::Paused;
+                }
+            }
+
+            // The last frame is shown for a whole frame before jumping back to the first
+            LoopMode::Loop => {
+                let len = end - start + 1.;
+                self.position = start + (self.position + step - start).rem_euclid(len);
+            }
+
+            // Bounces between the first and last frames. One cycle is there and back again
+            LoopMode::PingPong => {
+                let len = end - start;
+                if len == 0. {
+                    self.position = start;
+                    return;
+                }
+
+                let offset = self.position.clamp(start, end) - start;
+                let phase = if self.forward {
+                    offset
+                } else {
+                    2. * len - offset
+                };
+                let phase = (phase + step).rem_euclid(2. * len);
+
+                self.forward = phase <= len;
+                self.position = if self.forward {
+                    start + phase
+                } else {
+                    start + 2. * len - phase
+                };
+            }
+        }
+    }
+
+    // Whether playing once has run off the end of the range in the direction of play
+    fn finished(&self) -> bool {
+        if self.speed < 0. {
+            self.position <= self.start as f32
+        } else {
+            self.position >= self.end as f32
+        }
+    }
+
+    // Where playing once starts from, depending on the direction of play
+    fn start_position(&self) -> f32 {
+        if self.speed < 0. {
+            self.end as f32
+        } else {
+            self.start as f32
+        }
+    }
+}
+
+// Playback tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(mode: LoopMode, start: Frame, end: Frame) -> Playback {
+        let mut p = Playback::new();
+        p.set_range(start, end);
+        p.set_mode(mode);
+        p.stop();
+        p.play();
+        p
+    }
+
+    #[test]
+    fn playback_loop_test() {
+        let mut p = playing(LoopMode::Loop, 10, 19);
+        assert_eq!(p.frame(), 10);
+
+        // Fractional frames build up into whole ones
+        p.advance(0.6);
+        assert_eq!(p.frame(), 10);
+        p.advance(0.6);
+        assert_eq!(p.frame(), 11);
+
+        // Slow drawing skips frames instead of slowing down
+        p.advance(5.0);
+        assert_eq!(p.frame(), 16);
+
+        // The last frame is shown before wrapping around
+        p.advance(3.0);
+        assert_eq!(p.frame(), 19);
+        p.advance(1.0);
+        assert_eq!(p.frame(), 10);
+
+        // Speed multiplies time
+        p.speed = 2.0;
+        p.advance(2.0);
+        assert_eq!(p.frame(), 14);
+
+        // Paused playback doesn't move
+        p.pause();
+        p.advance(3.0);
+        assert_eq!(p.frame(), 14);
+
+        // Stopping goes back to the start
+        p.stop();
+        assert_eq!(p.frame(), 10);
+        assert_eq!(p.state(), PlayState::Stopped);
+    }
+
+    #[test]
+    fn playback_once_test() {
+        let mut p = playing(LoopMode::Once, 0, 10);
+        p.advance(8.0);
+        assert_eq!(p.frame(), 8);
+        p.advance(8.0);
+        assert_eq!(p.frame(), 10);
+        assert_eq!(p.state(), PlayState::Paused);
+
+        // Playing again starts over
+        p.play();
+        assert_eq!(p.frame(), 0);
+
+        // Backwards playback stops at the start
+        p.speed = -1.0;
+        p.seek(5);
+        p.play();
+        p.advance(9.0);
+        assert_eq!(p.frame(), 0);
+        assert!(!p.is_playing());
+    }
+
+    #[test]
+    fn playback_ping_pong_test() {
+        let mut p = playing(LoopMode::PingPong, 0, 10);
+        p.advance(7.0);
+        assert_eq!(p.frame(), 7);
+
+        // Bounces off the end
+        p.advance(5.0);
+        assert_eq!(p.frame(), 8);
+        p.advance(8.0);
+        assert_eq!(p.frame(), 0);
+
+        // And off the start
+        p.advance(4.0);
+        assert_eq!(p.frame(), 4);
+
+        // Several bounces in one step
+        p.advance(20.0);
+        assert_eq!(p.frame(), 4);
+        p.advance(1.0);
+        assert_eq!(p.frame(), 5);
+
+        // Empty ranges stay put
+        p.set_range(3, 3);
+        p.advance(2.5);
+        assert_eq!(p.frame(), 3);
+    }
+}
@@ -5,33 +5,209 @@ the current working project.
 
 TODO:
 */
-use crate::{mesh::Mesh, object::Object, vertex::Vertex};
+use crate::animation::{Frame, Track};
+use crate::constraint::{Constraint, Solver};
+use crate::driver::{self, Drivers};
+use crate::nla::{Clip, Strip};
+use crate::{hierarchy, mesh::Mesh, object::Object, vertex::Vertex};
 use glium::Display;
 use imgui::ImString;
+use nalgebra_glm as glm;
 use std::{fs::File, io::BufReader, path::Path};
 
-#[derive(Default)]
 pub struct Project {
     pub meshes: Vec<Mesh>,
     pub objs: Vec<Object>,
+
+    // Reusable animation, played on objects through their strips
+    pub clips: Vec<Clip>,
+
+    // Vertical field of view of the viewport camera, in degrees
+    pub fov: Track<f32>,
+
+    // Frames per second, for converting between frames and real time
+    pub fps: f32,
+
+    // Whether to draw objects at their rest values instead of animating them
+    pub show_rest_pose: bool,
+}
+
+impl Default for Project {
+    fn default() -> Project {
+        Project {
+            meshes: Vec::new(),
+            objs: Vec::new(),
+            clips: Vec::new(),
+            fov: Track::new(65.),
+            fps: 24.,
+            show_rest_pose: false,
+        }
+    }
 }
 
 impl Project {
+    // Converts a time in seconds to a (possibly fractional) frame number
+    pub fn frame_at_time(&self, seconds: f32) -> f32 {
+        seconds * self.fps
+    }
+
+    // Parent of every object, in the same order as objs
+    pub fn parents(&self) -> Vec<Option<usize>> {
+        self.objs.iter().map(Object::parent).collect()
+    }
+
+    // World matrix of every object, in the same order as objs. local gives an object's own model
+    // matrix, e.g. Object::model_mat_rest. Each object's matrix is only worked out once
+    pub fn world_mats<F>(&self, local: F) -> Vec<glm::Mat4>
+    where
+        F: Fn(&Object) -> glm::Mat4,
+    {
+        self.world_mats_from(self.objs.iter().map(local).collect())
+    }
+
+    // World matrices with everything that animates objects: keyframes, modifiers, layers, drivers
+    // and strips, followed by constraints. frame may be between frames, see frame_at_time for
+    // going from seconds
+    pub fn world_mats_at(&self, frame: f32) -> Vec<glm::Mat4> {
+        let drivers = Drivers::new(self, frame);
+        let local: Vec<_> = (0..self.objs.len())
+            .map(|i| self.objs[i].model_mat_with(frame, &self.clips, &drivers.driven(i)))
+            .collect();
+
+        if self.objs.iter().all(|obj| obj.constraints.is_empty()) {
+            return self.world_mats_from(local);
+        }
+        let relative: Vec<_> = self
+            .objs
+            .iter()
+            .zip(local)
+            .map(|(obj, local)| obj.parent_inverse() * local)
+            .collect();
+        let constraints = self.objs.iter().map(|obj| &obj.constraints[..]).collect();
+
+        Solver::new(&self.parents(), &relative, constraints, frame).world_mats()
+    }
+
+    fn world_mats_from(&self, local: Vec<glm::Mat4>) -> Vec<glm::Mat4> {
+        let relative: Vec<_> = self
+            .objs
+            .iter()
+            .zip(local)
+            .map(|(obj, local)| obj.parent_inverse() * local)
+            .collect();
+
+        hierarchy::world_mats(&self.parents(), &relative)
+    }
+
+    // Index of the first object with the given name
+    pub fn obj_named(&self, name: &str) -> Option<usize> {
+        self.objs.iter().position(|obj| obj.name.to_str() == name)
+    }
+
+    // Whether the child may be parented to the given object without making a cycle, either of
+    // parents or of constraints reading each other
+    pub fn can_parent(&self, child: usize, parent: usize) -> bool {
+        parent < self.objs.len()
+            && !hierarchy::creates_cycle(&self.parents(), child, parent)
+            && !self.reads_world(parent, child)
+    }
+
+    // Whether a constraint on the object at index may read the target's world matrix without
+    // making a cycle
+    pub fn can_target(&self, index: usize, target: usize) -> bool {
+        target < self.objs.len() && target != index && !self.reads_world(target, index)
+    }
+
+    // Whether working out the world matrix of the object at index needs the world matrix of other,
+    // through parents and constraint targets
+    fn reads_world(&self, index: usize, other: usize) -> bool {
+        driver::depends_on(self.world_inputs(index), &other, |&i| self.world_inputs(i))
+    }
+
+    // Objects whose world matrices the world matrix of the object at index is worked out from
+    fn world_inputs(&self, index: usize) -> Vec<usize> {
+        let obj = &self.objs[index];
+        obj.parent()
+            .into_iter()
+            .chain(obj.constraints.iter().filter_map(Constraint::target))
+            .filter(|&i| i < self.objs.len())
+            .collect()
+    }
+
+    // Whether the object at index can move through the world or change shape over time, either by
+    // itself or through one of its parents or constraint targets
+    pub fn moves(&self, index: usize) -> bool {
+        let mut seen = Vec::new();
+        let mut stack = vec![index];
+        while let Some(i) = stack.pop() {
+            if self.objs[i].is_animated() {
+                return true;
+            }
+            if !seen.contains(&i) {
+                seen.push(i);
+                stack.extend(self.world_inputs(i));
+            }
+        }
+        false
+    }
+
+    // Works out the parent inverse that keeps the child where it is at the given frame once it is
+    // parented to parent (or unparented, for None). Pass the result to Object::set_parent
+    pub fn reparent(
+        &self,
+        child: usize,
+        parent: Option<usize>,
+        frame: Frame,
+    ) -> Result<glm::Mat4, String> {
+        let obj = self.objs.get(child).ok_or("No such object")?;
+        if let Some(p) = parent {
+            if !self.can_parent(child, p) {
+                return Err(format!(
+                    "Can't parent {} to {}, as it would be its own ancestor",
+                    obj.name, self.objs[p].name
+                ));
+            }
+        }
+
+        // The child's world matrix is parent world * parent inverse * local, which must stay
+        // the same before and after
+        let world = self.world_mats_at(frame as f32);
+        let old_parent = obj.parent().map_or_else(glm::identity, |p| world[p]);
+        let new_parent = parent.map_or_else(glm::identity, |p| world[p]);
+        let new_parent_inverse = new_parent
+            .try_inverse()
+            .ok_or("The new parent is scaled to nothing, so children can't keep their place")?;
+
+        Ok(new_parent_inverse * old_parent * obj.parent_inverse())
+    }
+
+    // Turns an object's position, rotation and scale keyframes into a new clip, and plays it back
+    // on the object with a strip in their place. Returns the index of the clip
+    pub fn push_down(&mut self, index: usize, name: ImString) -> Result<usize, String> {
+        let obj = &mut self.objs[index];
+        let clip = obj
+            .take_clip(name)
+            .map_err(|e| format!("Can't make a clip from {}: {}", obj.name, e))?;
+
+        let (start, _) = clip.range().unwrap_or((0, 0));
+        obj.strips.push(Strip::new(self.clips.len(), start as f32));
+        self.clips.push(clip);
+
+        Ok(self.clips.len() - 1)
+    }
+
     pub fn load_mesh_from_file<P>(&mut self, display: &Display, path: P) -> Result<usize, String>
     where
         P: AsRef<Path>,
     {
         // Load obj from file
-        let input = BufReader::new(File::open(path.as_ref()).map_err(|e| e.to_string())?);
-        let obj: obj::Obj<obj::Position, u16> = obj::load_obj(input).map_err(|e| e.to_string())?;
+        let obj = read_obj(path.as_ref())?;
 
         // Convert obj vertices into our Vertex type
         let vertices: Vec<Vertex> = obj
             .vertices
             .iter()
-            .map(|p| Vertex {
-                position: p.position,
-            })
+            .map(|p| Vertex::new(p.position))
             .collect();
 
         // Create name for object and mesh
@@ -52,6 +228,43 @@ impl Project {
 
         Ok(self.meshes.len() - 1)
     }
+
+    // A copy of the mesh at index with a morph target loaded from an obj file, named after the
+    // file. The file must have the same vertices and faces as the mesh, only moved. Pass the result
+    // to History::load_morph_target
+    pub fn load_morph_target<P>(
+        &self,
+        display: &Display,
+        index: usize,
+        path: P,
+    ) -> Result<Mesh, String>
+    where
+        P: AsRef<Path>,
+    {
+        let mesh = self.meshes.get(index).ok_or("No such mesh")?;
+        let obj = read_obj(path.as_ref())?;
+        if obj.indices != mesh.indices {
+            return Err(format!(
+                "The morph target's faces don't match those of {}",
+                mesh.name
+            ));
+        }
+
+        let positions: Vec<[f32; 3]> = obj.vertices.iter().map(|p| p.position).collect();
+        let name = path
+            .as_ref()
+            .file_stem()
+            .map_or("Morph".to_string(), |s| s.to_string_lossy().into_owned())
+            .chars()
+            .map(ascii_or_qmark)
+            .collect::<String>();
+        mesh.with_morph(display, ImString::new(name), &positions)
+    }
+}
+
+fn read_obj(path: &Path) -> Result<obj::Obj<obj::Position, u16>, String> {
+    let input = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    obj::load_obj(input).map_err(|e| e.to_string())
 }
 
 // Utility function for converting unprintable chars into '?'
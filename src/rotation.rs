@@ -0,0 +1,355 @@
+/*
+This file contains helper functions for rotations stored as quaternions: converting to and from
+the Euler angles used by Object (applied as rotate_x, then rotate_y, then rotate_z), and the slerp
+and squad interpolation used by KeyframeQuat.
+
+TODO:
+*/
+use crate::animation::{Extrapolation, Frame, Handle, Key, KeyframeQuat, KeyframeV3};
+use glm::{Quat, Vec3};
+use nalgebra_glm as glm;
+use std::f32::consts::PI;
+
+// Euler angle conversion
+// =================================================================================================
+// Returns the quaternion for Euler angles (radians) applied the way Object::model_mat_at does,
+// which is the matrix product Rx * Ry * Rz
+pub fn euler_to_quat(euler: &Vec3) -> Quat {
+    let qx = glm::quat_angle_axis(euler.x, &glm::vec3(1., 0., 0.));
+    let qy = glm::quat_angle_axis(euler.y, &glm::vec3(0., 1., 0.));
+    let qz = glm::quat_angle_axis(euler.z, &glm::vec3(0., 0., 1.));
+    qx * qy * qz
+}
+
+// Returns Euler angles (radians) giving the same rotation as a quaternion. Each angle is picked to
+// be as close as possible to the matching angle in `near`, so that converting a sequence of
+// keyframes doesn't introduce sudden 360 degree spins
+pub fn quat_to_euler(q: &Quat, near: &Vec3) -> Vec3 {
+    let m = glm::quat_to_mat3(&glm::quat_normalize(q));
+
+    // Rx * Ry * Rz has sin(y) in row 0, column 2. The other angles come from the remaining
+    // entries of row 0 and column 2
+    let sy = m[(0, 2)].clamp(-1., 1.);
+    let (a, b) = if sy.abs() < 0.99999 {
+        let x = (-m[(1, 2)]).atan2(m[(2, 2)]);
+        let y = sy.asin();
+        let z = (-m[(0, 1)]).atan2(m[(0, 0)]);
+
+        // Every rotation has a second set of Euler angles, pick whichever is closer
+        (glm::vec3(x, y, z), glm::vec3(x + PI, PI - y, z + PI))
+    } else {
+        // Gimbal lock, x and z rotate around the same axis. Put all of it in x
+        let x = m[(2, 1)].atan2(m[(1, 1)]);
+        let y = sy.signum() * PI / 2.;
+        (glm::vec3(x, y, 0.), glm::vec3(x, y, 0.))
+    };
+
+    let a = unwrap_angles(&a, near);
+    let b = unwrap_angles(&b, near);
+    if (a - near).norm_squared() <= (b - near).norm_squared() {
+        a
+    } else {
+        b
+    }
+}
+
+// Adds whole turns to each angle to bring it as close as possible to the angle in `near`
+fn unwrap_angles(angles: &Vec3, near: &Vec3) -> Vec3 {
+    angles.zip_map(near, |a, n| a + ((n - a) / (2. * PI)).round() * 2. * PI)
+}
+
+// Track conversion
+// =================================================================================================
+// Converts an Euler angle track into a quaternion track with a keyframe at every keyframe of the
+// original, giving the same rotation at each of those frames and at rest. Easing, extrapolation and
+// bezier handles are carried over, with each handle pointing at the rotation its Euler handle does
+pub fn euler_track_to_quat(track: &KeyframeV3) -> KeyframeQuat {
+    match track.frames() {
+        None => KeyframeQuat::new(euler_to_quat(&track.at(0))),
+        Some(frames) => {
+            let mut quats = KeyframeQuat::new(euler_to_quat(&track.rest()));
+            let mut prev = Quat::identity();
+            for &f in frames {
+                let key = track.key_at(f).unwrap();
+
+                // Each keyframe is kept on the same side as the last, so that curves between
+                // them take the short way around
+                let value = same_side(&prev, &euler_to_quat(&key.value));
+                let handle = |h: Handle<Vec3>| Handle {
+                    kind: h.kind,
+                    frames: h.frames,
+                    value: same_side(&value, &euler_to_quat(&(key.value + h.value))) - value,
+                };
+                quats.set_key(
+                    f,
+                    Key {
+                        value,
+                        easing: key.easing,
+                        handle_in: key.handle_in.map(handle),
+                        handle_out: key.handle_out.map(handle),
+                    },
+                );
+                prev = value;
+            }
+            if let Some((pre, post)) = track.extrapolation() {
+                quats.set_extrapolation(pre, post);
+            }
+            quats
+        }
+    }
+}
+
+// Like euler_track_to_quat, but fails instead of changing how the track moves past its first or
+// last keyframe. Linear and offset extrapolation continue each Euler angle on its own, which a
+// quaternion track can't do
+pub fn euler_track_to_quat_checked(track: &KeyframeV3) -> Result<KeyframeQuat, String> {
+    if let Some((pre, post)) = track.extrapolation() {
+        for mode in [pre, post] {
+            if matches!(mode, Extrapolation::Linear | Extrapolation::CycleOffset) {
+                return Err(format!(
+                    "{:?} extrapolation of Euler rotations can't be carried over to quaternions",
+                    mode
+                ));
+            }
+        }
+    }
+    Ok(euler_track_to_quat(track))
+}
+
+// Converts a quaternion track into an Euler angle track with a keyframe at every keyframe of the
+// original, giving the same rotation at each of those frames. Angles are unwrapped from one
+// keyframe to the next, and handles and extrapolation are carried over
+pub fn quat_track_to_euler(track: &KeyframeQuat) -> KeyframeV3 {
+    match track.frames() {
+        None => KeyframeV3::new(quat_to_euler(&track.at(0), &Vec3::zeros())),
+        Some(frames) => {
+            let frames: Vec<Frame> = frames.copied().collect();
+            let mut euler = KeyframeV3::new(quat_to_euler(&track.rest(), &Vec3::zeros()));
+            let mut prev = Vec3::zeros();
+            for f in frames {
+                let key = track.key_at(f).unwrap();
+                let value = quat_to_euler(&key.value, &prev);
+
+                // Handle ends are unwrapped to be near the keyframe they belong to
+                let handle = |h: Handle<Quat>| Handle {
+                    kind: h.kind,
+                    frames: h.frames,
+                    value: quat_to_euler(&(key.value + h.value), &value) - value,
+                };
+                euler.set_key(
+                    f,
+                    Key {
+                        value,
+                        easing: key.easing,
+                        handle_in: key.handle_in.map(handle),
+                        handle_out: key.handle_out.map(handle),
+                    },
+                );
+                prev = value;
+            }
+            if let Some((pre, post)) = track.extrapolation() {
+                euler.set_extrapolation(pre, post);
+            }
+            euler
+        }
+    }
+}
+
+// Interpolation
+// =================================================================================================
+// Spherical linear interpolation, always taking the shortest way around
+pub fn slerp(a: &Quat, b: &Quat, t: f32) -> Quat {
+    // q and -q are the same rotation. Flip b onto a's side so the short path is taken
+    let b = if glm::quat_dot(a, b) < 0. { -b } else { *b };
+    let dot = glm::quat_dot(a, &b).min(1.);
+
+    // Nearly identical rotations, fall back to normalized lerp to avoid dividing by ~0
+    if dot > 0.9995 {
+        return glm::quat_normalize(&glm::quat_lerp(a, &b, t));
+    }
+
+    let theta = dot.acos();
+    let sin_theta = theta.sin();
+    let wa = ((1. - t) * theta).sin() / sin_theta;
+    let wb = (t * theta).sin() / sin_theta;
+    a * wa + b * wb
+}
+
+// Squad (spherical quadrangle) interpolation from q1 to q2, given the keyframes before and after
+// them. It passes through every keyframe like slerp, but with smooth changes in angular velocity
+// across keyframes. Without a neighbour on either side, that end behaves like plain slerp
+pub fn squad(q0: Option<&Quat>, q1: &Quat, q2: &Quat, q3: Option<&Quat>, t: f32) -> Quat {
+    if q0.is_none() && q3.is_none() {
+        return slerp(q1, q2, t);
+    }
+
+    // Keep all of the quaternions on the same side as q1 so every slerp is the short one
+    let q2 = same_side(q1, q2);
+    let s1 = squad_tangent(q0.map(|q| same_side(q1, q)).as_ref(), q1, Some(&q2));
+    let s2 = squad_tangent(Some(q1), &q2, q3.map(|q| same_side(&q2, q)).as_ref());
+
+    slerp_no_flip(
+        &slerp_no_flip(q1, &q2, t),
+        &slerp_no_flip(&s1, &s2, t),
+        2. * t * (1. - t),
+    )
+}
+
+// Inner control point used by squad at keyframe q, from its neighbours
+fn squad_tangent(prev: Option<&Quat>, q: &Quat, next: Option<&Quat>) -> Quat {
+    match (prev, next) {
+        (Some(prev), Some(next)) => {
+            let inv = glm::quat_inverse(q);
+            let a = (inv * next).ln();
+            let b = (inv * prev).ln();
+            q * (-(a + b) / 4.).exp()
+        }
+        _ => *q,
+    }
+}
+
+// Returns q or -q (the same rotation), whichever is closer to `to`
+fn same_side(to: &Quat, q: &Quat) -> Quat {
+    if glm::quat_dot(to, q) < 0. {
+        -q
+    } else {
+        *q
+    }
+}
+
+// Squad's inner slerps must not flip their inputs, or the curve would jump between the two paths
+fn slerp_no_flip(a: &Quat, b: &Quat, t: f32) -> Quat {
+    let dot = glm::quat_dot(a, b).clamp(-1., 1.);
+    if dot.abs() > 0.9995 {
+        return glm::quat_normalize(&glm::quat_lerp(a, b, t));
+    }
+
+    let theta = dot.acos();
+    let sin_theta = theta.sin();
+    a * (((1. - t) * theta).sin() / sin_theta) + b * ((t * theta).sin() / sin_theta)
+}
+
+// Rotation tests
+// =================================================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::HandleType;
+
+    // Two quaternions give the same rotation if their rotation matrices match
+    fn same_rotation(a: &Quat, b: &Quat) -> bool {
+        let (ma, mb) = (glm::quat_to_mat3(a), glm::quat_to_mat3(b));
+        (ma - mb).abs().max() < 0.0005
+    }
+
+    fn euler_mat(e: &Vec3) -> glm::Mat4 {
+        let m = glm::rotate_x(&glm::identity(), e.x);
+        let m = glm::rotate_y(&m, e.y);
+        glm::rotate_z(&m, e.z)
+    }
+
+    #[test]
+    fn euler_quat_conversion_test() {
+        let angles = [
+            glm::vec3(0., 0., 0.),
+            glm::vec3(0.3, -1.2, 2.5),
+            glm::vec3(3.0, 0.2, -0.7),
+            glm::vec3(1.0, PI / 2., 0.4),
+            glm::vec3(-2.0, 1.4, 6.0),
+        ];
+
+        for e in &angles {
+            // Quaternion matches the matrix built in Object::model_mat_at
+            let q = euler_to_quat(e);
+            let diff = glm::quat_to_mat4(&q) - euler_mat(e);
+            assert!(diff.abs().max() < 0.0005, "{:?}", e);
+
+            // And converting back gives the same rotation
+            let back = quat_to_euler(&q, e);
+            assert!(same_rotation(&euler_to_quat(&back), &q), "{:?}", e);
+        }
+
+        // Angles are unwrapped to be near the given ones
+        let e = quat_to_euler(
+            &euler_to_quat(&glm::vec3(0., 0., 0.1)),
+            &glm::vec3(0., 0., 6.3),
+        );
+        assert!((e.z - (0.1 + 2. * PI)).abs() < 0.0005);
+    }
+
+    #[test]
+    fn quat_track_conversion_test() {
+        let mut euler = KeyframeV3::new(Vec3::zeros());
+        euler.set_at(0, glm::vec3(0., 0., 0.));
+        euler.set_at(10, glm::vec3(0., 0., 3.0));
+        euler.set_at(20, glm::vec3(1.0, 2.0, 6.0));
+
+        // Quaternion track has the same rotation at every keyed frame
+        let quats = euler_track_to_quat(&euler);
+        for &f in &[0, 10, 20] {
+            assert!(same_rotation(&quats.at(f), &euler_to_quat(&euler.at(f))));
+        }
+
+        // And so does converting back
+        let back = quat_track_to_euler(&quats);
+        assert_eq!(back.frames().unwrap().count(), 3);
+        for &f in &[0, 10, 20] {
+            assert!(same_rotation(&euler_to_quat(&back.at(f)), &quats.at(f)));
+        }
+    }
+
+    #[test]
+    fn quat_track_round_trip_test() {
+        let mut euler = KeyframeV3::new(Vec3::zeros());
+        euler.set_at(0, glm::vec3(0., 0., 0.));
+        euler.set_at(10, glm::vec3(0., 0.5, 1.0));
+        euler.set_at(20, glm::vec3(0.2, 0.5, 2.0));
+        let mut key = euler.key_at(10).unwrap();
+        key.handle_out = Some(Handle {
+            kind: HandleType::Free,
+            frames: 4.,
+            value: glm::vec3(0., 0.3, 0.),
+        });
+        euler.set_key(10, key);
+        euler.set_extrapolation(Extrapolation::Cycle, Extrapolation::Cycle);
+
+        // Handles and extrapolation survive the trip through a quaternion track
+        let back = quat_track_to_euler(&euler_track_to_quat(&euler));
+        assert_eq!(back.extrapolation(), euler.extrapolation());
+        let (a, b) = (euler.key_at(10).unwrap(), back.key_at(10).unwrap());
+        assert_eq!(b.handle_out.unwrap().frames, 4.);
+        assert!(
+            (a.handle_out.unwrap().value - b.handle_out.unwrap().value)
+                .abs()
+                .max()
+                < 0.0005
+        );
+
+        // So sampling between keys, and past the last one, gives the same rotation
+        for &t in &[3.5, 12.25, 17., 26.5] {
+            let (q1, q2) = (
+                euler_to_quat(&euler.sample(t)),
+                euler_to_quat(&back.sample(t)),
+            );
+            assert!(same_rotation(&q1, &q2), "{}", t);
+        }
+    }
+
+    #[test]
+    fn slerp_squad_test() {
+        let a = Quat::identity();
+        let b = glm::quat_angle_axis(PI / 2., &glm::vec3(0., 1., 0.));
+        let half = glm::quat_angle_axis(PI / 4., &glm::vec3(0., 1., 0.));
+
+        assert!(same_rotation(&slerp(&a, &b, 0.5), &half));
+
+        // Slerp takes the short way even if b is on the other side
+        assert!(same_rotation(&slerp(&a, &-b, 0.5), &half));
+
+        // Squad without neighbours is slerp, and always passes through its ends
+        assert!(same_rotation(&squad(None, &a, &b, None, 0.5), &half));
+        let c = glm::quat_angle_axis(1.0, &glm::vec3(1., 0., 0.));
+        assert!(same_rotation(&squad(Some(&c), &a, &b, Some(&c), 0.), &a));
+        assert!(same_rotation(&squad(Some(&c), &a, &b, Some(&c), 1.), &b));
+    }
+}
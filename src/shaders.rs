@@ -1,29 +1,123 @@
 /*
 This file contains source code for GLSL shaders used for rendering.
-Currently they only provide solid-color rendering.
+Currently they only provide rainbow rendering, tinted by each object's color.
+Vertices are skinned to their bones (see armature.rs) in the vertex shader, either by blending the
+bones' matrices or, with dual_quat set, their dual quaternions. Before that, they are moved by
+their mesh's morph targets (see mesh.rs), read from the morphs texture by vertex index.
 
 TODO:
-    * Re-add colors
     * Lighting (see vertex.rs too)
     * Texturing (see vertex.rs too)
 */
 pub const VERT_SHADER: &str = r#"
 #version 330 core
 
+// Same as armature::MAX_BONES, mesh::MAX_MORPHS and mesh::MORPH_TEXTURE_WIDTH
+const int MAX_BONES = 64;
+const int MAX_MORPHS = 32;
+const int MORPH_TEXTURE_WIDTH = 1024;
+
 layout (location = 0) in vec3 position;
+in uvec4 joints;
+in vec4 weights;
 uniform mat4 matrix;
-// uniform vec3 color;
+uniform mat4 bones[MAX_BONES];
+uniform int bone_count;
+uniform bool dual_quat;
+uniform sampler2D morphs;
+uniform float morph_weights[MAX_MORPHS];
+uniform int morph_count;
+uniform int vertex_count;
 out vec3 color_;
 
-void main() { color_ = (position+1)/2;
-              gl_Position = matrix * vec4(position, 1.0); }
+// Moves p by each morph target, by its weight. Each target's deltas follow the last one's in the
+// morphs texture, one texel per vertex
+vec3 morph(vec3 p) {
+    for (int i = 0; i < min(morph_count, MAX_MORPHS); i++) {
+        if (morph_weights[i] != 0.0) {
+            int texel = i * vertex_count + gl_VertexID;
+            ivec2 at = ivec2(texel % MORPH_TEXTURE_WIDTH, texel / MORPH_TEXTURE_WIDTH);
+            p += texelFetch(morphs, at, 0).xyz * morph_weights[i];
+        }
+    }
+    return p;
+}
+
+// Rotation part of a bone matrix as a quaternion (x, y, z, w)
+vec4 to_quat(mat4 m) {
+    mat3 r = mat3(normalize(m[0].xyz), normalize(m[1].xyz), normalize(m[2].xyz));
+    float trace = r[0][0] + r[1][1] + r[2][2];
+    if (trace > 0.0) {
+        float s = sqrt(trace + 1.0) * 2.0;
+        return vec4((r[1][2] - r[2][1]) / s, (r[2][0] - r[0][2]) / s,
+                    (r[0][1] - r[1][0]) / s, 0.25 * s);
+    } else if (r[0][0] > r[1][1] && r[0][0] > r[2][2]) {
+        float s = sqrt(1.0 + r[0][0] - r[1][1] - r[2][2]) * 2.0;
+        return vec4(0.25 * s, (r[1][0] + r[0][1]) / s,
+                    (r[2][0] + r[0][2]) / s, (r[1][2] - r[2][1]) / s);
+    } else if (r[1][1] > r[2][2]) {
+        float s = sqrt(1.0 + r[1][1] - r[0][0] - r[2][2]) * 2.0;
+        return vec4((r[1][0] + r[0][1]) / s, 0.25 * s,
+                    (r[2][1] + r[1][2]) / s, (r[2][0] - r[0][2]) / s);
+    } else {
+        float s = sqrt(1.0 + r[2][2] - r[0][0] - r[1][1]) * 2.0;
+        return vec4((r[2][0] + r[0][2]) / s, (r[2][1] + r[1][2]) / s,
+                    0.25 * s, (r[0][1] - r[1][0]) / s);
+    }
+}
+
+vec4 quat_mul(vec4 a, vec4 b) {
+    return vec4(a.w * b.xyz + b.w * a.xyz + cross(a.xyz, b.xyz), a.w * b.w - dot(a.xyz, b.xyz));
+}
+
+// Blends the bones' dual quaternions, flipping any facing away from the first bone's so they
+// take the short way around, then moves p by the result
+vec3 dual_quat_skin(vec3 p) {
+    vec4 first = to_quat(bones[min(int(joints.x), MAX_BONES - 1)]);
+    vec4 real = vec4(0.0);
+    vec4 dual = vec4(0.0);
+    for (int i = 0; i < 4; i++) {
+        mat4 bone = bones[min(int(joints[i]), MAX_BONES - 1)];
+        vec4 r = to_quat(bone);
+        vec4 d = 0.5 * quat_mul(vec4(bone[3].xyz, 0.0), r);
+        float w = dot(r, first) < 0.0 ? -weights[i] : weights[i];
+        real += r * w;
+        dual += d * w;
+    }
+
+    float len = length(real);
+    real /= len;
+    dual /= len;
+    vec3 rotated = p + 2.0 * cross(real.xyz, cross(real.xyz, p) + real.w * p);
+    return rotated + 2.0 * (real.w * dual.xyz - dual.w * real.xyz + cross(real.xyz, dual.xyz));
+}
+
+void main() {
+    color_ = (position+1)/2;
+
+    vec3 morphed = morph(position);
+    vec4 skinned = vec4(morphed, 1.0);
+    if (bone_count > 0 && dot(weights, vec4(1.0)) > 0.0) {
+        if (dual_quat) {
+            skinned = vec4(dual_quat_skin(morphed), 1.0);
+        } else {
+            mat4 skin = mat4(0.0);
+            for (int i = 0; i < 4; i++) {
+                skin += bones[min(int(joints[i]), MAX_BONES - 1)] * weights[i];
+            }
+            skinned = skin * skinned;
+        }
+    }
+    gl_Position = matrix * skinned;
+}
 "#;
 
 pub const FRAG_SHADER: &str = r#"
 #version 330 core
 
 in vec3 color_;
+uniform vec4 tint;
 out vec4 color;
 
-void main() { color = vec4(color_, 1.0); }
+void main() { color = vec4(color_, 1.0) * tint; }
 "#;
@@ -4,21 +4,39 @@ menu bar at the top of the screen. Each widget is denoted by starting with a des
 is, a line of ~ and ending with a line of -
 
 TODO:
-    * Animation sequencer
-    * Animation controls for 1 object
 */
-use crate::animation::Frame;
+use crate::animation::{
+    AutoKey, Extrapolation, Frame, HandleSide, HandleType, KeyCollision, KeyframeV3, Track,
+};
+use crate::armature::{Armature, Bone, MAX_BONES};
+use crate::constraint::{self, Axis, Constraint, ConstraintKind, TransformChannel};
+use crate::driver::{self, Driver};
+use crate::easing::{EaseMode, Easing};
+use crate::history::History;
+use crate::ik::{IkChain, IkSolver};
+use crate::interpolate::Interpolate;
+use crate::layer::{Layer, LayerMode};
+use crate::modifier::{self, Modifier, ModifierKind};
+use crate::nla::{Strip, StripMode};
+use crate::object::{Property, RotationMode};
+use crate::onion::OnionSkin;
+use crate::overlay::MotionPath;
+use crate::playback::{LoopMode, PlayState, Playback};
 use crate::project::Project;
+use crate::rotation::{euler_to_quat, quat_to_euler};
 use crate::Object;
 use glium::Display;
 use imgui::*;
+use nalgebra_glm::{Vec3, Vec4};
+use std::collections::{BTreeMap, BTreeSet};
 
 pub fn draw(
     ui: &mut Ui,
     project: &mut Project,
     state: &mut UIState,
     display: &Display,
-    _current_frame: &mut Frame,
+    playback: &mut Playback,
+    history: &mut History,
 ) {
     // Special case for main menu. If resetting UI, skip this frame and reset
     if state.reset {
@@ -26,21 +44,150 @@ pub fn draw(
         return;
     }
 
-    main_menu(ui, state);
+    // A drag is over once the mouse button is let go. The next change starts a new command
+    if !ui.is_mouse_down(MouseButton::Left) {
+        history.end_merge();
+    }
+
+    // Ctrl+Z undoes, Ctrl+Shift+Z redoes, unless text is being typed
+    let io = ui.io();
+    if io.key_ctrl && !io.want_text_input && ui.is_key_pressed(ui.key_index(Key::Z)) {
+        if io.key_shift {
+            history.redo(project);
+        } else {
+            history.undo(project);
+        }
+    }
+
+    // Undoing may have removed the selected mesh or object
+    state.forget_removed(project);
+
+    main_menu(ui, state, project, playback.frame(), history);
     meshes_list(ui, state, project);
     objects_list(ui, state, project);
-    new_mesh(ui, state, project, display);
-    new_object(ui, state, project);
-    object_attributes(ui, state, project);
+    new_mesh(ui, state, project, display, history);
+    new_object(ui, state, project, history);
+    object_attributes(ui, state, project, playback, history);
+    transport_bar(ui, state, project, playback);
+    timeline(ui, state, project, playback, history);
+    graph_editor(ui, state, project, playback, history);
+    modifiers_panel(ui, state, project, playback, history);
+    strips_panel(ui, state, project, playback, history);
+    layers_panel(ui, state, project, playback, history);
+    constraints_panel(ui, state, project, playback, history);
+    armature_panel(ui, state, project, display, playback, history);
+    history_panel(ui, state, project, history);
 }
 
-fn main_menu(ui: &mut Ui, state: &mut UIState) {
+fn main_menu(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    current_frame: Frame,
+    history: &mut History,
+) {
     // Draw menu bar
     ui.main_menu_bar(|| {
         if MenuItem::new(im_str!("Reset UI")).build(ui) {
             state.reset = true;
             return;
         }
+
+        // Undo and redo, also on Ctrl+Z and Ctrl+Shift+Z
+        ui.menu(im_str!("Edit"), true, || {
+            let (done, undone) = history.entries();
+            let (can_undo, can_redo) = (!done.is_empty(), !undone.is_empty());
+            if MenuItem::new(im_str!("Undo"))
+                .shortcut(im_str!("Ctrl+Z"))
+                .enabled(can_undo)
+                .build(ui)
+            {
+                history.undo(project);
+            }
+            if MenuItem::new(im_str!("Redo"))
+                .shortcut(im_str!("Ctrl+Shift+Z"))
+                .enabled(can_redo)
+                .build(ui)
+            {
+                history.redo(project);
+            }
+            MenuItem::new(im_str!("History")).build_with_ref(ui, &mut state.show_history);
+            MenuItem::new(im_str!("Layers")).build_with_ref(ui, &mut state.show_layers);
+            MenuItem::new(im_str!("Constraints")).build_with_ref(ui, &mut state.show_constraints);
+            MenuItem::new(im_str!("Armature")).build_with_ref(ui, &mut state.show_armature);
+        });
+
+        // Project wide settings
+        ui.menu(im_str!("Project"), true, || {
+            let mut fps = project.fps;
+            if Drag::new(im_str!("Frame rate"))
+                .range(1.0..=240.0)
+                .display_format(im_str!("%.2f fps"))
+                .build(ui, &mut fps)
+            {
+                history.edit_settings(project, "Change frame rate", Some("fps"), |p| p.fps = fps);
+            }
+
+            let mut show_rest_pose = project.show_rest_pose;
+            if ui.checkbox(im_str!("Show rest pose"), &mut show_rest_pose) {
+                history.edit_settings(project, "Show rest pose", None, |p| {
+                    p.show_rest_pose = show_rest_pose
+                });
+            }
+        });
+
+        // What is drawn over the viewport
+        ui.menu(im_str!("View"), true, || {
+            let path = &mut state.motion_path;
+            ui.checkbox(im_str!("Motion path"), &mut path.enabled);
+            let mut range = [path.before, path.after];
+            if Drag::new(im_str!("Frames before/after"))
+                .range(0..=1000)
+                .build_array(ui, &mut range)
+            {
+                path.before = range[0];
+                path.after = range[1];
+            }
+            Drag::new(im_str!("Label every"))
+                .range(0..=1000)
+                .display_format(im_str!("%d frames"))
+                .build(ui, &mut path.label_every);
+
+            ui.separator();
+            let onion = &mut state.onion_skin;
+            ui.checkbox(im_str!("Onion skinning"), &mut onion.enabled);
+            ui.checkbox(im_str!("All animated objects"), &mut onion.all_animated);
+            let mut ghosts = [onion.before, onion.after];
+            if Drag::new(im_str!("Ghosts before/after"))
+                .range(0..=20)
+                .build_array(ui, &mut ghosts)
+            {
+                onion.before = ghosts[0];
+                onion.after = ghosts[1];
+            }
+            Drag::new(im_str!("Frames apart"))
+                .range(1..=100)
+                .build(ui, &mut onion.step);
+            Slider::new(im_str!("Opacity"))
+                .range(0.0..=1.0)
+                .build(ui, &mut onion.opacity);
+            ColorEdit::new(im_str!("Past"), &mut onion.past_color).build(ui);
+            ColorEdit::new(im_str!("Future"), &mut onion.future_color).build(ui);
+        });
+
+        // Viewport camera settings
+        ui.menu(im_str!("Camera"), true, || {
+            let mut fov = project.fov.at(current_frame);
+            if Drag::new(im_str!("FOV"))
+                .range(10.0..=150.0)
+                .build(ui, &mut fov)
+            {
+                let auto_key = state.auto_key();
+                history.edit_settings(project, "Change FOV", Some("fov"), |p| {
+                    p.fov.edit_at(current_frame, fov, auto_key)
+                });
+            }
+        });
     });
 }
 
@@ -71,6 +218,28 @@ fn meshes_list(ui: &mut Ui, state: &mut UIState, project: &Project) {
             }
             // -------------------------------------------------------------------------------------
 
+            // "Load morph target" button, for another shape of the selected mesh
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if button_if(
+                ui,
+                state.selected_mesh.is_some(),
+                im_str!("Load morph target"),
+                "Load morph target (select a mesh first)",
+                [130., 20.],
+            ) {
+                // This logic is handled in the [Load Mesh] window
+                state.morph_target_mesh = state.selected_mesh;
+            }
+            if ui.is_item_hovered() {
+                if let Some(mesh) = state.selected_mesh.map(|i| &project.meshes[i]) {
+                    let names: Vec<&str> = mesh.morphs.iter().map(|m| m.name.to_str()).collect();
+                    if !names.is_empty() {
+                        ui.tooltip_text(format!("Morph targets: {}", names.join(", ")));
+                    }
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
             // Mesh selecion list
             // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
             for (i, mesh) in project.meshes.iter().enumerate() {
@@ -88,15 +257,30 @@ fn objects_list(ui: &mut Ui, state: &mut UIState, project: &mut Project) {
             // Render object selection list
             // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
             for (i, object) in project.objs.iter().enumerate() {
-                ui.radio_button(object.name_imstr(), &mut state.selected_object, Some(i));
+                if ui.radio_button(object.name_imstr(), &mut state.selected_object, Some(i)) {
+                    state.parent_error = None;
+                    state.driver_error = None;
+                    state.selected_bone = None;
+                    state.bone_error = None;
+                }
             }
             // -------------------------------------------------------------------------------------
         });
 }
 
-fn new_mesh(ui: &mut Ui, state: &mut UIState, project: &mut Project, display: &Display) {
-    if state.loading_new_mesh {
-        modal(ui, im_str!("Load Mesh"), || {
+fn new_mesh(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    display: &Display,
+    history: &mut History,
+) {
+    if state.loading_new_mesh || state.morph_target_mesh.is_some() {
+        let title = match state.morph_target_mesh {
+            Some(_) => im_str!("Load Morph Target"),
+            None => im_str!("Load Mesh"),
+        };
+        modal(ui, title, || {
             // If there is an error waiting to be rendered, show it. Otherwise, show the path
             // input and buttons
             if let Some(err_msg) = &state.new_mesh_error {
@@ -139,8 +323,14 @@ fn new_mesh(ui: &mut Ui, state: &mut UIState, project: &mut Project, display: &D
                     "Ok (enter a path)",
                     [80., 20.],
                 ) {
-                    // Try loading mesh from path provided
-                    let res = project.load_mesh_from_file(display, state.new_mesh_path.to_str());
+                    // Try loading mesh or morph target from path provided
+                    let path = state.new_mesh_path.to_str();
+                    let res = match state.morph_target_mesh {
+                        Some(mesh) => history
+                            .load_morph_target(project, display, mesh, path)
+                            .map(|_| mesh),
+                        None => history.load_mesh(project, display, path),
+                    };
 
                     // If a new mesh was successfully loaded, reset mesh list ui and this modal
                     if res.is_ok() {
@@ -157,7 +347,7 @@ fn new_mesh(ui: &mut Ui, state: &mut UIState, project: &mut Project, display: &D
     }
 }
 
-fn new_object(ui: &mut Ui, state: &mut UIState, project: &mut Project) {
+fn new_object(ui: &mut Ui, state: &mut UIState, project: &mut Project, history: &mut History) {
     // state.mesh_for_obj is set in the [Meshes] window
     if let Some(i) = state.mesh_for_obj {
         modal(ui, im_str!("New Object"), || {
@@ -192,7 +382,7 @@ fn new_object(ui: &mut Ui, state: &mut UIState, project: &mut Project) {
                 let mesh = project.meshes[i].clone();
                 let name = state.new_obj_name.clone();
                 let object = Object::new(name, mesh);
-                project.objs.push(object);
+                history.add_object(project, object);
 
                 // Reset state related to a making new object
                 state.reset_new_object();
@@ -202,92 +392,3301 @@ fn new_object(ui: &mut Ui, state: &mut UIState, project: &mut Project) {
     }
 }
 
-fn object_attributes(ui: &mut Ui, state: &mut UIState, project: &mut Project) {
+fn object_attributes(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &mut Playback,
+    history: &mut History,
+) {
     Window::new(im_str!("Object Attributes"))
         .position([0., 619.], Condition::Appearing)
         .size([300., 300.], Condition::Appearing)
         .build(ui, || {
-            // Slider::new(im_str!("Numbers"))
-            //     .display_format(im_str!("%.3f"))
-            //     .range(-100.0..=100.0)
-            //     .build_array(ui, &mut state.ns);
-        });
-}
+            // Widgets edit a copy of the object. If anything changed, the copy replaces the
+            // object through the history at the end
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+            let current_frame = playback.frame();
+            let auto_key = state.auto_key();
 
-#[derive(Default)]
-pub struct UIState {
-    // UI Reset (set in [main_menu])
-    // =============================================================================================
-    // Whether to reset right now. If this is true, the ui will skip rendering for a frame to reset
-    // the position and size of all windows
-    reset: bool,
+            // Name of the change made, and the merge key for changes made by dragging
+            let mut change: Option<(&str, Option<&str>)> = None;
 
-    // Loading a [new_mesh]
-    // =============================================================================================
-    // Whether or not currently loading a new mesh
-    loading_new_mesh: bool,
+            // Position
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut position: [f32; 3] = obj.position.at(current_frame).into();
+            if Drag::new(im_str!("Position"))
+                .speed(0.01)
+                .display_format(im_str!("%.3f"))
+                .build_array(ui, &mut position)
+            {
+                obj.position
+                    .edit_at(current_frame, position.into(), auto_key);
+                change = Some(("Move", Some("position")));
+            }
+            if let Some(name) = key_buttons(ui, "position", &mut obj.position, playback) {
+                change = Some((name, None));
+            }
+            // -------------------------------------------------------------------------------------
 
-    // Path of obj file to load from
-    new_mesh_path: ImString,
+            // Rotation, shown in degrees. Quaternion rotations are shown and edited as Euler
+            // angles too
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let radians = match obj.rotation_mode() {
+                RotationMode::Euler => obj.rotation.at(current_frame),
+                RotationMode::Quaternion => {
+                    quat_to_euler(&obj.rotation_quat.at(current_frame), &Vec3::zeros())
+                }
+            };
+            let mut degrees: [f32; 3] = radians.map(f32::to_degrees).into();
+            if Drag::new(im_str!("Rotation"))
+                .speed(0.5)
+                .display_format(im_str!("%.1f"))
+                .build_array(ui, &mut degrees)
+            {
+                let radians = Vec3::from(degrees).map(f32::to_radians);
+                match obj.rotation_mode() {
+                    RotationMode::Euler => obj.rotation.edit_at(current_frame, radians, auto_key),
+                    RotationMode::Quaternion => {
+                        obj.rotation_quat
+                            .edit_at(current_frame, euler_to_quat(&radians), auto_key)
+                    }
+                }
+                change = Some(("Rotate", Some("rotation")));
+            }
+            let keyed = match obj.rotation_mode() {
+                RotationMode::Euler => key_buttons(ui, "rotation", &mut obj.rotation, playback),
+                RotationMode::Quaternion => {
+                    key_buttons(ui, "rotation", &mut obj.rotation_quat, playback)
+                }
+            };
+            if let Some(name) = keyed {
+                change = Some((name, None));
+            }
+            // -------------------------------------------------------------------------------------
 
-    // Error message to display if there was a problem loading a mesh in [New Mesh]
-    new_mesh_error: Option<String>,
+            // Scale
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut scale: [f32; 3] = obj.scale.at(current_frame).into();
+            if Drag::new(im_str!("Scale"))
+                .speed(0.01)
+                .display_format(im_str!("%.3f"))
+                .build_array(ui, &mut scale)
+            {
+                obj.scale.edit_at(current_frame, scale.into(), auto_key);
+                change = Some(("Scale", Some("scale")));
+            }
+            if let Some(name) = key_buttons(ui, "scale", &mut obj.scale, playback) {
+                change = Some((name, None));
+            }
+            // -------------------------------------------------------------------------------------
 
-    // [meshes_list] menu
-    // =============================================================================================
-    // Which mesh is selected in the Meshes dropdown
-    selected_mesh: Option<usize>,
+            // Rotation mode selection
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut mode = obj.rotation_mode();
+            ui.text("Rotation mode");
+            ui.radio_button(im_str!("Euler"), &mut mode, RotationMode::Euler);
+            ui.same_line(0.);
+            ui.radio_button(im_str!("Quaternion"), &mut mode, RotationMode::Quaternion);
+            if mode != obj.rotation_mode() {
+                obj.set_rotation_mode(mode);
+                change = Some(("Change rotation mode", None));
+            }
+            // -------------------------------------------------------------------------------------
 
-    // [objects_list] menu
-    // =============================================================================================
-    // Which object is selected in the Objects dropdown. Also used in [object_attributes]
-    selected_object: Option<usize>,
+            // Parent selection. Objects that would become their own ancestor aren't listed
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let parent_name = match obj.parent() {
+                Some(p) => project.objs[p].name.clone(),
+                None => ImString::new("None"),
+            };
+            let mut new_parent = None;
+            ComboBox::new(im_str!("Parent"))
+                .preview_value(&parent_name)
+                .build(ui, || {
+                    let none = Selectable::new(im_str!("None")).selected(obj.parent().is_none());
+                    if none.build(ui) {
+                        new_parent = Some(None);
+                    }
+                    for (i, other) in project.objs.iter().enumerate() {
+                        if !project.can_parent(index, i) {
+                            continue;
+                        }
+                        let id = ui.push_id(i as i32);
+                        if Selectable::new(&other.name)
+                            .selected(obj.parent() == Some(i))
+                            .build(ui)
+                        {
+                            new_parent = Some(Some(i));
+                        }
+                        id.pop(ui);
+                    }
+                });
 
-    // Creating a [new_object]
-    // =============================================================================================
-    // Which mesh is currently being made into an object
-    mesh_for_obj: Option<usize>,
+            // The object keeps its place in the world when its parent changes
+            if let Some(parent) = new_parent.filter(|&p| p != obj.parent()) {
+                match project.reparent(index, parent, current_frame) {
+                    Ok(parent_inverse) => {
+                        obj.set_parent(parent, parent_inverse);
+                        change = Some(("Set parent", None));
+                        state.parent_error = None;
+                    }
+                    Err(e) => state.parent_error = Some(e),
+                }
+            }
+            if let Some(e) = &state.parent_error {
+                ui.text_colored([1., 0.3, 0.3, 1.], e);
+            }
+            // -------------------------------------------------------------------------------------
 
-    // Name for new object
-    new_obj_name: ImString,
+            // Reset to rest pose button
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if ui.button(im_str!("Reset to rest pose"), [140., 20.]) {
+                obj.reset_to_rest_pose(current_frame);
+                change = Some(("Reset to rest pose", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Color and visibility
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let c = obj.color.at(current_frame);
+            let mut color = [c.x, c.y, c.z, c.w];
+            if ColorEdit::new(im_str!("Color"), &mut color).build(ui) {
+                obj.color
+                    .edit_at(current_frame, Vec4::from(color), auto_key);
+                change = Some(("Change color", Some("color")));
+            }
+
+            let mut visible = obj.visible.at(current_frame);
+            if ui.checkbox(im_str!("Visible"), &mut visible) {
+                obj.visible.edit_at(current_frame, visible, auto_key);
+                change = Some(("Change visibility", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Custom properties
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            for (i, prop) in obj.properties.iter_mut().enumerate() {
+                let mut value = prop.value.at(current_frame);
+                if Drag::new(&im_str!("{}##property{}", prop.name, i))
+                    .speed(0.01)
+                    .build(ui, &mut value)
+                {
+                    prop.value.edit_at(current_frame, value, auto_key);
+                    change = Some(("Change property", Some("property")));
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // New property name input and button
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.input_text(im_str!("##new_property"), &mut state.new_property_name)
+                .resize_buffer(true)
+                .allow_tab_input(false)
+                .build();
+            ui.same_line(0.);
+            if button_if(
+                ui,
+                !state.new_property_name.is_empty(),
+                im_str!("Add property"),
+                "Add property (enter a name)",
+                [100., 20.],
+            ) {
+                obj.properties
+                    .push(Property::new(state.new_property_name.clone()));
+                state.new_property_name.clear();
+                change = Some(("Add property", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Weights of the mesh's morph targets
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mesh = obj.mesh.clone();
+            if !mesh.morphs.is_empty() && CollapsingHeader::new(im_str!("Morph targets")).build(ui)
+            {
+                // Targets without a weight track yet get one once they are edited
+                for (i, morph) in mesh.morphs.iter().enumerate() {
+                    let id = ui.push_id(i as i32);
+                    let mut unset = Track::new(0.);
+                    let weight = obj.morph_weights.get_mut(i).unwrap_or(&mut unset);
+                    if let Some(edit) = keyable_drag(
+                        ui,
+                        &morph.name,
+                        weight,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Change morph weight",
+                    ) {
+                        if i >= obj.morph_weights.len() {
+                            obj.morph_weights.resize_with(i, || Track::new(0.));
+                            obj.morph_weights.push(unset);
+                        }
+                        change = Some(edit);
+                    }
+                    id.pop(ui);
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Drivers, setting attributes from expressions in place of their animation. Clicking
+            // one loads it into the inputs below for editing
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if CollapsingHeader::new(im_str!("Drivers")).build(ui) {
+                let attributes = obj.attributes();
+                let mut remove = None;
+                for (i, driver) in obj.drivers.iter().enumerate() {
+                    let id = ui.push_id(i as i32);
+                    if ui.button(im_str!("x"), [20., 20.]) {
+                        remove = Some(i);
+                    }
+                    ui.same_line(0.);
+                    let label = im_str!("{} = {}", driver.target, driver.text());
+                    if Selectable::new(&label).build(ui) {
+                        state.driver_target = attributes
+                            .iter()
+                            .position(|a| a == &driver.target)
+                            .unwrap_or(0);
+                        state.driver_text = ImString::new(driver.text());
+                        state.driver_error = None;
+                    }
+                    id.pop(ui);
+                }
+                if let Some(i) = remove {
+                    obj.drivers.remove(i);
+                    change = Some(("Remove driver", None));
+                }
+
+                // Attribute to drive and expression to drive it with
+                state.driver_target = state.driver_target.min(attributes.len() - 1);
+                let target = &attributes[state.driver_target];
+                ui.set_next_item_width(110.);
+                ComboBox::new(im_str!("##driver_target"))
+                    .preview_value(&im_str!("{}", target))
+                    .build(ui, || {
+                        for (i, attribute) in attributes.iter().enumerate() {
+                            if Selectable::new(&im_str!("{}", attribute))
+                                .selected(i == state.driver_target)
+                                .build(ui)
+                            {
+                                state.driver_target = i;
+                            }
+                        }
+                    });
+                ui.same_line(0.);
+                ui.text("=");
+                ui.same_line(0.);
+                ui.set_next_item_width(-1.);
+                ui.input_text(im_str!("##driver_text"), &mut state.driver_text)
+                    .resize_buffer(true)
+                    .allow_tab_input(false)
+                    .build();
+
+                // The expression is parsed and checked before the driver is set
+                if button_if(
+                    ui,
+                    !state.driver_text.is_empty(),
+                    im_str!("Set driver"),
+                    "Set driver (enter an expression)",
+                    [100., 20.],
+                ) {
+                    let driver = Driver::new(target.clone(), state.driver_text.to_str())
+                        .and_then(|d| driver::check(project, index, &d).map(|_| d));
+                    match driver {
+                        Ok(driver) => {
+                            obj.set_driver(driver);
+                            state.driver_error = None;
+                            change = Some(("Set driver", None));
+                        }
+                        Err(e) => state.driver_error = Some(e),
+                    }
+                }
+                if let Some(e) = &state.driver_error {
+                    ui.text_colored([1., 0.3, 0.3, 1.], e);
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
 }
 
-impl UIState {
-    pub fn new() -> UIState {
-        // For any future initialization
-        UIState::default()
-    }
+// Draws buttons to key a track at the current frame, delete the keyframe at the current frame and
+// jump to the previous or next keyframe. id tells apart the buttons of different tracks. Returns
+// the name of the change made to the track, if any
+fn key_buttons<T: Interpolate>(
+    ui: &Ui,
+    id: &str,
+    track: &mut Track<T>,
+    playback: &mut Playback,
+) -> Option<&'static str> {
+    let frame = playback.frame();
+    let mut change = None;
 
-    fn reset_all(&mut self) {
-        *self = UIState::new();
+    if ui.button(&im_str!("Key##{}", id), [40., 20.]) {
+        let value = track.at(frame);
+        track.set_at(frame, value);
+        change = Some("Insert keyframe");
     }
-
-    fn reset_new_mesh(&mut self) {
-        self.new_mesh_path.clear();
-        self.loading_new_mesh = false;
-        self.new_mesh_error = None;
+    ui.same_line(0.);
+    if button_if(
+        ui,
+        track.key_at(frame).is_some(),
+        &im_str!("Delete key##{}", id),
+        "Delete key (no keyframe on this frame)",
+        [80., 20.],
+    ) {
+        track.remove_at(frame);
+        change = Some("Delete keyframe");
     }
 
-    fn reset_new_object(&mut self) {
-        self.mesh_for_obj = None;
-        self.new_obj_name.clear();
+    // Jump to the previous and next keyframes
+    ui.same_line(0.);
+    match track.prev_key(frame) {
+        Some(prev) => {
+            if ui.button(&im_str!("<##{}", id), [20., 20.]) {
+                playback.seek(prev);
+            }
+        }
+        None => disabled_button(ui, "No earlier keyframe", [20., 20.]),
+    }
+    ui.same_line(0.);
+    match track.next_key(frame) {
+        Some(next) => {
+            if ui.button(&im_str!(">##{}", id), [20., 20.]) {
+                playback.seek(next);
+            }
+        }
+        None => disabled_button(ui, "No later keyframe", [20., 20.]),
     }
+
+    change
 }
 
-// Draws and shows a modal
-fn modal<F: FnOnce()>(ui: &Ui, title: &ImStr, func: F) {
-    ui.popup_modal(title).build(func);
-    ui.open_popup(title);
+fn transport_bar(ui: &mut Ui, state: &mut UIState, project: &Project, playback: &mut Playback) {
+    Window::new(im_str!("Playback"))
+        .position([300., 19.], Condition::Appearing)
+        .size([600., 110.], Condition::Appearing)
+        .build(ui, || {
+            let (start, end) = playback.range();
+
+            // Jump to start, play/pause, stop and jump to end buttons
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if ui.button(im_str!("|<"), [30., 20.]) {
+                playback.seek(start);
+            }
+            ui.same_line(0.);
+            let play_text = if playback.is_playing() {
+                im_str!("Pause")
+            } else {
+                im_str!("Play")
+            };
+            if ui.button(play_text, [60., 20.]) {
+                playback.toggle();
+            }
+            ui.same_line(0.);
+            if button_if(
+                ui,
+                playback.state() != PlayState::Stopped,
+                im_str!("Stop"),
+                "Stop (already stopped)",
+                [60., 20.],
+            ) {
+                playback.stop();
+            }
+            ui.same_line(0.);
+            if ui.button(im_str!(">|"), [30., 20.]) {
+                playback.seek(end);
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Current frame and time
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            let mut frame = playback.frame() as u32;
+            if Slider::new(im_str!("##frame"))
+                .range(start as u32..=end as u32)
+                .display_format(im_str!("Frame %d"))
+                .build(ui, &mut frame)
+            {
+                playback.seek(frame as Frame);
+            }
+            ui.same_line(0.);
+            ui.text(format!("{:.2}s", playback.frame() as f32 / project.fps));
+            // -------------------------------------------------------------------------------------
+
+            // Playback range
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut range = [start as u32, end as u32];
+            ui.set_next_item_width(150.);
+            if Drag::new(im_str!("Range")).build_array(ui, &mut range) {
+                playback.set_range(range[0] as Frame, range[1] as Frame);
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Speed
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            ui.set_next_item_width(100.);
+            Drag::new(im_str!("Speed"))
+                .range(-4.0..=4.0)
+                .speed(0.01)
+                .display_format(im_str!("%.2fx"))
+                .build(ui, &mut playback.speed);
+            // -------------------------------------------------------------------------------------
+
+            // Loop mode selection
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut mode = playback.mode();
+            ui.radio_button(im_str!("Once"), &mut mode, LoopMode::Once);
+            ui.same_line(0.);
+            ui.radio_button(im_str!("Loop"), &mut mode, LoopMode::Loop);
+            ui.same_line(0.);
+            ui.radio_button(im_str!("Ping-pong"), &mut mode, LoopMode::PingPong);
+            if mode != playback.mode() {
+                playback.set_mode(mode);
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Auto-key toggle and mode
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            ui.checkbox(im_str!("Auto-key"), &mut state.auto_key);
+            ui.same_line(0.);
+            ui.radio_button(
+                im_str!("All channels"),
+                &mut state.auto_key_mode,
+                AutoKey::All,
+            );
+            ui.same_line(0.);
+            ui.radio_button(
+                im_str!("Keyed only"),
+                &mut state.auto_key_mode,
+                AutoKey::KeyedOnly,
+            );
+            // -------------------------------------------------------------------------------------
+        });
 }
 
-// Draws a button that is only clickable if a given condition is met
-fn button_if(ui: &Ui, cond: bool, button_text: &ImStr, tooltip_text: &str, size: [f32; 2]) -> bool {
-    if cond {
-        ui.button(button_text, size)
-    } else {
-        disabled_button(ui, tooltip_text, size);
-        false
-    }
+// Sizes used to lay out the timeline, in pixels
+const TIMELINE_LABEL_WIDTH: f32 = 150.;
+const TIMELINE_RULER_HEIGHT: f32 = 20.;
+const TIMELINE_ROW_HEIGHT: f32 = 18.;
+const TIMELINE_KEY_RADIUS: f32 = 5.;
+
+fn timeline(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &mut Playback,
+    history: &mut History,
+) {
+    Window::new(im_str!("Timeline"))
+        .position([300., 619.], Condition::Appearing)
+        .size([900., 300.], Condition::Appearing)
+        .build(ui, || {
+            // One row for each object, showing all of its keyframes, followed by one row for
+            // each of its channels
+            let rows = timeline_rows(project);
+
+            // Canvas covering the rows, which takes all mouse input
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let [x0, y0] = ui.cursor_screen_pos();
+            let [w, _] = ui.content_region_avail();
+            let h = TIMELINE_RULER_HEIGHT + TIMELINE_ROW_HEIGHT * rows.len().max(1) as f32;
+            ui.invisible_button(im_str!("##timeline"), [w.max(1.), h]);
+            let hovered = ui.is_item_hovered();
+            // -------------------------------------------------------------------------------------
+
+            let keys_x0 = x0 + TIMELINE_LABEL_WIDTH;
+            let [mx, my] = ui.io().mouse_pos;
+            let shift = ui.io().key_shift;
+
+            // Zoom around the mouse with the scroll wheel, pan by dragging with the middle button
+            if hovered {
+                let wheel = ui.io().mouse_wheel;
+                if wheel != 0. {
+                    let under_mouse = state.timeline_frame_at(keys_x0, mx);
+                    state.timeline_zoom =
+                        (state.timeline_zoom * 1.2f32.powf(wheel)).clamp(1., 100.);
+                    state.timeline_offset = under_mouse - (mx - keys_x0) / state.timeline_zoom;
+                }
+                if ui.is_mouse_dragging(MouseButton::Middle) {
+                    state.timeline_offset -= ui.io().mouse_delta[0] / state.timeline_zoom;
+                }
+                state.timeline_offset = state.timeline_offset.max(-1.);
+            }
+
+            // Start of a left click: move the playhead, pick a keyframe or start a box selection
+            if hovered && ui.is_mouse_clicked(MouseButton::Left) {
+                if my < y0 + TIMELINE_RULER_HEIGHT {
+                    state.timeline_drag = Some(TimelineDrag::Playhead);
+                } else if let Some(keys) = timeline_hit(state, &rows, keys_x0, y0, [mx, my]) {
+                    let selected = keys.iter().all(|k| state.selected_keys.contains(k));
+                    if shift && selected {
+                        keys.iter().for_each(|k| {
+                            state.selected_keys.remove(k);
+                        });
+                    } else {
+                        if !shift && !selected {
+                            state.selected_keys.clear();
+                        }
+                        state.selected_keys.extend(keys);
+                    }
+                    state.timeline_drag = Some(TimelineDrag::Keys(mx));
+                } else {
+                    if !shift {
+                        state.selected_keys.clear();
+                    }
+                    state.timeline_drag = Some(TimelineDrag::Box([mx, my]));
+                }
+            }
+
+            // Frames the selected keyframes would move by if the mouse was released now. Limited
+            // so that no keyframe would go below frame zero
+            let key_delta = match state.timeline_drag {
+                Some(TimelineDrag::Keys(start_x)) => {
+                    let lowest = state.selected_keys.iter().map(|k| k.2).min().unwrap_or(0);
+                    (((mx - start_x) / state.timeline_zoom).round() as isize)
+                        .max(-(lowest as isize))
+                }
+                _ => 0,
+            };
+
+            // Ongoing and finished drags
+            let released = !ui.is_mouse_down(MouseButton::Left);
+            match state.timeline_drag {
+                Some(TimelineDrag::Playhead) => {
+                    let frame = state.timeline_frame_at(keys_x0, mx).round().max(0.);
+                    playback.seek(frame as Frame);
+                }
+                Some(TimelineDrag::Box(start)) if released => {
+                    let (min, max) = (
+                        [start[0].min(mx), start[1].min(my)],
+                        [start[0].max(mx), start[1].max(my)],
+                    );
+                    for (r, row) in rows.iter().enumerate() {
+                        let y = y0 + TIMELINE_RULER_HEIGHT + TIMELINE_ROW_HEIGHT * (r as f32 + 0.5);
+                        for (&frame, keys) in &row.keys {
+                            let x = state.timeline_x_at(keys_x0, frame as f32);
+                            if x >= min[0] && x <= max[0] && y >= min[1] && y <= max[1] {
+                                state.selected_keys.extend(keys);
+                            }
+                        }
+                    }
+                }
+                Some(TimelineDrag::Keys(_)) if released && key_delta != 0 => {
+                    let objects = state.selected_keys.iter().map(|k| k.0).collect();
+                    history.edit_objects(project, &objects, "Move keyframes", None, |objs| {
+                        move_selected_keys(state, objs, key_delta)
+                    });
+                }
+                _ => (),
+            }
+            if released {
+                state.timeline_drag = None;
+            }
+
+            // Delete the selected keyframes
+            if ui.is_window_focused()
+                && ui.is_key_pressed(ui.key_index(Key::Delete))
+                && !state.selected_keys.is_empty()
+            {
+                let selected = std::mem::take(&mut state.selected_keys);
+                let objects = selected.iter().map(|k| k.0).collect();
+                history.edit_objects(project, &objects, "Delete keyframes", None, |objs| {
+                    for (o, c, frame) in selected {
+                        if let Some(obj) = objs.get_mut(o) {
+                            if let Some((_, channel)) = obj.channels_mut().into_iter().nth(c) {
+                                channel.delete_key(frame);
+                            }
+                        }
+                    }
+                });
+            }
+
+            // Draw rows, keyframes and playhead
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let draw_list = ui.get_window_draw_list();
+            let (x1, y1) = (x0 + w, y0 + h);
+
+            // Ruler with a frame number at regular steps, at least 50 pixels apart
+            let step = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000]
+                .iter()
+                .copied()
+                .find(|s| *s as f32 * state.timeline_zoom >= 50.)
+                .unwrap_or(1000);
+            draw_list.with_clip_rect_intersect([keys_x0, y0], [x1, y1], || {
+                let first = (state.timeline_offset.max(0.) as usize / step) * step;
+                let last = state.timeline_frame_at(keys_x0, x1).max(0.) as usize;
+                for frame in (first..=last).step_by(step) {
+                    let x = state.timeline_x_at(keys_x0, frame as f32);
+                    draw_list
+                        .add_line(
+                            [x, y0 + TIMELINE_RULER_HEIGHT - 5.],
+                            [x, y1],
+                            (0.3, 0.3, 0.3),
+                        )
+                        .build();
+                    draw_list.add_text([x + 2., y0], (0.8, 0.8, 0.8), frame.to_string());
+                }
+            });
+
+            for (r, row) in rows.iter().enumerate() {
+                let top = y0 + TIMELINE_RULER_HEIGHT + TIMELINE_ROW_HEIGHT * r as f32;
+                let y = top + TIMELINE_ROW_HEIGHT / 2.;
+
+                // Object rows are darker, so that each object's channels are grouped together
+                let background = if row.channel.is_none() {
+                    [0.1, 0.1, 0.1, 0.6]
+                } else {
+                    [0.2, 0.2, 0.2, 0.3]
+                };
+                draw_list
+                    .add_rect([x0, top], [x1, top + TIMELINE_ROW_HEIGHT - 1.], background)
+                    .filled(true)
+                    .build();
+
+                draw_list.with_clip_rect_intersect(
+                    [x0, top],
+                    [keys_x0, top + TIMELINE_ROW_HEIGHT],
+                    || {
+                        let indent = if row.channel.is_none() { 4. } else { 16. };
+                        draw_list.add_text([x0 + indent, top + 2.], (1., 1., 1.), &row.label);
+                    },
+                );
+
+                draw_list.with_clip_rect_intersect(
+                    [keys_x0, top],
+                    [x1, top + TIMELINE_ROW_HEIGHT],
+                    || {
+                        for (&frame, keys) in &row.keys {
+                            let selected = keys.iter().all(|k| state.selected_keys.contains(k));
+                            let partly = keys.iter().any(|k| state.selected_keys.contains(k));
+
+                            // Selected keyframes being dragged are drawn where they will land
+                            let shown = if partly {
+                                (frame as isize + key_delta) as f32
+                            } else {
+                                frame as f32
+                            };
+                            let color = if selected {
+                                [1., 0.6, 0.1, 1.]
+                            } else if partly {
+                                [0.9, 0.8, 0.5, 1.]
+                            } else {
+                                [0.85, 0.85, 0.85, 1.]
+                            };
+                            diamond(
+                                &draw_list,
+                                [state.timeline_x_at(keys_x0, shown), y],
+                                TIMELINE_KEY_RADIUS,
+                                color,
+                            );
+                        }
+                    },
+                );
+            }
+
+            if rows.is_empty() {
+                draw_list.add_text(
+                    [x0 + 4., y0 + TIMELINE_RULER_HEIGHT + 2.],
+                    (0.8, 0.8, 0.8),
+                    "Add an object to animate it",
+                );
+            }
+
+            // Playhead
+            let x = state.timeline_x_at(keys_x0, playback.frame() as f32);
+            if x >= keys_x0 {
+                draw_list
+                    .add_line([x, y0], [x, y1], (1., 0.2, 0.2))
+                    .thickness(2.)
+                    .build();
+            }
+
+            // Box selection
+            if let Some(TimelineDrag::Box(start)) = state.timeline_drag {
+                draw_list
+                    .add_rect(start, [mx, my], [0.4, 0.6, 1., 0.25])
+                    .filled(true)
+                    .build();
+                draw_list.add_rect(start, [mx, my], (0.4, 0.6, 1.)).build();
+            }
+            // -------------------------------------------------------------------------------------
+        });
+}
+
+// One row of the timeline, for either a whole object or one of its channels
+struct TimelineRow {
+    label: String,
+    channel: Option<usize>,
+
+    // The keyframes shown at each frame. For object rows, this is every channel's keyframe there
+    keys: BTreeMap<Frame, Vec<KeyRef>>,
+}
+
+fn timeline_rows(project: &Project) -> Vec<TimelineRow> {
+    let mut rows = Vec::new();
+    for (o, obj) in project.objs.iter().enumerate() {
+        let object_row = rows.len();
+        rows.push(TimelineRow {
+            label: obj.name.to_string(),
+            channel: None,
+            keys: BTreeMap::new(),
+        });
+
+        for (c, (name, channel)) in obj.channels().into_iter().enumerate() {
+            let mut keys = BTreeMap::new();
+            for frame in channel.key_frames() {
+                keys.insert(frame, vec![(o, c, frame)]);
+                rows[object_row]
+                    .keys
+                    .entry(frame)
+                    .or_insert_with(Vec::new)
+                    .push((o, c, frame));
+            }
+
+            rows.push(TimelineRow {
+                label: name.to_string(),
+                channel: Some(c),
+                keys,
+            });
+        }
+    }
+    rows
+}
+
+// Returns the keyframes under the mouse, if there are any
+fn timeline_hit(
+    state: &UIState,
+    rows: &[TimelineRow],
+    keys_x0: f32,
+    y0: f32,
+    [mx, my]: [f32; 2],
+) -> Option<Vec<KeyRef>> {
+    let r = ((my - y0 - TIMELINE_RULER_HEIGHT) / TIMELINE_ROW_HEIGHT).floor();
+    if r < 0. || mx < keys_x0 {
+        return None;
+    }
+
+    let nearest = state.timeline_frame_at(keys_x0, mx).round().max(0.) as Frame;
+    let row = rows.get(r as usize)?;
+    row.keys
+        .range(nearest.saturating_sub(1)..=nearest + 1)
+        .find(|(&frame, _)| {
+            (state.timeline_x_at(keys_x0, frame as f32) - mx).abs() <= TIMELINE_KEY_RADIUS
+        })
+        .map(|(_, keys)| keys.clone())
+}
+
+// Moves every selected keyframe by delta frames, keeping them selected
+fn move_selected_keys(state: &mut UIState, objs: &mut [Object], delta: isize) {
+    // Group the selection by channel, so each channel's keyframes move together
+    let mut by_channel: BTreeMap<(usize, usize), Vec<Frame>> = BTreeMap::new();
+    for &(o, c, frame) in &state.selected_keys {
+        by_channel.entry((o, c)).or_default().push(frame);
+    }
+
+    state.selected_keys.clear();
+    for ((o, c), frames) in by_channel {
+        if let Some(obj) = objs.get_mut(o) {
+            if let Some((_, channel)) = obj.channels_mut().into_iter().nth(c) {
+                for frame in channel.shift_keys(&frames, delta) {
+                    state.selected_keys.insert((o, c, frame));
+                }
+            }
+        }
+    }
+}
+
+// Sizes used to lay out the graph editor, in pixels
+const GRAPH_POINT_RADIUS: f32 = 4.;
+
+// Colours of the x, y and z curves
+const GRAPH_COLORS: [[f32; 4]; 3] = [[1., 0.35, 0.35, 1.], [0.4, 1., 0.4, 1.], [0.4, 0.6, 1., 1.]];
+
+fn graph_editor(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &Playback,
+    history: &mut History,
+) {
+    Window::new(im_str!("Graph Editor"))
+        .position([900., 19.], Condition::Appearing)
+        .size([600., 400.], Condition::Appearing)
+        .build(ui, || {
+            // Like in [object_attributes], a copy of the object is edited and then replaces the
+            // object through the history
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+
+            // Channel selection
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let channel = &mut state.graph_channel;
+            ui.radio_button(im_str!("Position"), channel, GraphChannel::Position);
+            ui.same_line(0.);
+            ui.radio_button(im_str!("Rotation"), channel, GraphChannel::Rotation);
+            ui.same_line(0.);
+            ui.radio_button(im_str!("Scale"), channel, GraphChannel::Scale);
+            // -------------------------------------------------------------------------------------
+
+            // Selected points belong to one curve, forget them when looking at another
+            if state.graph_owner != Some((index, state.graph_channel)) {
+                state.graph_owner = Some((index, state.graph_channel));
+                state.graph_selected.clear();
+                state.graph_drag = None;
+            }
+
+            let mut change: Option<(&str, Option<&str>)> = None;
+            let (track, modifiers) = match state.graph_channel {
+                GraphChannel::Position => (&mut obj.position, &obj.position_modifiers),
+                GraphChannel::Rotation if obj.rotation_mode() == RotationMode::Euler => {
+                    (&mut obj.rotation, &obj.rotation_modifiers)
+                }
+                GraphChannel::Rotation => {
+                    ui.text("Switch to Euler rotation to edit rotation curves");
+                    state.keep_edited_object(history, index, obj);
+                    return;
+                }
+                GraphChannel::Scale => (&mut obj.scale, &obj.scale_modifiers),
+            };
+
+            // Handle type of the selected keyframes
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let handle_types = [
+                (im_str!("Auto"), Some(HandleType::Auto)),
+                (im_str!("Clamped"), Some(HandleType::AutoClamped)),
+                (im_str!("Vector"), Some(HandleType::Vector)),
+                (im_str!("Aligned"), Some(HandleType::Aligned)),
+                (im_str!("Free"), Some(HandleType::Free)),
+                (im_str!("No handles"), None),
+            ];
+            for (i, (label, kind)) in handle_types.iter().enumerate() {
+                if i > 0 {
+                    ui.same_line(0.);
+                }
+                if ui.button(label, [0., 20.]) {
+                    for frame in graph_selected_frames(&state.graph_selected) {
+                        match kind {
+                            Some(kind) => {
+                                track.set_handle_type(frame, HandleSide::In, *kind);
+                                track.set_handle_type(frame, HandleSide::Out, *kind);
+                            }
+                            None => {
+                                track.clear_handles(frame);
+                            }
+                        }
+                    }
+                    change = Some(("Change handle type", None));
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Frame all button, fitting every keyframe into view
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            let frame_all = ui.button(im_str!("Frame all"), [0., 20.]);
+            // -------------------------------------------------------------------------------------
+
+            // Modifiers window toggle
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            ui.checkbox(im_str!("Modifiers"), &mut state.show_modifiers);
+            // -------------------------------------------------------------------------------------
+
+            // Retiming of every keyframe from the first selected one to the last, around the first
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let selected = graph_selected_frames(&state.graph_selected);
+            ui.set_next_item_width(60.);
+            Drag::new(im_str!("Scale##retime"))
+                .speed(0.01)
+                .build(ui, &mut state.retime_scale);
+            ui.same_line(0.);
+            ui.set_next_item_width(60.);
+            Drag::new(im_str!("Offset##retime"))
+                .speed(0.1)
+                .build(ui, &mut state.retime_offset);
+            ui.same_line(0.);
+            let tooltip = "Select the keyframes to retime first";
+            if button_if(
+                ui,
+                !selected.is_empty(),
+                im_str!("Retime"),
+                tooltip,
+                [0., 20.],
+            ) {
+                let (first, last) = (selected.first().unwrap(), selected.last().unwrap());
+                let (scale, offset) = (state.retime_scale, state.retime_offset);
+                if track.retime(
+                    *first..=*last,
+                    *first as f32,
+                    scale,
+                    offset,
+                    state.key_collision,
+                ) > 0
+                {
+                    state.graph_selected.clear();
+                    change = Some(("Retime keyframes", None));
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Moves the keyframe closest to the current frame onto it
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            let current_frame = playback.frame();
+            let nearest = track
+                .nearest_key(current_frame as f32)
+                .filter(|f| *f != current_frame);
+            let tooltip = "There is no keyframe to move onto the current frame";
+            let label = im_str!("Key to playhead");
+            if button_if(ui, nearest.is_some(), label, tooltip, [0., 20.])
+                && track.move_key(nearest.unwrap(), current_frame, state.key_collision)
+            {
+                state.graph_selected.clear();
+                change = Some(("Move keyframe", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            // What happens to keyframes that retimed or moved ones land on
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.same_line(0.);
+            ui.set_next_item_width(80.);
+            choice_combo(
+                ui,
+                im_str!("On collision"),
+                &KeyCollision::ALL,
+                KeyCollision::name,
+                &mut state.key_collision,
+            );
+            // -------------------------------------------------------------------------------------
+
+            // Easing of the segments starting at the selected keyframes, shown for the first one.
+            // Segments with handles are bezier curves and ignore it
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if let Some(mut easing) = selected.first().and_then(|f| track.easing_at(*f)) {
+                let mut mode = easing.mode().unwrap_or(EaseMode::InOut);
+                ui.set_next_item_width(120.);
+                let mut changed = choice_combo(
+                    ui,
+                    im_str!("Easing"),
+                    &Easing::all(mode),
+                    Easing::name,
+                    &mut easing,
+                );
+                if easing != Easing::Linear {
+                    ui.same_line(0.);
+                    ui.set_next_item_width(100.);
+                    if choice_combo(
+                        ui,
+                        im_str!("Ease"),
+                        &EaseMode::ALL,
+                        EaseMode::name,
+                        &mut mode,
+                    ) {
+                        easing = easing.with_mode(mode);
+                        changed = true;
+                    }
+                }
+                if changed {
+                    for frame in &selected {
+                        track.set_easing_at(*frame, easing);
+                    }
+                    change = Some(("Change easing", None));
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // What the curve does before its first keyframe and after its last one
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if let Some((mut pre, mut post)) = track.extrapolation() {
+                let modes = &Extrapolation::ALL;
+                ui.set_next_item_width(120.);
+                let mut changed =
+                    choice_combo(ui, im_str!("Before"), modes, Extrapolation::name, &mut pre);
+                ui.same_line(0.);
+                ui.set_next_item_width(120.);
+                changed |=
+                    choice_combo(ui, im_str!("After"), modes, Extrapolation::name, &mut post);
+                if changed {
+                    track.set_extrapolation(pre, post);
+                    change = Some(("Change extrapolation", None));
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Canvas for the curves, which takes all mouse input
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let [x0, y0] = ui.cursor_screen_pos();
+            let [w, h] = ui.content_region_avail();
+            let (w, h) = (w.max(1.), h.max(1.));
+            ui.invisible_button(im_str!("##graph"), [w, h]);
+            let hovered = ui.is_item_hovered();
+            // -------------------------------------------------------------------------------------
+
+            // Curves are drawn from the bottom left corner up
+            let origin = [x0, y0 + h];
+            let [mx, my] = ui.io().mouse_pos;
+            let shift = ui.io().key_shift;
+
+            if frame_all {
+                state.graph_frame_all(track, [w, h]);
+            }
+
+            // Zoom around the mouse with the scroll wheel, pan by dragging with the middle button.
+            // Holding Ctrl only zooms values, holding Shift only zooms time
+            if hovered {
+                let wheel = ui.io().mouse_wheel;
+                if wheel != 0. {
+                    let under_mouse = state.graph_from_screen(origin, [mx, my]);
+                    let factor = 1.2f32.powf(wheel);
+                    if !ui.io().key_ctrl {
+                        state.graph_zoom[0] = (state.graph_zoom[0] * factor).clamp(0.5, 200.);
+                    }
+                    if !shift {
+                        state.graph_zoom[1] = (state.graph_zoom[1] * factor).clamp(0.01, 10000.);
+                    }
+                    state.graph_offset = [
+                        under_mouse[0] - (mx - origin[0]) / state.graph_zoom[0],
+                        under_mouse[1] - (origin[1] - my) / state.graph_zoom[1],
+                    ];
+                }
+                if ui.is_mouse_dragging(MouseButton::Middle) {
+                    let [dx, dy] = ui.io().mouse_delta;
+                    state.graph_offset[0] -= dx / state.graph_zoom[0];
+                    state.graph_offset[1] += dy / state.graph_zoom[1];
+                }
+            }
+
+            // Start of a left click: pick a keyframe or handle, or start a box selection
+            if hovered && ui.is_mouse_clicked(MouseButton::Left) {
+                let points = graph_points(track, &state.graph_selected);
+                let hit = points
+                    .iter()
+                    .map(|(p, pos)| (p, state.graph_to_screen(origin, *pos)))
+                    .map(|(p, [x, y])| (*p, (x - mx).hypot(y - my)))
+                    .filter(|(_, d)| *d <= GRAPH_POINT_RADIUS + 2.)
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+                match hit {
+                    Some((point, _)) => {
+                        let selected = state.graph_selected.contains(&point);
+                        if shift && selected {
+                            state.graph_selected.remove(&point);
+                        } else {
+                            if !shift && !selected {
+                                state.graph_selected.clear();
+                            }
+                            state.graph_selected.insert(point);
+                        }
+                        state.graph_drag = Some(GraphDrag::Points {
+                            start: [mx, my],
+                            original: track.clone(),
+                            delta: 0,
+                        });
+                    }
+                    None => {
+                        if !shift {
+                            state.graph_selected.clear();
+                        }
+                        state.graph_drag = Some(GraphDrag::Box([mx, my]));
+                    }
+                }
+            }
+
+            // Ongoing and finished drags. Dragged points are edited live, always starting over
+            // from the track as it was when the drag started so that keyframes passed over on the
+            // way aren't lost
+            let released = !ui.is_mouse_down(MouseButton::Left);
+            match &mut state.graph_drag {
+                Some(GraphDrag::Points {
+                    start,
+                    original,
+                    delta,
+                }) if *start != [mx, my] => {
+                    *track = original.clone();
+                    let frames = (mx - start[0]) / state.graph_zoom[0];
+                    let value = (start[1] - my) / state.graph_zoom[1];
+                    *delta = drag_graph_points(track, &state.graph_selected, frames, value);
+                    change = Some(("Move keyframes", Some("graph")));
+                }
+                Some(GraphDrag::Box(start)) if released => {
+                    let (min, max) = (
+                        [start[0].min(mx), start[1].min(my)],
+                        [start[0].max(mx), start[1].max(my)],
+                    );
+                    for (point, pos) in graph_points(track, &state.graph_selected) {
+                        let [x, y] = state.graph_to_screen(origin, pos);
+                        if x >= min[0] && x <= max[0] && y >= min[1] && y <= max[1] {
+                            state.graph_selected.insert(point);
+                        }
+                    }
+                }
+                _ => (),
+            }
+            if released {
+                // Selected points that moved in time are still selected at their new frames
+                if let Some(GraphDrag::Points { delta, .. }) = state.graph_drag {
+                    let moved = graph_selected_frames(&state.graph_selected);
+                    state.graph_selected = state
+                        .graph_selected
+                        .iter()
+                        .map(|p| p.shifted_if(&moved, delta))
+                        .collect();
+                }
+                state.graph_drag = None;
+            }
+
+            // Delete the selected keyframes
+            if ui.is_window_focused()
+                && ui.is_key_pressed(ui.key_index(Key::Delete))
+                && !state.graph_selected.is_empty()
+            {
+                for frame in graph_selected_frames(&state.graph_selected) {
+                    track.remove_at(frame);
+                }
+                state.graph_selected.clear();
+                change = Some(("Delete keyframes", None));
+            }
+
+            // Draw grid, curves, keyframes, handles and playhead
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let draw_list = ui.get_window_draw_list();
+            draw_list.with_clip_rect_intersect([x0, y0], [x0 + w, y0 + h], || {
+                draw_list
+                    .add_rect([x0, y0], [x0 + w, y0 + h], [0.1, 0.1, 0.1, 0.6])
+                    .filled(true)
+                    .build();
+
+                // Grid lines at regular steps of time and value, at least 50 pixels apart
+                let [first_frame, bottom] = state.graph_from_screen(origin, [x0, y0 + h]);
+                let [last_frame, top] = state.graph_from_screen(origin, [x0 + w, y0]);
+                let frame_step = grid_step(state.graph_zoom[0]).max(1.);
+                let value_step = grid_step(state.graph_zoom[1]);
+                let decimals = (-value_step.log10().floor()).max(0.) as usize;
+
+                let mut frame = (first_frame / frame_step).floor() * frame_step;
+                while frame <= last_frame {
+                    let [x, _] = state.graph_to_screen(origin, [frame, 0.]);
+                    draw_list
+                        .add_line([x, y0], [x, y0 + h], (0.25, 0.25, 0.25))
+                        .build();
+                    draw_list.add_text([x + 2., y0], (0.7, 0.7, 0.7), format!("{}", frame));
+                    frame += frame_step;
+                }
+
+                let mut value = (bottom / value_step).floor() * value_step;
+                while value <= top {
+                    let [_, y] = state.graph_to_screen(origin, [0., value]);
+                    let color = if value.abs() < value_step / 2. {
+                        (0.45, 0.45, 0.45)
+                    } else {
+                        (0.25, 0.25, 0.25)
+                    };
+                    draw_list.add_line([x0, y], [x0 + w, y], color).build();
+                    draw_list.add_text(
+                        [x0 + 2., y - 14.],
+                        (0.7, 0.7, 0.7),
+                        format!("{:.*}", decimals, value),
+                    );
+                    value += value_step;
+                }
+
+                // Curves are sampled every couple of pixels through the track's own evaluation,
+                // which at() uses for whole frames, so they match playback exactly. With
+                // modifiers, the keyed curve is faded and the modified one drawn over it
+                let samples: Vec<(f32, Vec3, Vec3)> = (0..=(w / 2.) as usize + 1)
+                    .map(|i| {
+                        let x = x0 + i as f32 * 2.;
+                        let [frame, _] = state.graph_from_screen(origin, [x, 0.]);
+                        let keyed = track.sample(frame);
+                        let modified = if modifiers.is_empty() {
+                            keyed
+                        } else {
+                            modifier::evaluate(track, modifiers, frame)
+                        };
+                        (x, keyed, modified)
+                    })
+                    .collect();
+                for (c, color) in GRAPH_COLORS.iter().enumerate() {
+                    let faded = [color[0], color[1], color[2], 0.35];
+                    for pair in samples.windows(2) {
+                        let (xa, ka, ma) = pair[0];
+                        let (xb, kb, mb) = pair[1];
+                        let [_, ya] = state.graph_to_screen(origin, [0., ka[c]]);
+                        let [_, yb] = state.graph_to_screen(origin, [0., kb[c]]);
+                        if modifiers.is_empty() {
+                            draw_list.add_line([xa, ya], [xb, yb], *color).build();
+                            continue;
+                        }
+                        draw_list.add_line([xa, ya], [xb, yb], faded).build();
+
+                        let [_, ya] = state.graph_to_screen(origin, [0., ma[c]]);
+                        let [_, yb] = state.graph_to_screen(origin, [0., mb[c]]);
+                        draw_list.add_line([xa, ya], [xb, yb], *color).build();
+                    }
+                }
+
+                // Keyframes and the handles of selected ones
+                for (point, pos) in graph_points(track, &state.graph_selected) {
+                    let [x, y] = state.graph_to_screen(origin, pos);
+                    let selected = state.graph_selected.contains(&point);
+                    let color = if selected {
+                        [1., 0.6, 0.1, 1.]
+                    } else {
+                        GRAPH_COLORS[point.component()]
+                    };
+
+                    match point {
+                        GraphPoint::Key(..) => {
+                            draw_list
+                                .add_rect(
+                                    [x - GRAPH_POINT_RADIUS, y - GRAPH_POINT_RADIUS],
+                                    [x + GRAPH_POINT_RADIUS, y + GRAPH_POINT_RADIUS],
+                                    color,
+                                )
+                                .filled(true)
+                                .build();
+                        }
+                        GraphPoint::In(frame, c) | GraphPoint::Out(frame, c) => {
+                            let key = track.value_at_key(frame).unwrap();
+                            let key_pos = state.graph_to_screen(origin, [frame as f32, key[c]]);
+                            draw_list.add_line(key_pos, [x, y], (0.8, 0.8, 0.8)).build();
+                            draw_list
+                                .add_circle([x, y], GRAPH_POINT_RADIUS, color)
+                                .filled(true)
+                                .build();
+                        }
+                    }
+                }
+
+                // Playhead
+                let [x, _] = state.graph_to_screen(origin, [playback.frame() as f32, 0.]);
+                draw_list
+                    .add_line([x, y0], [x, y0 + h], (1., 0.2, 0.2))
+                    .thickness(2.)
+                    .build();
+
+                // Box selection
+                if let Some(GraphDrag::Box(start)) = state.graph_drag {
+                    draw_list
+                        .add_rect(start, [mx, my], [0.4, 0.6, 1., 0.25])
+                        .filled(true)
+                        .build();
+                    draw_list.add_rect(start, [mx, my], (0.4, 0.6, 1.)).build();
+                }
+            });
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
+}
+
+// A point that can be picked in the graph editor: a keyframe or one of its handles, for one
+// component (x, y or z) of the curve
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum GraphPoint {
+    Key(Frame, usize),
+    In(Frame, usize),
+    Out(Frame, usize),
+}
+
+impl GraphPoint {
+    fn frame(self) -> Frame {
+        match self {
+            GraphPoint::Key(f, _) | GraphPoint::In(f, _) | GraphPoint::Out(f, _) => f,
+        }
+    }
+
+    fn component(self) -> usize {
+        match self {
+            GraphPoint::Key(_, c) | GraphPoint::In(_, c) | GraphPoint::Out(_, c) => c,
+        }
+    }
+
+    // The same point, delta frames later if its keyframe is one of the given frames
+    fn shifted_if(self, frames: &BTreeSet<Frame>, delta: isize) -> GraphPoint {
+        if !frames.contains(&self.frame()) {
+            return self;
+        }
+
+        let f = (self.frame() as isize + delta) as Frame;
+        match self {
+            GraphPoint::Key(_, c) => GraphPoint::Key(f, c),
+            GraphPoint::In(_, c) => GraphPoint::In(f, c),
+            GraphPoint::Out(_, c) => GraphPoint::Out(f, c),
+        }
+    }
+}
+
+// Which of the selected object's tracks the graph editor shows
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+enum GraphChannel {
+    #[default]
+    Position,
+    Rotation,
+    Scale,
+}
+
+// Something being dragged around the graph editor
+enum GraphDrag {
+    // Box selection, from where the drag started
+    Box([f32; 2]),
+
+    // Selected points, from where the drag started. The track as it was before the drag, and how
+    // many frames the selected keyframes have moved so far
+    Points {
+        start: [f32; 2],
+        original: KeyframeV3,
+        delta: isize,
+    },
+}
+
+// Returns every point of a track that can be picked, with its position as (frame, value). Handles
+// are only shown for keyframes that are selected or have a selected handle
+fn graph_points(
+    track: &KeyframeV3,
+    selected: &BTreeSet<GraphPoint>,
+) -> Vec<(GraphPoint, [f32; 2])> {
+    let frames: Vec<Frame> = track
+        .frames()
+        .map(|f| f.copied().collect())
+        .unwrap_or_default();
+    let mut points = Vec::new();
+
+    for frame in frames {
+        let key = track.key_at(frame).unwrap();
+        let f = frame as f32;
+        for c in 0..3 {
+            let v = key.value[c];
+            points.push((GraphPoint::Key(frame, c), [f, v]));
+
+            let show_handles = [
+                GraphPoint::Key(frame, c),
+                GraphPoint::In(frame, c),
+                GraphPoint::Out(frame, c),
+            ]
+            .iter()
+            .any(|p| selected.contains(p));
+            if !show_handles {
+                continue;
+            }
+
+            if let Some(h) = key.handle_in {
+                points.push((GraphPoint::In(frame, c), [f + h.frames, v + h.value[c]]));
+            }
+            if let Some(h) = key.handle_out {
+                points.push((GraphPoint::Out(frame, c), [f + h.frames, v + h.value[c]]));
+            }
+        }
+    }
+
+    points
+}
+
+// Frames of the keyframes that have a selected point
+fn graph_selected_frames(selected: &BTreeSet<GraphPoint>) -> BTreeSet<Frame> {
+    selected.iter().map(|p| p.frame()).collect()
+}
+
+// Moves the selected points by the given number of frames and value. Keyframes move in whole
+// frames, taking all of their components along, and their handles with them. Handles of keyframes
+// that aren't moving are moved on their own. Returns the number of frames keyframes moved by
+fn drag_graph_points(
+    track: &mut KeyframeV3,
+    selected: &BTreeSet<GraphPoint>,
+    frames: f32,
+    value: f32,
+) -> isize {
+    let key_frames: Vec<Frame> = selected
+        .iter()
+        .filter(|p| matches!(p, GraphPoint::Key(..)))
+        .map(|p| p.frame())
+        .collect::<BTreeSet<Frame>>()
+        .into_iter()
+        .collect();
+
+    let moved = track.shift_keys(&key_frames, frames.round() as isize);
+    let delta = match (key_frames.first(), moved.first()) {
+        (Some(&from), Some(&to)) => to as isize - from as isize,
+        _ => 0,
+    };
+
+    // Handles, grouped by side, so that a handle with several selected components moves once
+    let mut handles: BTreeMap<(Frame, bool), Vec<usize>> = BTreeMap::new();
+    for point in selected {
+        match *point {
+            GraphPoint::Key(f, c) => {
+                let f = (f as isize + delta) as Frame;
+                if let Some(mut v) = track.value_at_key(f) {
+                    v[c] += value;
+                    track.set_at(f, v);
+                }
+            }
+            GraphPoint::In(f, c) if !key_frames.contains(&f) => {
+                handles.entry((f, false)).or_default().push(c)
+            }
+            GraphPoint::Out(f, c) if !key_frames.contains(&f) => {
+                handles.entry((f, true)).or_default().push(c)
+            }
+            _ => (),
+        }
+    }
+
+    for ((f, out), components) in handles {
+        let (side, handle) = match (out, track.key_at(f)) {
+            (false, Some(key)) => (HandleSide::In, key.handle_in),
+            (true, Some(key)) => (HandleSide::Out, key.handle_out),
+            _ => continue,
+        };
+        if let Some(h) = handle {
+            let mut v = h.value;
+            for c in components {
+                v[c] += value;
+            }
+            track.set_handle(f, side, h.frames + frames, v);
+        }
+    }
+
+    delta
+}
+
+// Returns a step between grid lines that puts them at least 50 pixels apart, given how many
+// pixels one unit takes. Steps are 1, 2 or 5 times a power of ten
+fn grid_step(pixels_per_unit: f32) -> f32 {
+    let min = 50. / pixels_per_unit;
+    let power = 10f32.powf(min.log10().floor());
+    [1., 2., 5., 10.]
+        .iter()
+        .map(|m| m * power)
+        .find(|step| *step >= min)
+        .unwrap_or(10. * power)
+}
+
+fn modifiers_panel(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &mut Playback,
+    history: &mut History,
+) {
+    if !state.show_modifiers {
+        return;
+    }
+
+    let (graph_channel, auto_key) = (state.graph_channel, state.auto_key());
+
+    let mut opened = state.show_modifiers;
+    Window::new(im_str!("Modifiers"))
+        .position([600., 129.], Condition::Appearing)
+        .size([300., 490.], Condition::Appearing)
+        .opened(&mut opened)
+        .build(ui, || {
+            // Like in [object_attributes], a copy of the object is edited and then replaces the
+            // object through the history. The channel is the one shown in [graph_editor]
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+            // Name of the channel, its keyed value on this frame, and its modifiers
+            let frame = playback.frame();
+            let (channel, keyed, modifiers) = match graph_channel {
+                GraphChannel::Position => (
+                    "position",
+                    obj.position.at(frame),
+                    &mut obj.position_modifiers,
+                ),
+                GraphChannel::Rotation if obj.rotation_mode() == RotationMode::Euler => (
+                    "rotation",
+                    obj.rotation.at(frame),
+                    &mut obj.rotation_modifiers,
+                ),
+                GraphChannel::Rotation => {
+                    ui.text("Switch to Euler rotation to modify rotation");
+                    state.keep_edited_object(history, index, obj);
+                    return;
+                }
+                GraphChannel::Scale => ("scale", obj.scale.at(frame), &mut obj.scale_modifiers),
+            };
+            let mut change: Option<(&str, Option<&str>)> = None;
+
+            ui.text(format!("Modifiers on {} of {}", channel, obj.name));
+
+            // Buttons adding each kind of modifier to the end of the stack. Limits start out at
+            // the current value
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let kinds = [
+                ModifierKind::noise(modifiers.len() as u32),
+                ModifierKind::sine(),
+                ModifierKind::stepped(),
+                ModifierKind::limits(keyed),
+            ];
+            for (i, kind) in kinds.iter().enumerate() {
+                if i > 0 {
+                    ui.same_line(0.);
+                }
+                if ui.button(&im_str!("{}", kind.name()), [0., 20.]) {
+                    modifiers.push(Modifier::new(kind.clone()));
+                    change = Some(("Add modifier", None));
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // The stack, applied from top to bottom
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let count = modifiers.len();
+            let mut remove = None;
+            let mut swap = None;
+            for (i, m) in modifiers.iter_mut().enumerate() {
+                let id = ui.push_id(i as i32);
+                let open = CollapsingHeader::new(&im_str!("{}##header", m.kind.name()))
+                    .default_open(true)
+                    .build(ui);
+                if !open {
+                    id.pop(ui);
+                    continue;
+                }
+
+                // Mute, move and remove
+                if ui.checkbox(im_str!("Mute"), &mut m.muted) {
+                    change = Some(("Mute modifier", None));
+                }
+                ui.same_line(0.);
+                if button_if(ui, i > 0, im_str!("Up"), "Already first", [30., 20.]) {
+                    swap = Some((i - 1, i));
+                }
+                ui.same_line(0.);
+                if button_if(
+                    ui,
+                    i + 1 < count,
+                    im_str!("Down"),
+                    "Already last",
+                    [40., 20.],
+                ) {
+                    swap = Some((i, i + 1));
+                }
+                ui.same_line(0.);
+                if ui.button(im_str!("Remove"), [0., 20.]) {
+                    remove = Some(i);
+                }
+
+                // Parameters of each kind
+                let params: Vec<Option<(&str, Option<&str>)>> = match &mut m.kind {
+                    ModifierKind::Noise {
+                        seed,
+                        amplitude,
+                        frequency,
+                        phase,
+                    } => {
+                        let seeded = Drag::new(im_str!("Seed")).build(ui, seed);
+                        vec![
+                            seeded.then_some(("Change modifier", Some("Change modifier"))),
+                            keyable_drag(
+                                ui,
+                                im_str!("Amplitude"),
+                                amplitude,
+                                0.01,
+                                playback,
+                                auto_key,
+                                "Change modifier",
+                            ),
+                            keyable_drag(
+                                ui,
+                                im_str!("Frequency"),
+                                frequency,
+                                0.001,
+                                playback,
+                                auto_key,
+                                "Change modifier",
+                            ),
+                            keyable_drag(
+                                ui,
+                                im_str!("Phase"),
+                                phase,
+                                0.01,
+                                playback,
+                                auto_key,
+                                "Change modifier",
+                            ),
+                        ]
+                    }
+                    ModifierKind::Sine {
+                        amplitude,
+                        frequency,
+                        phase,
+                    } => vec![
+                        keyable_drag(
+                            ui,
+                            im_str!("Amplitude"),
+                            amplitude,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        ),
+                        keyable_drag(
+                            ui,
+                            im_str!("Frequency"),
+                            frequency,
+                            0.001,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        ),
+                        keyable_drag(
+                            ui,
+                            im_str!("Phase"),
+                            phase,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        ),
+                    ],
+                    ModifierKind::Stepped { step, offset } => vec![
+                        keyable_drag(
+                            ui,
+                            im_str!("Step"),
+                            step,
+                            0.05,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        ),
+                        keyable_drag(
+                            ui,
+                            im_str!("Offset"),
+                            offset,
+                            0.05,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        ),
+                    ],
+                    ModifierKind::Limits {
+                        min,
+                        max,
+                        use_min,
+                        use_max,
+                    } => {
+                        let min_used = ui.checkbox(im_str!("Use min"), use_min);
+                        let min_changed = keyable_drag(
+                            ui,
+                            im_str!("Min"),
+                            min,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        );
+                        let max_used = ui.checkbox(im_str!("Use max"), use_max);
+                        let max_changed = keyable_drag(
+                            ui,
+                            im_str!("Max"),
+                            max,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Change modifier",
+                        );
+                        vec![
+                            min_used.then_some(("Change modifier", None)),
+                            min_changed,
+                            max_used.then_some(("Change modifier", None)),
+                            max_changed,
+                        ]
+                    }
+                };
+                if let Some(param) = params.into_iter().flatten().last() {
+                    change = Some(param);
+                }
+
+                // Influence and frame range, shared by every kind
+                if let Some(c) = keyable_drag(
+                    ui,
+                    im_str!("Influence"),
+                    &mut m.influence,
+                    0.01,
+                    playback,
+                    auto_key,
+                    "Change modifier",
+                ) {
+                    change = Some(c);
+                }
+                let mut limited = m.range.is_some();
+                if ui.checkbox(im_str!("Frame range"), &mut limited) {
+                    let (start, end) = playback.range();
+                    m.range = if limited { Some((start, end)) } else { None };
+                    change = Some(("Change modifier range", None));
+                }
+                if let Some((start, end)) = &mut m.range {
+                    let mut range = [*start as u32, *end as u32];
+                    if Drag::new(im_str!("Frames")).build_array(ui, &mut range) {
+                        *start = range[0].min(range[1]) as Frame;
+                        *end = range[0].max(range[1]) as Frame;
+                        change = Some(("Change modifier range", Some("modifier range")));
+                    }
+                }
+
+                id.pop(ui);
+            }
+            if let Some((a, b)) = swap {
+                modifiers.swap(a, b);
+                change = Some(("Reorder modifiers", None));
+            }
+            if let Some(i) = remove {
+                modifiers.remove(i);
+                change = Some(("Remove modifier", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
+
+    state.show_modifiers = opened;
+}
+
+// Drag for a keyable value with key buttons below it, editing it on the current frame. Returns the
+// name and merge key of the change made, if any. Edits are named edit
+fn keyable_drag<T: Interpolate>(
+    ui: &Ui,
+    label: &ImStr,
+    track: &mut Track<T>,
+    speed: f32,
+    playback: &mut Playback,
+    auto_key: AutoKey,
+    edit: &'static str,
+) -> Option<(&'static str, Option<&'static str>)> {
+    let frame = playback.frame();
+    let mut change = None;
+
+    let mut value = track.at(frame);
+    let mut components: Vec<f32> = (0..T::COMPONENTS).map(|i| value.component(i)).collect();
+    if Drag::new(label)
+        .speed(speed)
+        .display_format(im_str!("%.3f"))
+        .build_array(ui, &mut components)
+    {
+        for (i, c) in components.into_iter().enumerate() {
+            value.set_component(i, c);
+        }
+        track.edit_at(frame, value, auto_key);
+        change = Some((edit, Some(edit)));
+    }
+    if let Some(name) = key_buttons(ui, label.to_str(), track, playback) {
+        change = Some((name, None));
+    }
+
+    change
+}
+
+fn strips_panel(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &mut Playback,
+    history: &mut History,
+) {
+    Window::new(im_str!("Strips"))
+        .position([900., 419.], Condition::Appearing)
+        .size([600., 200.], Condition::Appearing)
+        .build(ui, || {
+            // Widgets edit a copy of the object, like in [object_attributes]
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+            let mut change: Option<(&str, Option<&str>)> = None;
+
+            // Making a clip out of the object's keyframes
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.set_next_item_width(200.);
+            ui.input_text(im_str!("##clip_name"), &mut state.new_clip_name)
+                .build();
+            ui.same_line(0.);
+            if ui.button(im_str!("Make clip"), [0., 0.]) {
+                let name = if state.new_clip_name.is_empty() {
+                    ImString::new(format!("{} clip", obj.name))
+                } else {
+                    state.new_clip_name.clone()
+                };
+
+                // The object has changed, so the copy is out of date
+                match history.push_down(project, index, name) {
+                    Ok(clip) => {
+                        state.new_clip_name.clear();
+                        state.clip_error = None;
+                        state.strip_clip = Some(clip);
+                        return;
+                    }
+                    Err(e) => state.clip_error = Some(e),
+                }
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(
+                    "Moves the position, rotation and scale keyframes into a new clip, \
+                     played back by a strip in their place",
+                );
+            }
+            if let Some(e) = &state.clip_error {
+                ui.text_colored([1., 0.3, 0.3, 1.], e);
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Adding a strip for any clip, starting on the current frame
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let preview = match state.strip_clip {
+                Some(c) => project.clips[c].name.clone(),
+                None => ImString::new("Pick a clip"),
+            };
+            ui.set_next_item_width(200.);
+            ComboBox::new(im_str!("##strip_clip"))
+                .preview_value(&preview)
+                .build(ui, || {
+                    for (i, clip) in project.clips.iter().enumerate() {
+                        let id = ui.push_id(i as i32);
+                        if Selectable::new(&clip.name)
+                            .selected(state.strip_clip == Some(i))
+                            .build(ui)
+                        {
+                            state.strip_clip = Some(i);
+                        }
+                        id.pop(ui);
+                    }
+                });
+            ui.same_line(0.);
+            if let Some(clip) = state.strip_clip {
+                if ui.button(im_str!("Add strip"), [0., 0.]) {
+                    obj.strips.push(Strip::new(clip, playback.frame() as f32));
+                    change = Some(("Add strip", None));
+                }
+            } else {
+                ui.text_disabled("Add strip");
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Strips, blended on top of the object's own animation from top to bottom
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut remove = None;
+            for (i, strip) in obj.strips.iter_mut().enumerate() {
+                ui.separator();
+                let id = ui.push_id(i as i32);
+
+                let clip = match project.clips.get(strip.clip) {
+                    Some(clip) => clip,
+                    None => {
+                        ui.text_disabled("Missing clip");
+                        id.pop(ui);
+                        continue;
+                    }
+                };
+                ui.text(format!(
+                    "{}, frames {:.1} to {:.1}",
+                    clip.name,
+                    strip.start,
+                    strip.end(clip)
+                ));
+
+                if ui.checkbox(im_str!("Mute"), &mut strip.muted) {
+                    change = Some(("Mute strip", None));
+                }
+                ui.same_line(0.);
+                let mode = strip.mode;
+                ui.radio_button(im_str!("Replace"), &mut strip.mode, StripMode::Replace);
+                ui.same_line(0.);
+                ui.radio_button(im_str!("Additive"), &mut strip.mode, StripMode::Additive);
+                if strip.mode != mode {
+                    change = Some(("Change strip mode", None));
+                }
+                ui.same_line(0.);
+                if ui.button(im_str!("Remove"), [0., 0.]) {
+                    remove = Some(i);
+                }
+
+                if Drag::new(im_str!("Start"))
+                    .speed(0.1)
+                    .display_format(im_str!("%.1f"))
+                    .build(ui, &mut strip.start)
+                {
+                    change = Some(("Move strip", Some("strip start")));
+                }
+                if Drag::new(im_str!("Time scale"))
+                    .range(0.01..=100.)
+                    .speed(0.01)
+                    .display_format(im_str!("%.2f"))
+                    .build(ui, &mut strip.time_scale)
+                {
+                    change = Some(("Change strip time scale", Some("strip time scale")));
+                }
+                if Drag::new(im_str!("Repeat"))
+                    .range(0.01..=1000.)
+                    .speed(0.01)
+                    .display_format(im_str!("%.2f"))
+                    .build(ui, &mut strip.repeat)
+                {
+                    change = Some(("Change strip repeat", Some("strip repeat")));
+                }
+                let mut blend = [strip.blend_in, strip.blend_out];
+                if Drag::new(im_str!("Blend in/out"))
+                    .range(0.0..=1000.)
+                    .speed(0.1)
+                    .display_format(im_str!("%.1f"))
+                    .build_array(ui, &mut blend)
+                {
+                    strip.blend_in = blend[0];
+                    strip.blend_out = blend[1];
+                    change = Some(("Change strip blend", Some("strip blend")));
+                }
+
+                id.pop(ui);
+            }
+            if let Some(i) = remove {
+                obj.strips.remove(i);
+                change = Some(("Remove strip", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
+}
+
+fn layers_panel(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &mut Playback,
+    history: &mut History,
+) {
+    if !state.show_layers {
+        return;
+    }
+
+    let auto_key = state.auto_key();
+
+    let mut opened = state.show_layers;
+    Window::new(im_str!("Layers"))
+        .position([300., 129.], Condition::Appearing)
+        .size([300., 490.], Condition::Appearing)
+        .opened(&mut opened)
+        .build(ui, || {
+            // Widgets edit a copy of the object, like in [object_attributes]
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+            let mut change: Option<(&str, Option<&str>)> = None;
+
+            // Adding layers on top of the stack
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let name = ImString::new(format!("Layer {}", obj.layers.len() + 1));
+            if ui.button(im_str!("Add additive layer"), [0., 20.]) {
+                obj.layers
+                    .push(Layer::new(name.clone(), LayerMode::Additive));
+                change = Some(("Add layer", None));
+            }
+            ui.same_line(0.);
+            if ui.button(im_str!("Add override layer"), [0., 20.]) {
+                obj.layers.push(Layer::new(name, LayerMode::Override));
+                change = Some(("Add layer", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            // The stack, blended on top of the object's own tracks from top to bottom
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let count = obj.layers.len();
+            let mut remove = None;
+            let mut swap = None;
+            for (i, layer) in obj.layers.iter_mut().enumerate() {
+                let id = ui.push_id(i as i32);
+                let open = CollapsingHeader::new(&im_str!("{}##header", layer.name))
+                    .default_open(true)
+                    .build(ui);
+                if !open {
+                    id.pop(ui);
+                    continue;
+                }
+
+                if ui
+                    .input_text(im_str!("Name"), &mut layer.name)
+                    .resize_buffer(true)
+                    .build()
+                {
+                    change = Some(("Rename layer", Some("layer name")));
+                }
+
+                // Mute, solo, move and remove
+                if ui.checkbox(im_str!("Mute"), &mut layer.muted) {
+                    change = Some(("Mute layer", None));
+                }
+                ui.same_line(0.);
+                if ui.checkbox(im_str!("Solo"), &mut layer.solo) {
+                    change = Some(("Solo layer", None));
+                }
+                ui.same_line(0.);
+                if button_if(ui, i > 0, im_str!("Up"), "Already first", [30., 20.]) {
+                    swap = Some((i - 1, i));
+                }
+                ui.same_line(0.);
+                if button_if(
+                    ui,
+                    i + 1 < count,
+                    im_str!("Down"),
+                    "Already last",
+                    [40., 20.],
+                ) {
+                    swap = Some((i, i + 1));
+                }
+                ui.same_line(0.);
+                if ui.button(im_str!("Remove"), [0., 20.]) {
+                    remove = Some(i);
+                }
+
+                let mode = layer.mode;
+                ui.radio_button(im_str!("Additive"), &mut layer.mode, LayerMode::Additive);
+                ui.same_line(0.);
+                ui.radio_button(im_str!("Override"), &mut layer.mode, LayerMode::Override);
+                if layer.mode != mode {
+                    change = Some(("Change layer mode", None));
+                }
+
+                let edits = vec![
+                    keyable_drag(
+                        ui,
+                        im_str!("Weight"),
+                        &mut layer.weight,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Change layer",
+                    ),
+                    keyable_drag(
+                        ui,
+                        im_str!("Position"),
+                        &mut layer.position,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Change layer",
+                    ),
+                    keyable_drag(
+                        ui,
+                        im_str!("Rotation"),
+                        &mut layer.rotation,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Change layer",
+                    ),
+                    keyable_drag(
+                        ui,
+                        im_str!("Scale"),
+                        &mut layer.scale,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Change layer",
+                    ),
+                ];
+                if let Some(edit) = edits.into_iter().flatten().last() {
+                    change = Some(edit);
+                }
+
+                id.pop(ui);
+            }
+            if let Some((a, b)) = swap {
+                obj.layers.swap(a, b);
+                change = Some(("Reorder layers", None));
+            }
+            if let Some(i) = remove {
+                obj.layers.remove(i);
+                change = Some(("Remove layer", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
+
+    state.show_layers = opened;
+}
+
+fn constraints_panel(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    playback: &mut Playback,
+    history: &mut History,
+) {
+    if !state.show_constraints {
+        return;
+    }
+
+    let auto_key = state.auto_key();
+
+    let mut opened = state.show_constraints;
+    Window::new(im_str!("Constraints"))
+        .position([300., 129.], Condition::Appearing)
+        .size([300., 490.], Condition::Appearing)
+        .opened(&mut opened)
+        .build(ui, || {
+            // Widgets edit a copy of the object, like in [object_attributes]
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+            let mut change: Option<(&str, Option<&str>)> = None;
+
+            // Where the object is now, for starting limits and paths off from
+            let frame = playback.frame();
+            let world = constraint::decompose(&project.world_mats_at(frame as f32)[index]);
+
+            // Buttons adding each kind of constraint to the end of the stack
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let channels = [
+                (TransformChannel::Location, world.position),
+                (
+                    TransformChannel::Rotation,
+                    quat_to_euler(&world.rotation, &Vec3::zeros()),
+                ),
+                (TransformChannel::Scale, world.scale),
+            ];
+            let rows = [
+                vec![
+                    ConstraintKind::track_to(),
+                    ConstraintKind::follow_path(world.position),
+                ],
+                channels
+                    .iter()
+                    .map(|(channel, _)| ConstraintKind::copy(*channel))
+                    .collect(),
+                channels
+                    .iter()
+                    .map(|(channel, value)| ConstraintKind::limit(*channel, *value))
+                    .collect(),
+            ];
+            for row in &rows {
+                for (i, kind) in row.iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.);
+                    }
+                    if ui.button(&im_str!("{}", kind.name()), [0., 20.]) {
+                        obj.constraints.push(Constraint::new(kind.clone()));
+                        change = Some(("Add constraint", None));
+                    }
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // The stack, applied from top to bottom
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let count = obj.constraints.len();
+            let mut remove = None;
+            let mut swap = None;
+            for (i, c) in obj.constraints.iter_mut().enumerate() {
+                let id = ui.push_id(i as i32);
+                let open = CollapsingHeader::new(&im_str!("{}##header", c.kind.name()))
+                    .default_open(true)
+                    .build(ui);
+                if !open {
+                    id.pop(ui);
+                    continue;
+                }
+
+                // Mute, move and remove
+                if ui.checkbox(im_str!("Mute"), &mut c.muted) {
+                    change = Some(("Mute constraint", None));
+                }
+                ui.same_line(0.);
+                if button_if(ui, i > 0, im_str!("Up"), "Already first", [30., 20.]) {
+                    swap = Some((i - 1, i));
+                }
+                ui.same_line(0.);
+                if button_if(
+                    ui,
+                    i + 1 < count,
+                    im_str!("Down"),
+                    "Already last",
+                    [40., 20.],
+                ) {
+                    swap = Some((i, i + 1));
+                }
+                ui.same_line(0.);
+                if ui.button(im_str!("Remove"), [0., 20.]) {
+                    remove = Some(i);
+                }
+
+                // Settings of each kind
+                let edits: Vec<Option<(&str, Option<&str>)>> = match &mut c.kind {
+                    ConstraintKind::TrackTo {
+                        target,
+                        point,
+                        track,
+                        up,
+                    } => {
+                        let targeted = target_combo(ui, project, index, target, "Point");
+                        let pointed = match target {
+                            Some(_) => None,
+                            None => keyable_drag(
+                                ui,
+                                im_str!("Point"),
+                                point,
+                                0.01,
+                                playback,
+                                auto_key,
+                                "Change constraint",
+                            ),
+                        };
+                        vec![
+                            targeted.then_some(("Change constraint target", None)),
+                            pointed,
+                            axis_combo(ui, im_str!("Track axis"), track)
+                                .then_some(("Change constraint", None)),
+                            axis_combo(ui, im_str!("Up axis"), up)
+                                .then_some(("Change constraint", None)),
+                        ]
+                    }
+                    ConstraintKind::Copy {
+                        target,
+                        axes,
+                        offset,
+                        ..
+                    } => {
+                        let targeted = target_combo(ui, project, index, target, "None");
+                        let masked = axis_checkboxes(ui, "", axes);
+                        vec![
+                            targeted.then_some(("Change constraint target", None)),
+                            masked.then_some(("Change constraint", None)),
+                            ui.checkbox(im_str!("Offset"), offset)
+                                .then_some(("Change constraint", None)),
+                        ]
+                    }
+                    ConstraintKind::Limit {
+                        min,
+                        max,
+                        use_min,
+                        use_max,
+                        ..
+                    } => {
+                        let min_used = axis_checkboxes(ui, "Min ", use_min);
+                        let min_changed = keyable_drag(
+                            ui,
+                            im_str!("Min"),
+                            min,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Change constraint",
+                        );
+                        let max_used = axis_checkboxes(ui, "Max ", use_max);
+                        let max_changed = keyable_drag(
+                            ui,
+                            im_str!("Max"),
+                            max,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Change constraint",
+                        );
+                        vec![
+                            min_used.then_some(("Change constraint", None)),
+                            min_changed,
+                            max_used.then_some(("Change constraint", None)),
+                            max_changed,
+                        ]
+                    }
+                    ConstraintKind::FollowPath {
+                        points,
+                        offset,
+                        follow,
+                        forward,
+                        up,
+                    } => {
+                        let mut edits = vec![keyable_drag(
+                            ui,
+                            im_str!("Offset"),
+                            offset,
+                            0.005,
+                            playback,
+                            auto_key,
+                            "Change constraint",
+                        )];
+                        edits.push(
+                            ui.checkbox(im_str!("Follow"), follow)
+                                .then_some(("Change constraint", None)),
+                        );
+                        if *follow {
+                            edits.push(
+                                axis_combo(ui, im_str!("Forward axis"), forward)
+                                    .then_some(("Change constraint", None)),
+                            );
+                            edits.push(
+                                axis_combo(ui, im_str!("Up axis"), up)
+                                    .then_some(("Change constraint", None)),
+                            );
+                        }
+
+                        // Points of the path, in world space
+                        let mut remove_point = None;
+                        for (p, point) in points.iter_mut().enumerate() {
+                            let id = ui.push_id(p as i32);
+                            let mut values: [f32; 3] = (*point).into();
+                            if Drag::new(&im_str!("Point {}", p + 1))
+                                .speed(0.01)
+                                .display_format(im_str!("%.3f"))
+                                .build_array(ui, &mut values)
+                            {
+                                *point = values.into();
+                                edits.push(Some(("Move path point", Some("path point"))));
+                            }
+                            ui.same_line(0.);
+                            if ui.small_button(im_str!("x")) {
+                                remove_point = Some(p);
+                            }
+                            id.pop(ui);
+                        }
+                        if let Some(p) = remove_point {
+                            points.remove(p);
+                            edits.push(Some(("Remove path point", None)));
+                        }
+                        if ui.button(im_str!("Add point"), [0., 20.]) {
+                            points.push(world.position);
+                            edits.push(Some(("Add path point", None)));
+                        }
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text("Adds a point where the object is now");
+                        }
+                        edits
+                    }
+                };
+                if let Some(edit) = edits.into_iter().flatten().last() {
+                    change = Some(edit);
+                }
+
+                // Influence, shared by every kind
+                if let Some(c) = keyable_drag(
+                    ui,
+                    im_str!("Influence"),
+                    &mut c.influence,
+                    0.01,
+                    playback,
+                    auto_key,
+                    "Change constraint",
+                ) {
+                    change = Some(c);
+                }
+
+                id.pop(ui);
+            }
+            if let Some((a, b)) = swap {
+                obj.constraints.swap(a, b);
+                change = Some(("Reorder constraints", None));
+            }
+            if let Some(i) = remove {
+                obj.constraints.remove(i);
+                change = Some(("Remove constraint", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
+
+    state.show_constraints = opened;
+}
+
+fn armature_panel(
+    ui: &mut Ui,
+    state: &mut UIState,
+    project: &mut Project,
+    display: &Display,
+    playback: &mut Playback,
+    history: &mut History,
+) {
+    if !state.show_armature {
+        return;
+    }
+
+    let auto_key = state.auto_key();
+    let mut opened = true;
+
+    Window::new(im_str!("Armature"))
+        .position([300., 129.], Condition::Appearing)
+        .size([300., 490.], Condition::Appearing)
+        .opened(&mut opened)
+        .build(ui, || {
+            // Widgets edit a copy of the object, like in [object_attributes]
+            let (index, mut obj) = match state.take_edited_object(project, history) {
+                Some(edited) => edited,
+                None => {
+                    ui.text("Select an object first");
+                    return;
+                }
+            };
+            let mut change: Option<(&str, Option<&str>)> = None;
+
+            let armature = match &mut obj.armature {
+                Some(armature) => armature,
+                None => {
+                    if ui.button(im_str!("Add armature"), [0., 20.]) {
+                        obj.armature = Some(Armature::default());
+                        history.edit_object(project, index, "Add armature", None, |o| {
+                            *o = obj.clone()
+                        });
+                    }
+                    state.keep_edited_object(history, index, obj);
+                    return;
+                }
+            };
+
+            // Skinning the mesh to the bones
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if button_if(
+                ui,
+                !armature.bones.is_empty(),
+                im_str!("Bind mesh"),
+                "Add a bone first",
+                [0., 20.],
+            ) {
+                let positions: Vec<Vec3> = obj
+                    .mesh
+                    .vertices
+                    .iter()
+                    .map(|v| Vec3::from(v.position))
+                    .collect();
+                let skin = armature.auto_weights(&positions);
+                obj.mesh = obj.mesh.skinned(display, &skin);
+                change = Some(("Bind mesh to armature", None));
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(
+                    "Makes the mesh follow the bones nearest to each vertex at rest. Bind again \
+                     after moving bones at rest",
+                );
+            }
+            if ui.checkbox(
+                im_str!("Dual quaternion skinning"),
+                &mut armature.dual_quaternion,
+            ) {
+                change = Some(("Change skinning", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Bone hierarchy, with children indented under their parents
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.separator();
+            let mut depths: Vec<usize> = Vec::with_capacity(armature.bones.len());
+            for (i, bone) in armature.bones.iter().enumerate() {
+                let depth = bone.parent.map_or(0, |p| depths[p] + 1);
+                depths.push(depth);
+
+                let id = ui.push_id(i as i32);
+                let label = ImString::new(format!("{}{}", "  ".repeat(depth), bone.name));
+                if Selectable::new(&label)
+                    .selected(state.selected_bone == Some(i))
+                    .build(ui)
+                {
+                    state.selected_bone = Some(i);
+                }
+                id.pop(ui);
+            }
+
+            // New bones carry on from the end of the selected bone, or start at the origin
+            let parent = state.selected_bone;
+            if ui.button(im_str!("Add bone"), [0., 20.]) {
+                let head = parent.map_or(Vec3::zeros(), |p| armature.bones[p].tail);
+                let tail = head + Vec3::new(0., 1., 0.);
+                let name = ImString::new(format!("Bone {}", armature.bones.len() + 1));
+                match armature.add_bone(Bone::new(name, parent, head, tail)) {
+                    Ok(bone) => {
+                        state.selected_bone = Some(bone);
+                        state.bone_error = None;
+                        change = Some(("Add bone", None));
+                    }
+                    Err(e) => state.bone_error = Some(e),
+                }
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Adds a child of the selected bone");
+            }
+            if let Some(e) = &state.bone_error {
+                ui.text_colored([1., 0.3, 0.3, 1.], e);
+            }
+            // -------------------------------------------------------------------------------------
+
+            // The selected bone's rest placement and pose
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            if let Some(bone) = state.selected_bone.and_then(|b| armature.bones.get_mut(b)) {
+                ui.separator();
+                if ui
+                    .input_text(im_str!("Name"), &mut bone.name)
+                    .resize_buffer(true)
+                    .build()
+                {
+                    change = Some(("Rename bone", Some("bone name")));
+                }
+
+                ui.text("Rest");
+                for (label, point) in [
+                    (im_str!("Head"), &mut bone.head),
+                    (im_str!("Tail"), &mut bone.tail),
+                ] {
+                    let mut values: [f32; 3] = (*point).into();
+                    if Drag::new(label)
+                        .speed(0.01)
+                        .display_format(im_str!("%.3f"))
+                        .build_array(ui, &mut values)
+                    {
+                        *point = values.into();
+                        change = Some(("Move bone at rest", Some("bone rest")));
+                    }
+                }
+
+                ui.text("Pose");
+                let edits = vec![
+                    keyable_drag(
+                        ui,
+                        im_str!("Position"),
+                        &mut bone.position,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Pose bone",
+                    ),
+                    keyable_drag(
+                        ui,
+                        im_str!("Rotation"),
+                        &mut bone.rotation,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Pose bone",
+                    ),
+                    keyable_drag(
+                        ui,
+                        im_str!("Scale"),
+                        &mut bone.scale,
+                        0.01,
+                        playback,
+                        auto_key,
+                        "Pose bone",
+                    ),
+                ];
+                if let Some(edit) = edits.into_iter().flatten().last() {
+                    change = Some(edit);
+                }
+
+                // How far inverse kinematics may bend the bone away from its parent
+                let mut limited = bone.ik_limit.is_some();
+                if ui.checkbox(im_str!("Limit bend"), &mut limited) {
+                    bone.ik_limit = limited.then_some((0., std::f32::consts::PI));
+                    change = Some(("Limit bone bend", None));
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Keeps IK chains from bending the bone past these angles");
+                }
+                if let Some((min, max)) = &mut bone.ik_limit {
+                    let mut degrees = [min.to_degrees(), max.to_degrees()];
+                    if Drag::new(im_str!("Bend (degrees)"))
+                        .range(0.0..=180.0)
+                        .display_format(im_str!("%.1f"))
+                        .build_array(ui, &mut degrees)
+                    {
+                        *min = degrees[0].min(degrees[1]).to_radians();
+                        *max = degrees[1].max(degrees[0]).to_radians();
+                        change = Some(("Limit bone bend", Some("bone limit")));
+                    }
+                }
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Inverse kinematics chains, solved from top to bottom after the keyed pose
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            ui.separator();
+            let tip = state.selected_bone.filter(|&b| b < armature.bones.len());
+            if button_if(
+                ui,
+                tip.is_some(),
+                im_str!("Add IK chain"),
+                "Select the bone at the end of the chain first",
+                [0., 20.],
+            ) {
+                // Starts out reaching for where the bone's tail already is, so nothing jumps
+                let tip = tip.unwrap();
+                let mats = armature.pose_mats(&armature.poses(playback.frame() as f32));
+                let bone = &armature.bones[tip];
+                let offset = bone.tail - bone.head;
+                let target = (mats[tip] * Vec4::new(offset.x, offset.y, offset.z, 1.)).xyz();
+                armature.chains.push(IkChain::new(tip, target));
+                change = Some(("Add IK chain", None));
+            }
+
+            let Armature { bones, chains, .. } = armature;
+            let mut remove = None;
+            for (i, chain) in chains.iter_mut().enumerate() {
+                let id = ui.push_id(i as i32);
+                let name = bones.get(chain.tip).map_or("", |b| b.name.to_str());
+                let open = CollapsingHeader::new(&im_str!("IK: {}##chain", name))
+                    .default_open(true)
+                    .build(ui);
+                if !open {
+                    id.pop(ui);
+                    continue;
+                }
+
+                // Mute and remove
+                if ui.checkbox(im_str!("Mute"), &mut chain.muted) {
+                    change = Some(("Mute IK chain", None));
+                }
+                ui.same_line(0.);
+                if ui.button(im_str!("Remove"), [0., 20.]) {
+                    remove = Some(i);
+                }
+
+                // Solver, and how many bones it turns
+                let solver = chain.solver;
+                ui.radio_button(im_str!("Two bone"), &mut chain.solver, IkSolver::TwoBone);
+                ui.same_line(0.);
+                ui.radio_button(im_str!("FABRIK"), &mut chain.solver, IkSolver::Fabrik);
+                if chain.solver != solver {
+                    chain.length = match chain.solver {
+                        IkSolver::TwoBone => 2,
+                        IkSolver::Fabrik => chain.length.max(3),
+                    };
+                    change = Some(("Change IK solver", None));
+                }
+                if chain.solver == IkSolver::Fabrik {
+                    let mut length = chain.length as u32;
+                    if Drag::new(im_str!("Chain length"))
+                        .range(1..=MAX_BONES as u32)
+                        .display_format(im_str!("%d bones"))
+                        .build(ui, &mut length)
+                    {
+                        chain.length = length.max(1) as usize;
+                        change = Some(("Change IK chain", Some("ik length")));
+                    }
+                    if Drag::new(im_str!("Iterations"))
+                        .range(1..=100)
+                        .build(ui, &mut chain.iterations)
+                    {
+                        change = Some(("Change IK chain", Some("ik iterations")));
+                    }
+                }
+
+                // Where the chain reaches for and bends towards
+                let mut edits = vec![keyable_drag(
+                    ui,
+                    im_str!("Target"),
+                    &mut chain.target,
+                    0.01,
+                    playback,
+                    auto_key,
+                    "Move IK target",
+                )];
+                if chain.solver == IkSolver::TwoBone {
+                    let mut use_pole = chain.pole.is_some();
+                    if ui.checkbox(im_str!("Use pole"), &mut use_pole) {
+                        let front = bones[chain.tip].head + Vec3::new(0., 0., 1.);
+                        chain.pole = use_pole.then(|| Track::new(front));
+                        edits.push(Some(("Change IK pole", None)));
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Bends the chain towards a point, such as a knee or elbow");
+                    }
+                    if let Some(pole) = &mut chain.pole {
+                        edits.push(keyable_drag(
+                            ui,
+                            im_str!("Pole"),
+                            pole,
+                            0.01,
+                            playback,
+                            auto_key,
+                            "Move IK pole",
+                        ));
+                    }
+                }
+                edits.push(keyable_drag(
+                    ui,
+                    im_str!("IK/FK blend"),
+                    &mut chain.blend,
+                    0.01,
+                    playback,
+                    auto_key,
+                    "Change IK/FK blend",
+                ));
+                if let Some(edit) = edits.into_iter().flatten().last() {
+                    change = Some(edit);
+                }
+
+                id.pop(ui);
+            }
+            if let Some(i) = remove {
+                chains.remove(i);
+                change = Some(("Remove IK chain", None));
+            }
+            // -------------------------------------------------------------------------------------
+
+            if let Some((name, merge_key)) = change {
+                history.edit_object(project, index, name, merge_key, |o| *o = obj.clone());
+            }
+            state.keep_edited_object(history, index, obj);
+        });
+
+    state.show_armature = opened;
+}
+
+// Combo picking the object a constraint on the object at index reads, listing only objects that
+// wouldn't make a cycle. none names the choice of no object. Returns whether the target changed
+fn target_combo(
+    ui: &Ui,
+    project: &Project,
+    index: usize,
+    target: &mut Option<usize>,
+    none: &str,
+) -> bool {
+    let preview = match target {
+        Some(t) => project.objs[*t].name.clone(),
+        None => ImString::new(none),
+    };
+    let mut changed = false;
+
+    ComboBox::new(im_str!("Target"))
+        .preview_value(&preview)
+        .build(ui, || {
+            if Selectable::new(&ImString::new(none))
+                .selected(target.is_none())
+                .build(ui)
+            {
+                *target = None;
+                changed = true;
+            }
+            for (i, obj) in project.objs.iter().enumerate() {
+                if !project.can_target(index, i) {
+                    continue;
+                }
+                let id = ui.push_id(i as i32);
+                if Selectable::new(&obj.name)
+                    .selected(*target == Some(i))
+                    .build(ui)
+                {
+                    *target = Some(i);
+                    changed = true;
+                }
+                id.pop(ui);
+            }
+        });
+
+    changed
+}
+
+// Combo picking one of an object's local axes. Returns whether it changed
+fn axis_combo(ui: &Ui, label: &ImStr, axis: &mut Axis) -> bool {
+    let mut changed = false;
+    ComboBox::new(label)
+        .preview_value(&ImString::new(axis.name()))
+        .build(ui, || {
+            for choice in Axis::ALL.iter() {
+                if Selectable::new(&ImString::new(choice.name()))
+                    .selected(axis == choice)
+                    .build(ui)
+                {
+                    *axis = *choice;
+                    changed = true;
+                }
+            }
+        });
+    changed
+}
+
+// Combo picking one of the given choices, shown by name. Returns whether it changed
+fn choice_combo<T: Copy + PartialEq>(
+    ui: &Ui,
+    label: &ImStr,
+    choices: &[T],
+    name: fn(T) -> &'static str,
+    value: &mut T,
+) -> bool {
+    let mut changed = false;
+    ComboBox::new(label)
+        .preview_value(&ImString::new(name(*value)))
+        .build(ui, || {
+            for choice in choices {
+                if Selectable::new(&ImString::new(name(*choice)))
+                    .selected(value == choice)
+                    .build(ui)
+                {
+                    *value = *choice;
+                    changed = true;
+                }
+            }
+        });
+    changed
+}
+
+// X, Y and Z checkboxes on one line, each label starting with prefix. Returns whether any changed
+fn axis_checkboxes(ui: &Ui, prefix: &str, axes: &mut [bool; 3]) -> bool {
+    let mut changed = false;
+    for (i, name) in ["X", "Y", "Z"].iter().enumerate() {
+        if i > 0 {
+            ui.same_line(0.);
+        }
+        changed |= ui.checkbox(&im_str!("{}{}", prefix, name), &mut axes[i]);
+    }
+    changed
+}
+
+fn history_panel(ui: &mut Ui, state: &mut UIState, project: &mut Project, history: &mut History) {
+    if !state.show_history {
+        return;
+    }
+
+    Window::new(im_str!("History"))
+        .position([1200., 619.], Condition::Appearing)
+        .size([300., 300.], Condition::Appearing)
+        .opened(&mut state.show_history)
+        .build(ui, || {
+            // History depth
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let mut depth = history.depth() as u32;
+            if Drag::new(im_str!("Steps kept"))
+                .range(1..=1000)
+                .build(ui, &mut depth)
+            {
+                history.set_depth(depth as usize);
+            }
+            // -------------------------------------------------------------------------------------
+
+            // Entries, clicking one undoes or redoes up to it. Undone entries are greyed out
+            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+            let (done, undone) = history.entries();
+            let (done, undone): (Vec<String>, Vec<String>) = (
+                done.into_iter().map(str::to_string).collect(),
+                undone.into_iter().map(str::to_string).collect(),
+            );
+            let mut go_to = None;
+
+            if Selectable::new(im_str!("Original"))
+                .selected(done.is_empty())
+                .build(ui)
+            {
+                go_to = Some(0);
+            }
+            for (i, name) in done.iter().enumerate() {
+                if Selectable::new(&im_str!("{}##done{}", name, i))
+                    .selected(i + 1 == done.len())
+                    .build(ui)
+                {
+                    go_to = Some(i + 1);
+                }
+            }
+            let grey = ui.push_style_color(StyleColor::Text, [0.5, 0.5, 0.5, 1.]);
+            for (i, name) in undone.iter().enumerate() {
+                if Selectable::new(&im_str!("{}##undone{}", name, i)).build(ui) {
+                    go_to = Some(done.len() + i + 1);
+                }
+            }
+            grey.pop(ui);
+
+            if let Some(done) = go_to {
+                history.go_to(project, done);
+            }
+            // -------------------------------------------------------------------------------------
+        });
+}
+
+#[derive(Default)]
+pub struct UIState {
+    // UI Reset (set in [main_menu])
+    // =============================================================================================
+    // Whether to reset right now. If this is true, the ui will skip rendering for a frame to reset
+    // the position and size of all windows
+    reset: bool,
+
+    // Loading a [new_mesh]
+    // =============================================================================================
+    // Whether or not currently loading a new mesh
+    loading_new_mesh: bool,
+
+    // Mesh a morph target is being loaded for, if loading one instead of a new mesh
+    morph_target_mesh: Option<usize>,
+
+    // Path of obj file to load from
+    new_mesh_path: ImString,
+
+    // Error message to display if there was a problem loading a mesh in [New Mesh]
+    new_mesh_error: Option<String>,
+
+    // [meshes_list] menu
+    // =============================================================================================
+    // Which mesh is selected in the Meshes dropdown
+    selected_mesh: Option<usize>,
+
+    // [objects_list] menu
+    // =============================================================================================
+    // Which object is selected in the Objects dropdown. Also used in [object_attributes]
+    selected_object: Option<usize>,
+
+    // Creating a [new_object]
+    // =============================================================================================
+    // Which mesh is currently being made into an object
+    mesh_for_obj: Option<usize>,
+
+    // Name for new object
+    new_obj_name: ImString,
+
+    // [object_attributes] menu
+    // =============================================================================================
+    // Copy of the selected object edited by [object_attributes] and [armature_panel], with the
+    // revision of the project it was made at (see History::revision). It is kept from one frame to
+    // the next, so the object is only copied again once the project or the selection changes
+    edited_object: Option<(u64, usize, Object)>,
+
+    // Name for a new custom property on the selected object
+    new_property_name: ImString,
+
+    // Error message to display if the selected object couldn't be parented
+    parent_error: Option<String>,
+
+    // Attribute (index into Object::attributes) and expression for a driver being set, and what
+    // is wrong with the expression if it couldn't be
+    driver_target: usize,
+    driver_text: ImString,
+    driver_error: Option<String>,
+
+    // [timeline] window
+    // =============================================================================================
+    // First frame shown, and how many pixels wide each frame is
+    timeline_offset: f32,
+    timeline_zoom: f32,
+
+    // What the left mouse button is currently dragging, if anything
+    timeline_drag: Option<TimelineDrag>,
+
+    // Selected keyframes. Also used by any other window editing keyframes
+    selected_keys: BTreeSet<KeyRef>,
+
+    // [modifiers_panel] window (opened in [graph_editor])
+    // =============================================================================================
+    show_modifiers: bool,
+
+    // Viewport overlays, set in [main_menu]
+    // =============================================================================================
+    motion_path: MotionPath,
+    onion_skin: OnionSkin,
+
+    // [strips_panel] window
+    // =============================================================================================
+    // Name for a clip made from the selected object's keyframes
+    new_clip_name: ImString,
+
+    // Error message to display if a clip couldn't be made
+    clip_error: Option<String>,
+
+    // Clip played by new strips
+    strip_clip: Option<usize>,
+
+    // [layers_panel] window (opened in [main_menu])
+    // =============================================================================================
+    show_layers: bool,
+
+    // [constraints_panel] window (opened in [main_menu])
+    // =============================================================================================
+    show_constraints: bool,
+
+    // [armature_panel] window (opened in [main_menu])
+    // =============================================================================================
+    show_armature: bool,
+
+    // Bone of the selected object's armature being edited. New bones are added as its children
+    selected_bone: Option<usize>,
+
+    // Error message to display if a bone couldn't be added
+    bone_error: Option<String>,
+
+    // [history_panel] window (opened in [main_menu])
+    // =============================================================================================
+    show_history: bool,
+
+    // [transport_bar] window
+    // =============================================================================================
+    // Whether edits add keyframes (see AutoKey), and which tracks they add them to when they do
+    auto_key: bool,
+    auto_key_mode: AutoKey,
+
+    // [graph_editor] window
+    // =============================================================================================
+    // Which track of the selected object is shown
+    graph_channel: GraphChannel,
+
+    // Object and track the selected points belong to
+    graph_owner: Option<(usize, GraphChannel)>,
+
+    // Selected keyframes and handles
+    graph_selected: BTreeSet<GraphPoint>,
+
+    // What the left mouse button is currently dragging, if anything
+    graph_drag: Option<GraphDrag>,
+
+    // Frame and value at the bottom left corner, and how many pixels one frame and one unit of
+    // value take
+    graph_offset: [f32; 2],
+    graph_zoom: [f32; 2],
+
+    // How the selected keyframes are retimed
+    retime_scale: f32,
+    retime_offset: f32,
+
+    // What happens to keyframes that retimed or moved ones land on
+    key_collision: KeyCollision,
+}
+
+// A keyframe of one of an object's channels: object index, channel index (see
+// Object::channels) and frame
+type KeyRef = (usize, usize, Frame);
+
+// Something being dragged around the timeline
+#[derive(Copy, Clone)]
+enum TimelineDrag {
+    // The current frame
+    Playhead,
+
+    // Box selection, from where the drag started
+    Box([f32; 2]),
+
+    // Selected keyframes, from the x position the drag started at
+    Keys(f32),
+}
+
+impl UIState {
+    pub fn new() -> UIState {
+        UIState {
+            timeline_zoom: 10.,
+            auto_key_mode: AutoKey::All,
+            graph_offset: [-2., -3.],
+            graph_zoom: [10., 50.],
+            retime_scale: 1.,
+            ..UIState::default()
+        }
+    }
+
+    fn reset_all(&mut self) {
+        *self = UIState::new();
+    }
+
+    fn reset_new_mesh(&mut self) {
+        self.new_mesh_path.clear();
+        self.loading_new_mesh = false;
+        self.morph_target_mesh = None;
+        self.new_mesh_error = None;
+    }
+
+    fn reset_new_object(&mut self) {
+        self.mesh_for_obj = None;
+        self.new_obj_name.clear();
+    }
+
+    // Deselects meshes and objects that no longer exist, e.g. after undoing their creation
+    fn forget_removed(&mut self, project: &Project) {
+        if self.selected_mesh >= Some(project.meshes.len()) {
+            self.selected_mesh = None;
+        }
+        if self.mesh_for_obj >= Some(project.meshes.len()) {
+            self.reset_new_object();
+        }
+        if self.morph_target_mesh >= Some(project.meshes.len()) {
+            self.reset_new_mesh();
+        }
+        if self.selected_object >= Some(project.objs.len()) {
+            self.selected_object = None;
+        }
+        if self.strip_clip >= Some(project.clips.len()) {
+            self.strip_clip = None;
+        }
+        self.selected_keys.retain(|k| k.0 < project.objs.len());
+
+        let bones = self
+            .selected_object
+            .and_then(|i| project.objs[i].armature.as_ref())
+            .map_or(0, |a| a.bones.len());
+        if self.selected_bone >= Some(bones) {
+            self.selected_bone = None;
+        }
+    }
+
+    // The selected object's index and the copy of it to edit. Only copied from the project if the
+    // project or the selection changed since the copy was kept (see keep_edited_object)
+    fn take_edited_object(
+        &mut self,
+        project: &Project,
+        history: &History,
+    ) -> Option<(usize, Object)> {
+        let index = self.selected_object?;
+        match self.edited_object.take() {
+            Some((revision, i, obj)) if revision == history.revision() && i == index => {
+                Some((index, obj))
+            }
+            _ => Some((index, project.objs[index].clone())),
+        }
+    }
+
+    // Keeps the copy of the object at index for the next frame. It must match the project, so any
+    // change made to it must have been recorded in the history first
+    fn keep_edited_object(&mut self, history: &History, index: usize, obj: Object) {
+        self.edited_object = Some((history.revision(), index, obj));
+    }
+
+    pub fn selected_object(&self) -> Option<usize> {
+        self.selected_object
+    }
+
+    pub fn selected_bone(&self) -> Option<usize> {
+        self.selected_bone
+    }
+
+    pub fn motion_path(&self) -> &MotionPath {
+        &self.motion_path
+    }
+
+    pub fn onion_skin(&self) -> &OnionSkin {
+        &self.onion_skin
+    }
+
+    // Auto-key mode edits are made with
+    fn auto_key(&self) -> AutoKey {
+        if self.auto_key {
+            self.auto_key_mode
+        } else {
+            AutoKey::Off
+        }
+    }
+
+    // Frame under the given x position of the timeline
+    fn timeline_frame_at(&self, keys_x0: f32, x: f32) -> f32 {
+        (x - keys_x0) / self.timeline_zoom + self.timeline_offset
+    }
+
+    // X position of the given frame on the timeline
+    fn timeline_x_at(&self, keys_x0: f32, frame: f32) -> f32 {
+        keys_x0 + (frame - self.timeline_offset) * self.timeline_zoom
+    }
+
+    // Screen position of a (frame, value) point in the graph editor, given the screen position of
+    // its bottom left corner
+    fn graph_to_screen(&self, origin: [f32; 2], [frame, value]: [f32; 2]) -> [f32; 2] {
+        [
+            origin[0] + (frame - self.graph_offset[0]) * self.graph_zoom[0],
+            origin[1] - (value - self.graph_offset[1]) * self.graph_zoom[1],
+        ]
+    }
+
+    // (frame, value) point under a screen position in the graph editor
+    fn graph_from_screen(&self, origin: [f32; 2], [x, y]: [f32; 2]) -> [f32; 2] {
+        [
+            (x - origin[0]) / self.graph_zoom[0] + self.graph_offset[0],
+            (origin[1] - y) / self.graph_zoom[1] + self.graph_offset[1],
+        ]
+    }
+
+    // Pans and zooms the graph editor so the whole keyed range of a track fits in the given size
+    fn graph_frame_all(&mut self, track: &KeyframeV3, [w, h]: [f32; 2]) {
+        let frames: Vec<Frame> = track
+            .frames()
+            .map(|f| f.copied().collect())
+            .unwrap_or_default();
+        let (first, last) = match (frames.first(), frames.last()) {
+            (Some(&first), Some(&last)) => (first as f32, (last as f32).max(first as f32 + 1.)),
+            _ => (0., 100.),
+        };
+
+        let (mut low, mut high) = (f32::MAX, f32::MIN);
+        for i in 0..=100 {
+            let v = track.sample(first + (last - first) * i as f32 / 100.);
+            for c in 0..3 {
+                low = low.min(v[c]);
+                high = high.max(v[c]);
+            }
+        }
+        if high - low < 0.001 {
+            low -= 1.;
+            high += 1.;
+        }
+
+        // Leave a margin around the curves
+        let (margin_x, margin_y) = ((last - first) * 0.1, (high - low) * 0.1);
+        self.graph_zoom = [
+            w / (last - first + 2. * margin_x),
+            h / (high - low + 2. * margin_y),
+        ];
+        self.graph_offset = [first - margin_x, low - margin_y];
+    }
+}
+
+// Draws and shows a modal
+fn modal<F: FnOnce()>(ui: &Ui, title: &ImStr, func: F) {
+    ui.popup_modal(title).build(func);
+    ui.open_popup(title);
+}
+
+// Draws a button that is only clickable if a given condition is met
+fn button_if(ui: &Ui, cond: bool, button_text: &ImStr, tooltip_text: &str, size: [f32; 2]) -> bool {
+    if cond {
+        ui.button(button_text, size)
+    } else {
+        disabled_button(ui, tooltip_text, size);
+        false
+    }
+}
+
+// Draws a filled diamond, used for keyframes
+fn diamond(draw_list: &WindowDrawList, [x, y]: [f32; 2], r: f32, color: [f32; 4]) {
+    draw_list
+        .add_triangle([x - r, y], [x, y - r], [x + r, y], color)
+        .filled(true)
+        .build();
+    draw_list
+        .add_triangle([x - r, y], [x, y + r], [x + r, y], color)
+        .filled(true)
+        .build();
 }
 
 // Draws a hollow box which shows a tooltip when hovered
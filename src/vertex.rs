@@ -1,7 +1,8 @@
 /*
 This file contains the Vertex struct used as a vertex when rendering through glium. For each Vertex,
 the vertex shader is run once.
-Currently the struct only contains the position of a vertex.
+Along with its position, each vertex has up to 4 bones it follows when its mesh is skinned (see
+armature.rs), and how much it follows each of them. Vertices with all weights 0 aren't skinned.
 
 TODO:
     * Lighting data (see shaders.rs too)
@@ -9,17 +10,32 @@ TODO:
 */
 use glium::implement_vertex;
 
-implement_vertex!(Vertex, position);
+implement_vertex!(Vertex, position, joints, weights);
 #[derive(Copy, Clone)]
 pub struct Vertex {
     pub position: [f32; 3],
+
+    // Indices of the bones followed, and how much each counts
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl Vertex {
+    // A vertex that isn't skinned
+    pub fn new(position: [f32; 3]) -> Vertex {
+        Vertex {
+            position,
+            joints: [0; 4],
+            weights: [0.; 4],
+        }
+    }
 }
 
 #[macro_export]
 macro_rules! vertices {
     ($($n1:expr, $n2:expr, $n3:expr),+) => {
         [
-            $(Vertex { position: [$n1, $n2, $n3 ] }),+
+            $(Vertex::new([$n1, $n2, $n3])),+
         ]
     };
 }